@@ -0,0 +1,278 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use anyhow::Result;
+use futures::{StreamExt, TryStreamExt};
+use kube::{
+    api::{Api, DynamicObject, Patch, PatchParams},
+    core::ApiResource,
+    runtime::{watcher, WatchStreamExt},
+    Client,
+    ResourceExt};
+use serde_json::{json, Value};
+
+use crate::ax_kube::{client, leader::Leadership};
+use crate::ax_types::EntityDb;
+use crate::backstage::entities::{EntityError, RawEntity};
+use crate::configuration::{BackstageSettings, Settings};
+use crate::supervisor::Supervisor;
+
+pub const CATALOG_ENTITY_GROUP: &str = "backstage.acme.com";
+pub const CATALOG_ENTITY_VERSION: &str = "v1alpha1";
+pub const CATALOG_ENTITY_KIND: &str = "CatalogEntity";
+pub const CATALOG_ENTITY_PLURAL: &str = "catalogentities";
+
+// key prefix of CatalogEntity sourced entities in the EntityDb
+const STORE_PREFIX: &str = "catalogentity";
+
+const PHASE_ACCEPTED: &str = "Accepted";
+const PHASE_INVALID: &str = "Invalid";
+
+/// ApiResource of the CatalogEntity custom resource
+pub fn catalog_entity_resource() -> ApiResource {
+    ApiResource {
+        group: CATALOG_ENTITY_GROUP.to_owned(),
+        version: CATALOG_ENTITY_VERSION.to_owned(),
+        api_version: format!("{}/{}", CATALOG_ENTITY_GROUP, CATALOG_ENTITY_VERSION),
+        kind: CATALOG_ENTITY_KIND.to_owned(),
+        plural: CATALOG_ENTITY_PLURAL.to_owned(),
+    }
+}
+
+// watch_catalog_entities - Starts threads to track CatalogEntity custom resources,
+//         validates their spec as Backstage entities and stores the accepted ones.
+pub async fn watch_catalog_entities(conf: &Settings, 
+    store: EntityDb, 
+    leadership: Arc<Leadership>,
+    supervisor: &Supervisor) -> Result<()> {
+    if !conf.kube.catalog_entities.enabled {
+        tracing::info!("CatalogEntity watch disabled");
+        return Ok(());
+    }
+
+    let cli = client::client(&conf.kube).await?;
+    let ar = catalog_entity_resource();
+
+    let scopes: Vec<Option<String>> = if conf.kube.catalog_entities.namespaces.is_empty() {
+        vec![None]
+    } else {
+        conf.kube.catalog_entities.namespaces.iter().cloned().map(Some).collect()
+    };
+
+    for ns in scopes {
+        let api: Api<DynamicObject> = match ns {
+            Some(ref ns) => Api::namespaced_with(cli.clone(), ns, &ar),
+            None => Api::all_with(cli.clone(), &ar),
+        };
+        let prefix = match ns {
+            Some(ref ns) => format!("{}/{}/", STORE_PREFIX, ns),
+            None => format!("{}/", STORE_PREFIX),
+        };
+        let name = format!("CatalogEntity watch {}", prefix);
+        let cli = cli.clone();
+        let ar = ar.clone();
+        let bsc = conf.backstage.clone();
+        let store = store.clone();
+        let leadership = leadership.clone();
+
+        supervisor.spawn(&name, supervisor.token(), move |token| {
+            let api = api.clone();
+            let prefix = prefix.clone();
+            let cli = cli.clone();
+            let ar = ar.clone();
+            let bsc = bsc.clone();
+            let store = store.clone();
+            let leadership = leadership.clone();
+
+            async move {
+                // errors are retried with backoff instead of reconnecting at once
+                let mut stream = watcher(api, watcher::Config::default())
+                    .default_backoff()
+                    .boxed();
+                // keys seen during a relist, others were deleted while disconnected
+                let mut relisted: HashSet<String> = HashSet::new();
+
+                loop {
+                    let next = tokio::select! {
+                        _ = token.cancelled() => break,
+                        next = stream.try_next() => next,
+                    };
+
+                    match next {
+                        Ok(Some(watcher::Event::Init)) => {
+                            relisted.clear();
+                        },
+                        Ok(Some(watcher::Event::InitApply(o))) => {
+                            relisted.insert(store_key(&o));
+                            accept(&cli, &ar, &bsc, &store, &leadership, o).await;
+                        },
+                        Ok(Some(watcher::Event::InitDone)) => {
+                            store.lock().unwrap()
+                                .retain(|k, _| !k.starts_with(&prefix) || relisted.contains(k));
+                        },
+                        Ok(Some(watcher::Event::Apply(o))) => {
+                            accept(&cli, &ar, &bsc, &store, &leadership, o).await;
+                        },
+                        Ok(Some(watcher::Event::Delete(o))) => {
+                            tracing::info!("CatalogEntity removed {}", store_key(&o));
+                            store.lock().unwrap().remove(&store_key(&o));
+                        },
+                        Ok(None) => break,
+                        Err(why) => {
+                            tracing::error!("failed to get CatalogEntity watch response: {:?}", why);
+                            continue;
+                        },
+                    }
+                }
+            }
+        });
+    }
+
+    Ok(())
+}
+
+// Validate the CatalogEntity spec, store it when valid and report the outcome in its status.
+// Only the leader writes the status, other replicas just keep their store warm.
+async fn accept(cli: &Client,
+    ar: &ApiResource,
+    bsc: &BackstageSettings,
+    store: &EntityDb,
+    leadership: &Leadership,
+    obj: DynamicObject) {
+    let key = store_key(&obj);
+
+    let status = match validate(bsc, &obj) {
+        Ok(entity) => {
+            let entity_ref = entity.entity_ref();
+            store.lock().unwrap().insert(key.clone(), entity);
+            new_status(&obj, PHASE_ACCEPTED, "entity accepted".to_owned(), Some(entity_ref))
+        },
+        Err(why) => {
+            tracing::warn!("CatalogEntity {} rejected: {}", key, why);
+            store.lock().unwrap().remove(&key);
+            new_status(&obj, PHASE_INVALID, why.to_string(), None)
+        },
+    };
+
+    if !leadership.is_leader() {
+        return;
+    }
+
+    // status patches trigger watch events, skip those which change nothing
+    if !status_changed(&obj, &status) {
+        return;
+    }
+
+    if let Err(why) = write_status(cli, ar, &obj, status).await {
+        tracing::error!("failed to update CatalogEntity {} status: {:?}", key, why);
+    }
+}
+
+// Backstage entity of the CatalogEntity spec
+fn validate(bsc: &BackstageSettings, obj: &DynamicObject) -> Result<RawEntity, EntityError> {
+    let spec = obj.data.get("spec").cloned().unwrap_or(Value::Null);
+    RawEntity::from_value(bsc, spec)
+}
+
+fn new_status(obj: &DynamicObject, phase: &str, message: String, entity_ref: Option<String>) -> Value {
+    json!({
+        "phase": phase,
+        "message": message,
+        "entityRef": entity_ref,
+        "observedGeneration": obj.metadata.generation,
+    })
+}
+
+// Null fields are removed by the merge patch, so the stored status lacks them
+fn status_changed(obj: &DynamicObject, status: &Value) -> bool {
+    let mut desired = status.clone();
+    if let Some(fields) = desired.as_object_mut() {
+        fields.retain(|_, v| !v.is_null());
+    }
+    obj.data.get("status") != Some(&desired)
+}
+
+async fn write_status(cli: &Client,
+    ar: &ApiResource,
+    obj: &DynamicObject,
+    status: Value) -> Result<()> {
+    let api: Api<DynamicObject> = match obj.namespace() {
+        Some(ns) => Api::namespaced_with(cli.clone(), &ns, ar),
+        None => Api::all_with(cli.clone(), ar),
+    };
+    api.patch_status(&obj.name_any(),
+        &PatchParams::default(),
+        &Patch::Merge(json!({ "status": status })))
+        .await?;
+
+    Ok(())
+}
+
+fn store_key(obj: &DynamicObject) -> String {
+    format!("{}/{}/{}",
+        STORE_PREFIX,
+        obj.namespace().unwrap_or_default(),
+        obj.name_any())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::get_configuration;
+
+    fn catalog_entity(spec: Value, status: Option<Value>) -> DynamicObject {
+        let mut obj = json!({
+            "apiVersion": format!("{}/{}", CATALOG_ENTITY_GROUP, CATALOG_ENTITY_VERSION),
+            "kind": CATALOG_ENTITY_KIND,
+            "metadata": { "name": "orders", "namespace": "shop", "generation": 2 },
+            "spec": spec,
+        });
+        if let Some(status) = status {
+            obj["status"] = status;
+        }
+        serde_json::from_value(obj).unwrap()
+    }
+
+    fn component(name: &str) -> Value {
+        json!({
+            "apiVersion": "backstage.io/v1alpha1",
+            "kind": "Component",
+            "metadata": { "name": name },
+            "spec": { "type": "service", "owner": "team-a" },
+        })
+    }
+
+    #[test]
+    fn validate_accepts_backstage_entities() {
+        let bsc = get_configuration().unwrap().backstage;
+
+        let entity = validate(&bsc, &catalog_entity(component("orders"), None)).unwrap();
+        assert_eq!(entity.entity_ref(), "component:default/orders");
+
+        let why = validate(&bsc, &catalog_entity(component("orders--api"), None)).unwrap_err();
+        assert!(why.to_string().contains("invalid metadata.name"), "{}", why);
+
+        let mut spec = component("orders");
+        spec["spec"] = json!("service");
+        assert!(validate(&bsc, &catalog_entity(spec, None)).is_err());
+        assert!(validate(&bsc, &catalog_entity(Value::Null, None)).is_err());
+    }
+
+    #[test]
+    fn unchanged_status_is_not_patched() {
+        let obj = catalog_entity(component("orders"), None);
+        let invalid = new_status(&obj, PHASE_INVALID, "missing kind".to_owned(), None);
+        assert!(status_changed(&obj, &invalid));
+
+        // the merge patch left out the null entityRef
+        let patched = catalog_entity(component("orders"), Some(json!({
+            "phase": PHASE_INVALID,
+            "message": "missing kind",
+            "observedGeneration": 2,
+        })));
+        assert!(!status_changed(&patched, &invalid));
+
+        let accepted = new_status(&patched, PHASE_ACCEPTED, "entity accepted".to_owned(), Some("component:default/orders".to_owned()));
+        assert!(status_changed(&patched, &accepted));
+    }
+}
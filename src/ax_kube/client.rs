@@ -1,13 +1,18 @@
 use k8s_openapi::apimachinery::pkg::version;
 use anyhow::{Context, Result};
-use kube::{Client, Config, Error};
-use std::convert::TryFrom;
+use kube::{Client, Config};
+use kube::client::{Body, ConfigExt};
+use hyper_timeout::TimeoutConnector;
+use hyper_util::{client::legacy::{connect::HttpConnector, Client as HyperClient}, rt::TokioExecutor};
+use tower::{BoxError, ServiceBuilder};
+use kube::config::{KubeConfigOptions, Kubeconfig};
 use std::time::Duration;
 use tokio::time::sleep;
 use rand::Rng;
 use std::sync::{Arc, Mutex};
 use once_cell::sync::OnceCell;
-use crate::configuration::KubeSettings;
+use crate::configuration::{KubeConnectionSettings, KubeSettings};
+use crate::ax_kube::throttle::ThrottleLayer;
 use crate::errors::KubernetesError;
 
 // Global client for connection pooling
@@ -31,16 +36,6 @@ pub async fn initialize(settings: &KubeSettings) -> Result<()> {
     Ok(())
 }
 
-pub async fn client2(use_tls: bool) -> Result<Client, Error> {
-    // init kube client
-    let mut config = Config::infer().await.map_err(Error::InferConfig)?;
-    if !use_tls {
-        config.accept_invalid_certs = true;
-    }
-
-    Client::try_from(config)
-}
-
 /// Get the Kubernetes client
 ///
 /// # Returns
@@ -158,43 +153,56 @@ fn calculate_backoff(attempt: u32, base_delay_ms: u64, max_delay_ms: u64) -> u64
 /// # Returns
 /// A Result containing the client or an error
 async fn create_client(settings: &KubeSettings) -> Result<Client> {
-    // Try to infer Kubernetes config from the environment
-    let mut config = Config::infer().await
-        .context("Failed to infer Kubernetes configuration")?;
-    
-    // Apply TLS settings
-    if !settings.use_tls {
-        config.accept_invalid_certs = true;
+    // Use the explicit API server, the selected kubeconfig and context, or infer
+    // the config from the environment
+    let options = KubeConfigOptions {
+        context: settings.context.clone(),
+        ..KubeConfigOptions::default()
+    };
+    let mut config = match (&settings.api_server_url, &settings.kubeconfig) {
+        (Some(url), _) => {
+            let uri = url.parse::<http::Uri>()
+                .with_context(|| format!("Invalid API server URL {}", url))?;
+            Config::new(uri)
+        },
+        (None, Some(path)) => {
+            let kubeconfig = Kubeconfig::read_from(path)
+                .with_context(|| format!("Failed to read kubeconfig {}", path))?;
+            Config::from_custom_kubeconfig(kubeconfig, &options).await
+                .context("Failed to load Kubernetes configuration from kubeconfig")?
+        },
+        (None, None) if settings.context.is_some() => {
+            Config::from_kubeconfig(&options).await
+                .context("Failed to load Kubernetes configuration from kubeconfig")?
+        },
+        (None, None) => {
+            Config::infer().await
+                .context("Failed to infer Kubernetes configuration")?
+        },
+    };
+
+    if let Some(ref path) = settings.ca_file {
+        config.root_cert = Some(read_ca_bundle(path)?);
     }
-    
-    
-    
-    // Configure connection timeouts
-    let timeout = Duration::from_secs(30); // Default timeout
-    config.connect_timeout = Some(timeout);
-    config.read_timeout = Some(timeout);
-    config.write_timeout = Some(timeout);
-    
-    // Configure connection settings if using tokio runtime
-    // TODO review if this is still needed
-    #[cfg(feature = "runtime")]
-    {
-        use kube::client::ConfigExt;
-        // Apply connection pool settings
-        let conn_settings = &settings.connection;
-        // Set connection pool settings
-        config = config
-            .maybe_client_qps(Some(5.0)) // Limit QPS to 5
-            .maybe_client_burst(Some(10)) // Burst of 10
-            .with_connect_timeout(Duration::from_secs(conn_settings.connect_timeout_secs))
-            .with_read_timeout(Duration::from_secs(conn_settings.read_timeout_secs))
-            .with_write_timeout(Duration::from_secs(conn_settings.write_timeout_secs));
+
+    // kube reads the file again before the token expires
+    if let Some(ref path) = settings.token_file {
+        config.auth_info.token = None;
+        config.auth_info.token_file = Some(path.clone());
+    }
+
+    if let Some(ref user) = settings.impersonate_user {
+        config.auth_info.impersonate = Some(user.clone());
+        config.auth_info.impersonate_groups = (!settings.impersonate_groups.is_empty())
+            .then(|| settings.impersonate_groups.clone());
     }
 
+    if settings.insecure_skip_tls_verify {
+        tracing::warn!("the certificate of the Kubernetes API server is not verified");
+        config.accept_invalid_certs = true;
+    }
 
-    // Create the client
-    let client = Client::try_from(config)
-        .context("Failed to create Kubernetes client from configuration")?;
+    let client = build_client(config, &settings.connection)?;
     
     // Test the connection
     test_connection(&client).await?;
@@ -202,6 +210,56 @@ async fn create_client(settings: &KubeSettings) -> Result<Client> {
     Ok(client)
 }
 
+// DER certificates of a PEM CA bundle
+fn read_ca_bundle(path: &str) -> Result<Vec<Vec<u8>>> {
+    let pem = std::fs::read(path)
+        .with_context(|| format!("Failed to read CA bundle {}", path))?;
+    let certs = rustls_pemfile::certs(&mut pem.as_slice())
+        .map(|cert| cert.map(|der| der.to_vec()))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .with_context(|| format!("Invalid PEM in CA bundle {}", path))?;
+    if certs.is_empty() {
+        anyhow::bail!("No certificate in CA bundle {}", path);
+    }
+
+    Ok(certs)
+}
+
+// build_client - Builds the client stack of kube with the connection pool,
+//         keep alive, timeouts and client side rate limit of the settings
+fn build_client(mut config: Config, conn: &KubeConnectionSettings) -> Result<Client> {
+    config.connect_timeout = Some(Duration::from_secs(conn.connect_timeout_secs));
+    config.read_timeout = Some(Duration::from_secs(conn.read_timeout_secs));
+    config.write_timeout = Some(Duration::from_secs(conn.write_timeout_secs));
+
+    let mut http = HttpConnector::new();
+    http.enforce_http(false);
+    http.set_keepalive((conn.keep_alive_secs > 0).then(|| Duration::from_secs(conn.keep_alive_secs)));
+    let https = config.rustls_https_connector_with_connector(http)
+        .context("Failed to configure TLS of the Kubernetes client")?;
+
+    let mut connector = TimeoutConnector::new(https);
+    connector.set_connect_timeout(config.connect_timeout);
+    connector.set_read_timeout(config.read_timeout);
+    connector.set_write_timeout(config.write_timeout);
+
+    let http_client: HyperClient<_, Body> = HyperClient::builder(TokioExecutor::new())
+        .pool_max_idle_per_host(conn.pool_size)
+        .pool_idle_timeout(Duration::from_secs(conn.idle_timeout_secs))
+        .build(connector);
+
+    let throttle = (conn.qps > 0.0).then(|| ThrottleLayer::new(conn.qps, conn.burst));
+    let service = ServiceBuilder::new()
+        .option_layer(throttle)
+        .layer(config.base_uri_layer())
+        .option_layer(config.auth_layer().context("Failed to configure Kubernetes authentication")?)
+        .layer(config.extra_headers_layer().context("Failed to configure Kubernetes headers")?)
+        .map_err(BoxError::from)
+        .service(http_client);
+
+    Ok(Client::new(service, config.default_namespace))
+}
+
 /// Test the connection to the Kubernetes API
 /// 
 /// # Arguments
@@ -0,0 +1,195 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::ax_kube::watch_event::{WatchCommand, WatchEvent};
+
+/// Holds back Add and Update events of an object for a window, so that only the
+/// latest version of a burst, e.g. of a rolling StatefulSet, is ingested.
+///
+/// The window starts with the first event of the object, later events within
+/// it replace the held one. Deletes and Forgets pass at once and discard the
+/// held events they supersede.
+#[derive(Debug)]
+pub struct Debouncer {
+    window: Duration,
+    pending: HashMap<String, (Instant, WatchEvent)>,
+    // deadlines in order, the window is the same for all objects
+    deadlines: VecDeque<(Instant, String)>,
+    coalesced: u64,
+}
+
+impl Debouncer {
+    /// Passes all events through for a zero window
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            pending: HashMap::new(),
+            deadlines: VecDeque::new(),
+            coalesced: 0,
+        }
+    }
+
+    /// Hold the event, or return the event to process now
+    pub fn push(&mut self, we: WatchEvent) -> Option<WatchEvent> {
+        match we.command {
+            WatchCommand::Add(_) | WatchCommand::Update(_) if !self.window.is_zero() => {},
+            WatchCommand::Delete(_) => {
+                if let Some(key) = we.object_key() {
+                    self.pending.remove(&key);
+                }
+                return Some(we);
+            },
+            WatchCommand::Forget => {
+                let prefix = format!("{}/", we.watch_id);
+                self.pending.retain(|key, _| !key.starts_with(&prefix));
+                return Some(we);
+            },
+            _ => return Some(we),
+        }
+
+        let key = match we.object_key() {
+            Some(key) => key,
+            None => return Some(we),
+        };
+        match self.pending.get_mut(&key) {
+            Some((_, held)) => {
+                *held = we;
+                self.coalesced += 1;
+            },
+            None => {
+                let deadline = Instant::now() + self.window;
+                self.deadlines.push_back((deadline, key.clone()));
+                self.pending.insert(key, (deadline, we));
+            },
+        }
+
+        None
+    }
+
+    /// When the next held event is due
+    pub fn next_deadline(&mut self) -> Option<Instant> {
+        self.skip_stale();
+        self.deadlines.front().map(|(deadline, _)| *deadline)
+    }
+
+    /// Held event whose window ended
+    pub fn pop_due(&mut self, now: Instant) -> Option<WatchEvent> {
+        match self.next_deadline() {
+            Some(deadline) if deadline <= now => self.pop(),
+            _ => None,
+        }
+    }
+
+    /// Oldest held event regardless of its window, to flush on shutdown
+    pub fn pop(&mut self) -> Option<WatchEvent> {
+        self.skip_stale();
+        let (_, key) = self.deadlines.pop_front()?;
+        self.pending.remove(&key).map(|(_, we)| we)
+    }
+
+    /// Events replaced by a later version of their object
+    pub fn coalesced(&self) -> u64 {
+        self.coalesced
+    }
+
+    // Deadlines of events already deleted or forgotten, or of an earlier
+    // window of an object held again
+    fn skip_stale(&mut self) {
+        while let Some((deadline, key)) = self.deadlines.front() {
+            match self.pending.get(key) {
+                Some((held, _)) if held == deadline => break,
+                _ => {
+                    self.deadlines.pop_front();
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kube::api::DynamicObject;
+
+    const URL: &str = "/apis/apps/v1/namespaces/shop/statefulsets";
+    const WINDOW: Duration = Duration::from_millis(100);
+
+    fn event(command: fn(DynamicObject) -> WatchCommand, name: &str, version: &str) -> WatchEvent {
+        let obj: DynamicObject = serde_json::from_value(serde_json::json!({
+            "apiVersion": "apps/v1",
+            "kind": "StatefulSet",
+            "metadata": { "name": name, "namespace": "shop", "resourceVersion": version },
+        })).unwrap();
+
+        WatchEvent {
+            resource_url: URL.to_owned(),
+            watch_id: format!("{}#0", URL),
+            command: command(obj),
+            ..WatchEvent::default()
+        }
+    }
+
+    fn version(we: &WatchEvent) -> String {
+        match we.command {
+            WatchCommand::Add(ref obj) | WatchCommand::Update(ref obj) | WatchCommand::Delete(ref obj) => {
+                obj.metadata.resource_version.clone().unwrap()
+            },
+            _ => String::new(),
+        }
+    }
+
+    #[test]
+    fn holds_the_latest_version_until_the_window_ends() {
+        let mut debouncer = Debouncer::new(WINDOW);
+        let start = Instant::now();
+
+        assert!(debouncer.push(event(WatchCommand::Add, "redis", "1")).is_none());
+        assert!(debouncer.push(event(WatchCommand::Update, "redis", "2")).is_none());
+        assert!(debouncer.push(event(WatchCommand::Update, "redis", "3")).is_none());
+        assert!(debouncer.push(event(WatchCommand::Update, "other", "4")).is_none());
+        assert_eq!(debouncer.coalesced(), 2);
+
+        assert!(debouncer.pop_due(start).is_none());
+        let deadline = debouncer.next_deadline().unwrap();
+        assert!(deadline >= start + WINDOW);
+
+        let due = start + WINDOW * 2;
+        assert_eq!(debouncer.pop_due(due).map(|we| version(&we)).as_deref(), Some("3"));
+        assert_eq!(debouncer.pop_due(due).map(|we| version(&we)).as_deref(), Some("4"));
+        assert!(debouncer.pop_due(due).is_none());
+        assert!(debouncer.next_deadline().is_none());
+    }
+
+    #[test]
+    fn deletes_and_forgets_discard_held_events() {
+        let mut debouncer = Debouncer::new(WINDOW);
+
+        debouncer.push(event(WatchCommand::Update, "redis", "1"));
+        let delete = debouncer.push(event(WatchCommand::Delete, "redis", "2"));
+        assert_eq!(delete.map(|we| version(&we)).as_deref(), Some("2"));
+
+        debouncer.push(event(WatchCommand::Update, "other", "3"));
+        let forget = WatchEvent {
+            resource_url: URL.to_owned(),
+            watch_id: format!("{}#0", URL),
+            command: WatchCommand::Forget,
+            ..WatchEvent::default()
+        };
+        assert!(debouncer.push(forget).is_some());
+
+        assert!(debouncer.next_deadline().is_none());
+        assert!(debouncer.pop().is_none());
+
+        // a new window starts for an object held again
+        debouncer.push(event(WatchCommand::Add, "redis", "5"));
+        assert_eq!(debouncer.pop().map(|we| version(&we)).as_deref(), Some("5"));
+    }
+
+    #[test]
+    fn zero_window_passes_events() {
+        let mut debouncer = Debouncer::new(Duration::ZERO);
+        let we = debouncer.push(event(WatchCommand::Update, "redis", "1"));
+        assert_eq!(we.map(|we| version(&we)).as_deref(), Some("1"));
+        assert!(debouncer.pop().is_none());
+    }
+}
@@ -6,6 +6,7 @@ use anyhow::Result;
 
 use kube::{
     api::{Api, DynamicObject},
+    core::ApiResource as KubeApiResource,
     discovery::Scope,
     Client,
 };
@@ -150,6 +151,8 @@ pub struct ApiWithSelectors {
     pub label_selectors: Option<Vec<String>>,
     pub field_selectors: Option<Vec<String>>,
     pub event_type: String,
+    /// discovered group, version and kind of the watched objects
+    pub api_resource: KubeApiResource,
     pub api_dyn: Api<DynamicObject>,
 }
 
@@ -163,6 +166,7 @@ pub fn dynamic_api(
     let mut dyn_apis: Vec<ApiWithSelectors> = vec![];
     // let key_kind = resources.get(&ar.kind);
     // if key_kind == None { return dyn_apis }
+    let kube_ar = ar.clone().to_kube_ar();
     let ar_kind = ar.kind.clone().to_ascii_lowercase();
     let ar_plural = ar.plural.clone().to_ascii_lowercase();
 
@@ -176,29 +180,32 @@ pub fn dynamic_api(
         if caps.scope == Scope::Cluster {
             dyn_apis.push(ApiWithSelectors{
                 event_type: res.event_type.clone(),
+                api_resource: kube_ar.clone(),
                 label_selectors: Some(res.label_selectors.clone()),
                 field_selectors: Some(res.field_selectors.clone()),
                 api_dyn: Api::all_with(client.clone(), 
-                                        &ar.clone().to_kube_ar()),
+                                        &kube_ar),
             });
         } else if res.namespaces.len() > 0 {
             for ns in &res.namespaces {
                     dyn_apis.push(ApiWithSelectors{
                         event_type: res.event_type.clone(),
+                        api_resource: kube_ar.clone(),
                         label_selectors: Some(res.label_selectors.clone()),
                         field_selectors: Some(res.field_selectors.clone()),
                         api_dyn: Api::namespaced_with(client.clone(), 
                                                         &ns, 
-                                                        &ar.clone().to_kube_ar())}
+                                                        &kube_ar)}
                     );
             }
         } else if res.namespaces.len() == 0 {
             dyn_apis.push(ApiWithSelectors{
                 event_type: res.event_type.clone(),
+                api_resource: kube_ar.clone(),
                 label_selectors: Some(res.label_selectors.clone()),
                 field_selectors: Some(res.field_selectors.clone()),
                 api_dyn: Api::all_with(client.clone(), 
-                                        &ar.clone().to_kube_ar())}
+                                        &kube_ar)}
             );
         } else {
             tracing::error!("No resources provided");
@@ -0,0 +1,322 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use anyhow::Result;
+use k8s_openapi::api::coordination::v1::{Lease, LeaseSpec};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::MicroTime;
+use k8s_openapi::chrono::{DateTime, Duration as ChronoDuration, Utc};
+use kube::api::{Api, ObjectMeta, PostParams};
+use tokio::time::{self, Duration, Instant};
+
+use crate::ax_kube::client;
+use crate::configuration::{LeaderElectionSettings, Settings};
+use crate::errors::ConfigError;
+use crate::supervisor::Supervisor;
+
+/// Leadership of this replica.
+///
+/// All replicas watch and cache k8s resources and serve entities, only the
+/// leader writes to the cluster and publishes events.
+#[derive(Debug)]
+pub struct Leadership {
+    identity: String,
+    leader: AtomicBool,
+}
+
+impl Leadership {
+    fn new(identity: String, leader: bool) -> Arc<Self> {
+        Arc::new(Self {
+            identity,
+            leader: AtomicBool::new(leader),
+        })
+    }
+
+    /// Leadership of a replica running without leader election
+    pub fn always() -> Arc<Self> {
+        Self::new(identity(), true)
+    }
+
+    /// Leadership of a replica whose election failed to start, it never leads
+    pub fn never() -> Arc<Self> {
+        Self::new(identity(), false)
+    }
+
+    /// Identity of this replica in the Lease
+    pub fn identity(&self) -> &str {
+        &self.identity
+    }
+
+    /// Whether this replica is the leader
+    pub fn is_leader(&self) -> bool {
+        self.leader.load(Ordering::Relaxed)
+    }
+
+    fn set(&self, leader: bool) {
+        if self.leader.swap(leader, Ordering::Relaxed) != leader {
+            if leader {
+                tracing::info!("{} became the leader", self.identity);
+            } else {
+                tracing::warn!("{} is no longer the leader", self.identity);
+            }
+        }
+    }
+}
+
+// elect_leader - Starts a thread acquiring and renewing the coordination.k8s.io Lease,
+//         the Lease is released on shutdown so that another replica takes over quickly.
+pub async fn elect_leader(conf: &Settings, supervisor: &Supervisor) -> Result<Arc<Leadership>> {
+    let settings = conf.kube.leader_election.clone();
+    if !settings.enabled {
+        tracing::info!("Leader election disabled");
+        return Ok(Leadership::always());
+    }
+
+    let namespace = if settings.lease_namespace.is_empty() {
+        std::env::var("POD_NAMESPACE")
+            .map_err(|_| ConfigError::missing("kube.leader_election.lease_namespace"))?
+    } else {
+        settings.lease_namespace.clone()
+    };
+
+    let cli = client::client(&conf.kube).await?;
+    let api: Api<Lease> = Api::namespaced(cli, &namespace);
+    let leadership = Leadership::new(identity(), false);
+
+    tracing::info!("Electing leader with Lease {}/{} as {}",
+        namespace,
+        settings.lease_name,
+        leadership.identity());
+
+    let leader = leadership.clone();
+    supervisor.spawn("leader election", supervisor.token(), move |token| {
+        let api = api.clone();
+        let settings = settings.clone();
+        let leader = leader.clone();
+
+        async move {
+            let mut interval = time::interval(Duration::from_secs(settings.renew_interval_secs));
+            let renew_deadline = Duration::from_secs(settings.renew_deadline_secs);
+            let mut renewed = Instant::now();
+
+            loop {
+                // a leader which did not renew in time steps down, before the
+                // Lease expires for the other replicas
+                let deadline = renewed + renew_deadline;
+                tokio::select! {
+                    _ = token.cancelled() => break,
+                    _ = time::sleep_until(deadline), if leader.is_leader() => {
+                        tracing::error!("Lease {} not renewed within {}s", settings.lease_name, settings.renew_deadline_secs);
+                        leader.set(false);
+                        continue;
+                    },
+                    _ = interval.tick() => {},
+                }
+
+                let started = Instant::now();
+                let attempt = tokio::select! {
+                    _ = token.cancelled() => break,
+                    _ = time::sleep_until(deadline), if leader.is_leader() => {
+                        tracing::error!("Lease {} not renewed within {}s", settings.lease_name, settings.renew_deadline_secs);
+                        leader.set(false);
+                        continue;
+                    },
+                    attempt = time::timeout(renew_deadline, try_acquire(&api, &settings, leader.identity())) => {
+                        attempt.unwrap_or_else(|_| Err(anyhow::anyhow!("timed out")))
+                    },
+                };
+
+                match attempt {
+                    Ok(true) => {
+                        renewed = started;
+                        leader.set(true);
+                    },
+                    Ok(false) => leader.set(false),
+                    Err(why) => {
+                        // the leader keeps acting until the renew deadline
+                        tracing::error!("failed to renew Lease {}: {:?}", settings.lease_name, why);
+                    },
+                }
+            }
+
+            if leader.is_leader() {
+                if let Err(why) = release(&api, &settings, leader.identity()).await {
+                    tracing::error!("failed to release Lease {}: {:?}", settings.lease_name, why);
+                }
+                leader.set(false);
+            }
+        }
+    });
+
+    Ok(leadership)
+}
+
+// Acquire the Lease if it is free or expired, renew it if already held.
+// Returns whether this replica holds the Lease.
+async fn try_acquire(api: &Api<Lease>, settings: &LeaderElectionSettings, identity: &str) -> Result<bool> {
+    let now = Utc::now();
+    let duration = settings.lease_duration_secs as i32;
+
+    let mut lease = match api.get_opt(&settings.lease_name).await? {
+        Some(lease) => lease,
+        None => {
+            let lease = Lease {
+                metadata: ObjectMeta {
+                    name: Some(settings.lease_name.clone()),
+                    ..ObjectMeta::default()
+                },
+                spec: Some(LeaseSpec {
+                    holder_identity: Some(identity.to_owned()),
+                    lease_duration_seconds: Some(duration),
+                    acquire_time: Some(MicroTime(now)),
+                    renew_time: Some(MicroTime(now)),
+                    lease_transitions: Some(0),
+                    ..LeaseSpec::default()
+                }),
+            };
+
+            // a conflict means another replica created it in the meantime
+            return acquired(api.create(&PostParams::default(), &lease).await);
+        }
+    };
+
+    let spec = lease.spec.get_or_insert_with(LeaseSpec::default);
+    if !claim(spec, identity, duration, now) {
+        return Ok(false);
+    }
+
+    // the resourceVersion makes concurrent takeovers conflict
+    acquired(api.replace(&settings.lease_name, &PostParams::default(), &lease).await)
+}
+
+// Take or renew the Lease in the spec unless another replica holds it and it
+// has not expired. Returns whether the spec was claimed for this replica.
+fn claim(spec: &mut LeaseSpec, identity: &str, duration: i32, now: DateTime<Utc>) -> bool {
+    let holder = spec.holder_identity.clone().filter(|h| !h.is_empty());
+    let held = holder.as_deref() == Some(identity);
+    let expired = match spec.renew_time {
+        Some(ref renewed) => {
+            let held_for = spec.lease_duration_seconds.unwrap_or(duration);
+            renewed.0 + ChronoDuration::seconds(held_for.into()) < now
+        },
+        None => true,
+    };
+
+    if holder.is_some() && !held && !expired {
+        return false;
+    }
+
+    if !held {
+        spec.holder_identity = Some(identity.to_owned());
+        spec.acquire_time = Some(MicroTime(now));
+        spec.lease_transitions = Some(spec.lease_transitions.unwrap_or(0) + 1);
+    }
+    spec.lease_duration_seconds = Some(duration);
+    spec.renew_time = Some(MicroTime(now));
+    true
+}
+
+// Outcome of writing a claimed Lease, another replica won a conflict
+fn acquired(written: kube::Result<Lease>) -> Result<bool> {
+    match written {
+        Ok(_) => Ok(true),
+        Err(kube::Error::Api(ae)) if ae.code == 409 => Ok(false),
+        Err(why) => Err(why.into()),
+    }
+}
+
+// Give up the Lease held by this replica
+async fn release(api: &Api<Lease>, settings: &LeaderElectionSettings, identity: &str) -> Result<()> {
+    let mut lease = match api.get_opt(&settings.lease_name).await? {
+        Some(lease) => lease,
+        None => return Ok(()),
+    };
+
+    let spec = lease.spec.get_or_insert_with(LeaseSpec::default);
+    if spec.holder_identity.as_deref() != Some(identity) {
+        return Ok(());
+    }
+    spec.holder_identity = None;
+    spec.renew_time = None;
+
+    api.replace(&settings.lease_name, &PostParams::default(), &lease).await?;
+    tracing::info!("Released Lease {}", settings.lease_name);
+    Ok(())
+}
+
+// Pod name, unique across the replicas of the Deployment
+fn identity() -> String {
+    std::env::var("POD_NAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .unwrap_or_else(|_| format!("k8s-entity-provider-{}", std::process::id()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kube::core::ErrorResponse;
+
+    const DURATION: i32 = 15;
+
+    fn lease(holder: Option<&str>, renewed_secs_ago: i64) -> LeaseSpec {
+        LeaseSpec {
+            holder_identity: holder.map(str::to_owned),
+            lease_duration_seconds: Some(DURATION),
+            acquire_time: Some(MicroTime(Utc::now() - ChronoDuration::seconds(3600))),
+            renew_time: Some(MicroTime(Utc::now() - ChronoDuration::seconds(renewed_secs_ago))),
+            lease_transitions: Some(3),
+            ..LeaseSpec::default()
+        }
+    }
+
+    #[test]
+    fn free_lease_is_taken() {
+        let now = Utc::now();
+        for mut spec in [lease(None, 5), lease(Some(""), 5), LeaseSpec::default()] {
+            assert!(claim(&mut spec, "pod-a", DURATION, now));
+            assert_eq!(spec.holder_identity.as_deref(), Some("pod-a"));
+            assert_eq!(spec.acquire_time, Some(MicroTime(now)));
+            assert_eq!(spec.renew_time, Some(MicroTime(now)));
+        }
+    }
+
+    #[test]
+    fn held_lease_is_renewed() {
+        let now = Utc::now();
+        let mut spec = lease(Some("pod-a"), 5);
+        let acquired_at = spec.acquire_time.clone();
+
+        assert!(claim(&mut spec, "pod-a", DURATION, now));
+        assert_eq!(spec.renew_time, Some(MicroTime(now)));
+        assert_eq!(spec.acquire_time, acquired_at);
+        assert_eq!(spec.lease_transitions, Some(3));
+    }
+
+    #[test]
+    fn lease_of_another_replica_is_kept_until_it_expires() {
+        let now = Utc::now();
+        let mut spec = lease(Some("pod-b"), 5);
+        let before = spec.clone();
+        assert!(!claim(&mut spec, "pod-a", DURATION, now));
+        assert_eq!(spec, before);
+
+        let mut spec = lease(Some("pod-b"), DURATION as i64 + 1);
+        assert!(claim(&mut spec, "pod-a", DURATION, now));
+        assert_eq!(spec.holder_identity.as_deref(), Some("pod-a"));
+        assert_eq!(spec.acquire_time, Some(MicroTime(now)));
+        assert_eq!(spec.lease_transitions, Some(4));
+    }
+
+    #[test]
+    fn conflicting_write_loses_the_lease() {
+        let api_error = |code| kube::Error::Api(ErrorResponse {
+            status: "Failure".to_owned(),
+            message: "the object has been modified".to_owned(),
+            reason: "Conflict".to_owned(),
+            code,
+        });
+
+        assert!(acquired(Ok(Lease::default())).unwrap());
+        assert!(!acquired(Err(api_error(409))).unwrap());
+        assert!(acquired(Err(api_error(500))).is_err());
+    }
+}
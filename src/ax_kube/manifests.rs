@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use kube::api::DynamicObject;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::backstage::ingest::{cache_key, trim_object};
+
+// Path reading the manifests from stdin
+const STDIN: &str = "-";
+
+// load_manifests - Reads k8s manifests from YAML or JSON files, directories and stdin
+//         into DynamicObjects, as the watches would cache them.
+//
+// Files hold one or more documents, each a single object or a List of objects
+// like the output of `kubectl get -o yaml`. Objects are keyed like the watch
+// cache, objects without namespace are placed in the default one.
+pub fn load_manifests(paths: &[PathBuf]) -> Result<BTreeMap<String, DynamicObject>> {
+    let mut objects = BTreeMap::new();
+
+    for path in paths {
+        for file in manifest_files(path)? {
+            let text = read_manifest(&file)?;
+            parse_manifests(&text, &mut objects)
+                .with_context(|| format!("invalid manifest {}", file.display()))?;
+        }
+    }
+
+    Ok(objects)
+}
+
+// Files of a directory tree with a YAML or JSON extension, sorted for a stable output
+fn manifest_files(path: &Path) -> Result<Vec<PathBuf>> {
+    if path.as_os_str() == STDIN || !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let mut files = Vec::new();
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(path)
+        .with_context(|| format!("failed to read directory {}", path.display()))?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<std::io::Result<_>>()?;
+    entries.sort();
+
+    for entry in entries {
+        if entry.is_dir() {
+            files.extend(manifest_files(&entry)?);
+        } else if entry.extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| matches!(ext, "yaml" | "yml" | "json")) {
+            files.push(entry);
+        }
+    }
+
+    Ok(files)
+}
+
+fn read_manifest(path: &Path) -> Result<String> {
+    if path.as_os_str() == STDIN {
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text)?;
+        return Ok(text);
+    }
+
+    std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read {}", path.display()))
+}
+
+// Parse YAML documents, JSON being a subset of YAML
+fn parse_manifests(text: &str, objects: &mut BTreeMap<String, DynamicObject>) -> Result<()> {
+    for doc in serde_yaml::Deserializer::from_str(text) {
+        let value = Value::deserialize(doc)?;
+        let items = match value {
+            Value::Null => continue,
+            Value::Object(ref o) if is_list(o) => match o.get("items") {
+                Some(Value::Array(items)) => items.clone(),
+                _ => Vec::new(),
+            },
+            item => vec![item],
+        };
+
+        for item in items {
+            let mut obj: DynamicObject = serde_json::from_value(item)?;
+            let kind = match obj.types {
+                Some(ref tm) if !tm.kind.is_empty() => tm.kind.clone(),
+                _ => return Err(anyhow!("object {:?} without apiVersion and kind", obj.metadata.name)),
+            };
+            if obj.metadata.name.is_none() {
+                return Err(anyhow!("{} without metadata.name", kind));
+            }
+            obj.metadata.namespace.get_or_insert_with(|| "default".to_owned());
+
+            trim_object(&mut obj);
+            objects.insert(cache_key(&obj), obj);
+        }
+    }
+
+    Ok(())
+}
+
+// List, or a typed list like DeploymentList
+fn is_list(obj: &serde_json::Map<String, Value>) -> bool {
+    obj.get("kind")
+        .and_then(Value::as_str)
+        .is_some_and(|kind| kind.ends_with("List"))
+        && obj.contains_key("items")
+}
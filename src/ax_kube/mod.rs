@@ -2,14 +2,24 @@ mod apigroup;
 mod discovery;
 pub mod utils;
 pub mod client;
+pub mod catalog_entity;
+pub mod org_config;
+pub mod leader;
+pub mod snapshot;
+pub mod manifests;
 pub mod dynamic_object;
 pub mod watch;
 pub mod watch_event;
+pub mod watch_queue;
+pub mod debounce;
+pub mod throttle;
+pub mod token_review;
 
 pub use client::client;
 pub use discovery::new;
 pub use discovery::{dynamic_api, resolve_api_resources};
 pub use watch::watch;
 pub use watch_event::WatchEvent;
+pub use watch_queue::WatchQueue;
 
 
@@ -0,0 +1,201 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt::Debug;
+
+use anyhow::Result;
+use futures::{StreamExt, TryStreamExt};
+use k8s_openapi::api::core::v1::{ConfigMap, Secret};
+use kube::{api::Api, runtime::{watcher, WatchStreamExt}, Client, Resource, ResourceExt};
+use serde::de::DeserializeOwned;
+
+use crate::ax_kube::client;
+use crate::ax_types::OrgDb;
+use crate::backstage::org::OrgEntities;
+use crate::configuration::{BackstageSettings, Settings};
+use crate::supervisor::Supervisor;
+
+// watch_org_entities - Starts threads to track labelled ConfigMaps and Secrets
+//         declaring Groups, Users and Domains, replacing a redeploy on org changes.
+pub async fn watch_org_entities(conf: &Settings, store: OrgDb, supervisor: &Supervisor) -> Result<()> {
+    let settings = &conf.kube.org_entities;
+    if !settings.enabled {
+        tracing::info!("Org entities watch disabled");
+        return Ok(());
+    }
+
+    let cli = client::client(&conf.kube).await?;
+    let wc = watcher::Config::default().labels(&settings.label_selector);
+
+    let scopes: Vec<Option<String>> = if settings.namespaces.is_empty() {
+        vec![None]
+    } else {
+        settings.namespaces.iter().cloned().map(Some).collect()
+    };
+
+    for ns in scopes {
+        spawn_watch::<ConfigMap>(supervisor, &cli, ns.clone(), wc.clone(), conf.backstage.clone(), store.clone(),
+            config_map_data);
+
+        if settings.secrets {
+            spawn_watch::<Secret>(supervisor, &cli, ns.clone(), wc.clone(), conf.backstage.clone(), store.clone(),
+                secret_data);
+        }
+    }
+
+    Ok(())
+}
+
+fn config_map_data(cm: &ConfigMap) -> BTreeMap<String, String> {
+    cm.data.clone().unwrap_or_default()
+}
+
+// Secret values which are not UTF-8 text are skipped
+fn secret_data(secret: &Secret) -> BTreeMap<String, String> {
+    secret.data.clone()
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|(k, v)| String::from_utf8(v.0).ok().map(|v| (k, v)))
+        .collect()
+}
+
+// Watch ConfigMaps or Secrets, each of their data values holding org entity documents
+fn spawn_watch<K>(supervisor: &Supervisor,
+    cli: &Client,
+    ns: Option<String>,
+    wc: watcher::Config,
+    bsc: BackstageSettings,
+    store: OrgDb,
+    data: fn(&K) -> BTreeMap<String, String>)
+where
+    K: Resource<Scope = k8s_openapi::NamespaceResourceScope, DynamicType = ()>
+        + Clone + Debug + DeserializeOwned + Send + Sync + 'static,
+{
+    let api: Api<K> = match ns {
+        Some(ref ns) => Api::namespaced(cli.clone(), ns),
+        None => Api::all(cli.clone()),
+    };
+    let kind = K::kind(&()).to_lowercase();
+    let prefix = match ns {
+        Some(ref ns) => format!("{}/{}/", kind, ns),
+        None => format!("{}/", kind),
+    };
+
+    let name = format!("org entities watch {}", prefix);
+
+    supervisor.spawn(&name, supervisor.token(), move |token| {
+        let api = api.clone();
+        let wc = wc.clone();
+        let bsc = bsc.clone();
+        let store = store.clone();
+        let kind = kind.clone();
+        let prefix = prefix.clone();
+
+        async move {
+            // errors are retried with backoff instead of reconnecting at once
+            let mut stream = watcher(api, wc).default_backoff().boxed();
+            // keys seen during a relist, others were deleted while disconnected
+            let mut relisted: HashSet<String> = HashSet::new();
+
+            loop {
+                let next = tokio::select! {
+                    _ = token.cancelled() => break,
+                    next = stream.try_next() => next,
+                };
+
+                match next {
+                    Ok(Some(watcher::Event::Init)) => {
+                        relisted.clear();
+                    },
+                    Ok(Some(watcher::Event::InitApply(o))) => {
+                        let key = store_key(&kind, &o);
+                        relisted.insert(key.clone());
+                        apply(&bsc, &store, key, data(&o));
+                    },
+                    Ok(Some(watcher::Event::InitDone)) => {
+                        store.lock().unwrap()
+                            .retain(|k, _| !k.starts_with(&prefix) || relisted.contains(k));
+                    },
+                    Ok(Some(watcher::Event::Apply(o))) => {
+                        apply(&bsc, &store, store_key(&kind, &o), data(&o));
+                    },
+                    Ok(Some(watcher::Event::Delete(o))) => {
+                        let key = store_key(&kind, &o);
+                        tracing::info!("Org entities removed with {}", key);
+                        store.lock().unwrap().remove(&key);
+                    },
+                    Ok(None) => break,
+                    Err(why) => {
+                        tracing::error!("failed to get {} watch response: {:?}", kind, why);
+                        continue;
+                    },
+                }
+            }
+        }
+    });
+}
+
+// Parse all data values, keeping the last good entities if any value is invalid
+fn apply(bsc: &BackstageSettings, store: &OrgDb, key: String, data: BTreeMap<String, String>) {
+    let mut org = OrgEntities::default();
+    for (data_key, text) in data.iter() {
+        match OrgEntities::from_documents(bsc, text) {
+            Ok(entities) => org.extend(entities),
+            Err(why) => {
+                tracing::error!("invalid org entities in {}[{}]: {}", key, data_key, why);
+                return;
+            }
+        }
+    }
+
+    tracing::info!("Org entities loaded from {}: {} groups, {} users, {} domains",
+        key,
+        org.groups.len(),
+        org.users.len(),
+        org.domains.len());
+    store.lock().unwrap().insert(key, org);
+}
+
+fn store_key<K: Resource>(kind: &str, obj: &K) -> String {
+    format!("{}/{}/{}", kind, obj.namespace().unwrap_or_default(), obj.name_any())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::get_configuration;
+    use k8s_openapi::ByteString;
+
+    const TEAM: &str = "apiVersion: backstage.io/v1alpha1\nkind: Group\nmetadata: {name: team-a}\nspec: {type: team, children: []}";
+    const USER: &str = "apiVersion: backstage.io/v1alpha1\nkind: User\nmetadata: {name: jdoe}\nspec: {memberOf: [team-a]}";
+
+    #[test]
+    fn invalid_values_keep_the_last_good_entities() {
+        let bsc = get_configuration().unwrap().backstage;
+        let store = OrgDb::default();
+        let cm = ConfigMap {
+            data: Some(BTreeMap::from([("groups.yaml".to_owned(), TEAM.to_owned()), ("users.yaml".to_owned(), USER.to_owned())])),
+            ..ConfigMap::default()
+        };
+        apply(&bsc, &store, "configmap/org/teams".to_owned(), config_map_data(&cm));
+
+        let bad = BTreeMap::from([("groups.yaml".to_owned(), TEAM.to_owned()), ("users.yaml".to_owned(), "kind: [".to_owned())]);
+        apply(&bsc, &store, "configmap/org/teams".to_owned(), bad);
+
+        let org = store.lock().unwrap().get("configmap/org/teams").cloned().unwrap();
+        assert_eq!((org.groups.len(), org.users.len()), (1, 1));
+    }
+
+    #[test]
+    fn secret_data_skips_binary_values() {
+        let secret = Secret {
+            data: Some(BTreeMap::from([
+                ("users.yaml".to_owned(), ByteString(USER.as_bytes().to_vec())),
+                ("logo.png".to_owned(), ByteString(vec![0x89, 0x50, 0xff, 0xfe])),
+            ])),
+            ..Secret::default()
+        };
+
+        let data = secret_data(&secret);
+        assert_eq!(data.keys().collect::<Vec<_>>(), vec!["users.yaml"]);
+        assert_eq!(data["users.yaml"], USER);
+    }
+}
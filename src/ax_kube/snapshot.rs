@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use kube::{
+    api::{DynamicObject, ListParams},
+    core::TypeMeta};
+
+use crate::ax_kube::{
+    apigroup::AllResource,
+    client,
+    discovery};
+use crate::backstage::ingest::{cache_key, trim_object};
+use crate::configuration::Settings;
+
+/// k8s resource resolvable through API discovery
+#[derive(Debug, Clone)]
+pub struct DiscoveredResource {
+    pub group: String,
+    pub version: String,
+    pub kind: String,
+    pub plural: String,
+    pub namespaced: bool,
+    /// Whether the resource supports list and watch
+    pub watchable: bool,
+    /// Whether the resource is selected by kube.resources
+    pub configured: bool,
+}
+
+// discover_resources - Lists the resources served by the cluster at their recommended
+//         version, flagging those selected by the configuration.
+pub async fn discover_resources(conf: &Settings) -> Result<Vec<DiscoveredResource>> {
+    let cli = client::client(&conf.kube).await?;
+    let discovery = discovery::new(&cli).await?;
+
+    let configured: Vec<(String, String)> = discovery::resolve_api_resources(
+                                &discovery,
+                                &conf.kube.resources)
+        .into_iter()
+        .map(|(ar, _)| (ar.group, ar.kind))
+        .collect();
+
+    let mut resources: Vec<DiscoveredResource> = discovery
+        .groups()
+        .flat_map(|group| group.recommended_resources())
+        .map(|(ar, caps)| DiscoveredResource {
+            configured: configured.contains(&(ar.group.clone(), ar.kind.clone())),
+            namespaced: caps.scope == kube::discovery::Scope::Namespaced,
+            watchable: caps.operations.iter().any(|op| op == "list")
+                && caps.operations.iter().any(|op| op == "watch"),
+            group: ar.group,
+            version: ar.version,
+            kind: ar.kind,
+            plural: ar.plural,
+        })
+        .collect();
+
+    resources.sort_by(|a, b| (&a.group, &a.kind).cmp(&(&b.group, &b.kind)));
+    Ok(resources)
+}
+
+// list_objects - Lists the configured resources once, keyed like the watch cache.
+pub async fn list_objects(conf: &Settings) -> Result<BTreeMap<String, DynamicObject>> {
+    let cli = client::client(&conf.kube).await?;
+    let discovery = discovery::new(&cli).await?;
+    let mut objects = BTreeMap::new();
+
+    let api_res = discovery::resolve_api_resources(
+                        &discovery,
+                        &conf.kube.resources);
+
+    for (ares, caps) in api_res {
+        let types = TypeMeta {
+            api_version: ares.api_version.clone(),
+            kind: ares.kind.clone(),
+        };
+
+        let dyn_apis = discovery::dynamic_api(
+                                ares,
+                                caps,
+                                cli.clone(),
+                                &conf.kube.resources);
+
+        for apisel in dyn_apis {
+            let mut lp = ListParams::default();
+            if let Some(sel) = apisel.label_selectors.filter(|sel| !sel.is_empty()) {
+                lp = lp.labels(&sel.join(","));
+            }
+            if let Some(sel) = apisel.field_selectors.filter(|sel| !sel.is_empty()) {
+                lp = lp.fields(&sel.join(","));
+            }
+
+            for mut obj in apisel.api_dyn.list(&lp).await? {
+                obj.types = Some(types.clone());
+                trim_object(&mut obj);
+                objects.insert(cache_key(&obj), obj);
+            }
+        }
+    }
+
+    Ok(objects)
+}
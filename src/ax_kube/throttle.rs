@@ -0,0 +1,152 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use tokio::time::{sleep, Instant, Sleep};
+use tower::{Layer, Service};
+
+#[derive(Debug)]
+struct Bucket {
+    qps: f64,
+    burst: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    // Take a token, or the time until the next one is available
+    fn take(&mut self) -> Result<(), Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.qps).min(self.burst);
+        self.updated = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.qps))
+        }
+    }
+}
+
+/// Client side rate limit of the requests to the API server, qps requests per
+/// second with bursts of up to burst requests. Shared by all clones.
+#[derive(Debug, Clone)]
+pub struct ThrottleLayer {
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl ThrottleLayer {
+    pub fn new(qps: f64, burst: u32) -> Self {
+        let burst = f64::from(burst.max(1));
+        Self {
+            bucket: Arc::new(Mutex::new(Bucket {
+                qps,
+                burst,
+                tokens: burst,
+                updated: Instant::now(),
+            })),
+        }
+    }
+}
+
+impl<S> Layer<S> for ThrottleLayer {
+    type Service = Throttle<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Throttle {
+            inner,
+            bucket: self.bucket.clone(),
+            wait: None,
+            ready: false,
+        }
+    }
+}
+
+/// Service waiting for a token of the shared bucket before it is ready
+pub struct Throttle<S> {
+    inner: S,
+    bucket: Arc<Mutex<Bucket>>,
+    wait: Option<Pin<Box<Sleep>>>,
+    ready: bool,
+}
+
+impl<S: Clone> Clone for Throttle<S> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            bucket: self.bucket.clone(),
+            wait: None,
+            ready: false,
+        }
+    }
+}
+
+impl<S, Req> Service<Req> for Throttle<S>
+where
+    S: Service<Req>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        while !self.ready {
+            if let Some(wait) = self.wait.as_mut() {
+                if wait.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                self.wait = None;
+            }
+
+            match self.bucket.lock().unwrap().take() {
+                Ok(()) => self.ready = true,
+                Err(retry_after) => self.wait = Some(Box::pin(sleep(retry_after))),
+            }
+        }
+
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        self.ready = false;
+        self.inner.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use tower::{service_fn, ServiceExt};
+
+    #[tokio::test]
+    async fn requests_beyond_the_burst_wait_for_a_token() {
+        let layer = ThrottleLayer::new(20.0, 2);
+        let svc = layer.layer(service_fn(|req: u32| async move { Ok::<_, Infallible>(req) }));
+
+        let start = Instant::now();
+        assert_eq!(svc.clone().oneshot(1).await.unwrap(), 1);
+        assert_eq!(svc.clone().oneshot(2).await.unwrap(), 2);
+        assert!(start.elapsed() < Duration::from_millis(40));
+
+        // clones share the bucket, a token takes 50ms
+        assert_eq!(svc.clone().oneshot(3).await.unwrap(), 3);
+        assert!(start.elapsed() >= Duration::from_millis(40), "{:?}", start.elapsed());
+    }
+
+    #[tokio::test]
+    async fn clones_take_tokens_of_their_own() {
+        let layer = ThrottleLayer::new(1.0, 1);
+        let mut svc = layer.layer(service_fn(|req: u32| async move { Ok::<_, Infallible>(req) }));
+
+        svc.ready().await.unwrap();
+        // a clone is not ready before it took a token of its own
+        let mut other = svc.clone();
+        assert!(tokio::time::timeout(Duration::from_millis(50), other.ready()).await.is_err());
+        assert_eq!(svc.call(7).await.unwrap(), 7);
+    }
+}
@@ -0,0 +1,31 @@
+use anyhow::Result;
+use k8s_openapi::api::authentication::v1::{TokenReview, TokenReviewSpec};
+use kube::{api::{Api, PostParams}, Client};
+
+// review_token - Asks the API server whether the bearer token is valid, e.g. a
+//         service account token of the Backstage backend.
+//
+// Returns the name of the authenticated user, none if the token was rejected.
+pub async fn review_token(cli: &Client, token: &str, audiences: &[String]) -> Result<Option<String>> {
+    let api: Api<TokenReview> = Api::all(cli.clone());
+    let review = TokenReview {
+        spec: TokenReviewSpec {
+            token: Some(token.to_owned()),
+            audiences: (!audiences.is_empty()).then(|| audiences.to_vec()),
+        },
+        ..TokenReview::default()
+    };
+
+    let status = api.create(&PostParams::default(), &review).await?.status;
+    let user = status
+        .filter(|st| st.authenticated == Some(true))
+        .and_then(|st| {
+            if let Some(ref why) = st.error {
+                tracing::debug!("token review error: {}", why);
+            }
+            st.user
+        })
+        .and_then(|user| user.username);
+
+    Ok(user)
+}
@@ -1,8 +1,9 @@
 use crate::ax_kube::{
     client, 
-    discovery, 
+    discovery::{self, ApiWithSelectors}, 
     watch_event::WatchCommand, 
-    WatchEvent};
+    WatchEvent,
+    WatchQueue};
 
 use anyhow::Result;
 use futures::{stream, StreamExt, TryStreamExt};
@@ -10,11 +11,14 @@ use kube::{
     core::ApiResource,
     api::{Api, DynamicObject}, 
     runtime::watcher, 
+    Client,
     ResourceExt};
 // use kube::ResourceExt;
-use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio_util::sync::CancellationToken;
+use std::sync::Arc;
 // use tracing::field;
-use crate::configuration::Settings;
+use crate::configuration::{self as config, Settings};
+use crate::supervisor::Supervisor;
 #[derive(Debug)]
 enum SelectedEvents {
     Applied(watcher::Event<DynamicObject>),
@@ -23,16 +27,151 @@ enum SelectedEvents {
 }
 
 pub struct EventsChannels {
-    pub rx: Receiver<WatchEvent>,
-    pub tx: Sender<WatchEvent>,
+    pub queue: WatchQueue,
 }
 
-// watch - Starts threads to track configured resources, and Senders and a Receiver channels 
-//         for communicating results as WatchEvents
-// pub async fn watch(conf: &Settings, k8s_version: String) -> Result<Receiver<WatchEvent>> {
-pub async fn watch(conf: &Settings, k8s_version: String) -> Result<EventsChannels> {
-    let (tx, rx): (Sender<WatchEvent>, Receiver<WatchEvent>) = channel(32);
+// Watch threads of the configured resources, started and stopped as the configuration changes
+pub struct Watchers {
+    cli: Client,
+    k8s_version: String,
+    tx: WatchQueue,
+    supervisor: Arc<Supervisor>,
+    resources: Vec<config::Resource>,
+    // resource, watch id and token of the watch tasks
+    tasks: Vec<(config::Resource, String, CancellationToken)>,
+    // numbers the watch ids
+    next_id: u64,
+}
+
+impl Watchers {
+    pub fn new(cli: Client, 
+        k8s_version: String, 
+        tx: WatchQueue, 
+        supervisor: Arc<Supervisor>) -> Self {
+        Self {
+            cli,
+            k8s_version,
+            tx,
+            supervisor,
+            resources: Vec::new(),
+            tasks: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    // Start watching the resources in dedicated threads
+    pub async fn start(&mut self, resources: &[config::Resource]) -> Result<()> {
+        let resolved = self.resolve(resources).await?;
+        self.spawn(resolved);
+
+        Ok(())
+    }
+
+    // Resolve the APIs of the resources, nothing is started when discovery fails
+    async fn resolve(&self, resources: &[config::Resource]) -> Result<Vec<(config::Resource, Vec<ApiWithSelectors>)>> {
+        let discovery = discovery::new(&self.cli).await?;
+        let mut resolved = Vec::new();
+
+        for res in resources {
+            let selected = vec![res.clone()];
+            // Common discovery, parameters, and api configuration for a single resource
+            let api_res = discovery::resolve_api_resources( 
+                                &discovery, 
+                                &selected);
 
+            let mut apis = Vec::new();
+            for (ares, caps) in api_res {
+                tracing::debug!("resolved {:?} with {:?}", ares, caps);
+
+                apis.extend(discovery::dynamic_api(
+                                                ares, 
+                                                caps,
+                                                self.cli.clone(), 
+                                                &selected));
+            }
+            resolved.push((res.clone(), apis));
+        }
+
+        Ok(resolved)
+    }
+
+    fn spawn(&mut self, resolved: Vec<(config::Resource, Vec<ApiWithSelectors>)>) {
+        for (res, apis) in resolved {
+            for apisel in apis { 
+                // resources may share the resource_url with other selectors,
+                // their objects are told apart by the id of the watch
+                let watch_id = format!("{}#{}", apisel.api_dyn.resource_url(), self.next_id);
+                self.next_id += 1;
+                // stopped with the resource or on shutdown
+                let token = self.supervisor.token().child_token();
+                spawn_watch(&self.supervisor, 
+                    token.clone(), 
+                    apisel, 
+                    watch_id.clone(),
+                    self.k8s_version.clone(), 
+                    self.tx.clone());
+                self.tasks.push((res.clone(), watch_id, token));
+            }
+
+            self.resources.push(res);
+        }
+    }
+
+    // Stop watching the resources and forget their cached objects
+    pub async fn stop(&mut self, resources: &[config::Resource]) {
+        let (stopped, kept) = std::mem::take(&mut self.tasks)
+            .into_iter()
+            .partition(|(res, _, _)| resources.contains(res));
+        self.tasks = kept;
+        self.resources.retain(|res| !resources.contains(res));
+
+        for (_, watch_id, token) in stopped {
+            token.cancel();
+            let _res = self.tx.send(WatchEvent {
+                watch_id,
+                command: WatchCommand::Forget,
+                ..WatchEvent::default()
+            }).await;
+        }
+    }
+
+    // Start and stop watches so that only the given resources are watched.
+    // Returns the number of started and stopped resources. The added resources
+    // are resolved first, a failed discovery keeps the current watches. The
+    // removed ones are stopped before the added ones start, so that the Forget
+    // of a resource watched again with other selectors precedes its new objects.
+    pub async fn reconcile(&mut self, resources: &[config::Resource]) -> Result<(usize, usize)> {
+        let (added, removed) = diff(&self.resources, resources);
+
+        let resolved = self.resolve(&added).await?;
+        self.stop(&removed).await;
+        self.spawn(resolved);
+
+        Ok((added.len(), removed.len()))
+    }
+}
+
+// Resources to add and remove so that the watched ones become the wanted ones
+fn diff(watched: &[config::Resource], wanted: &[config::Resource]) -> (Vec<config::Resource>, Vec<config::Resource>) {
+    let added = wanted.iter()
+        .filter(|res| !watched.contains(res))
+        .cloned()
+        .collect();
+    let removed = watched.iter()
+        .filter(|res| !wanted.contains(res))
+        .cloned()
+        .collect();
+
+    (added, removed)
+}
+
+// watch - Starts threads to track configured resources, and the bounded queue
+//         of def_channel_size for communicating results as WatchEvents
+// pub async fn watch(conf: &Settings, k8s_version: String) -> Result<Receiver<WatchEvent>> {
+pub async fn watch(conf: &Settings, 
+    k8s_version: String, 
+    tx: WatchQueue,
+    supervisor: Arc<Supervisor>) -> Result<(EventsChannels, Watchers)> {
     let cli = match client::client(&conf.kube).await {
         Err(why) => {
             tracing::error!("k8s Client failed {:?}", why);
@@ -44,122 +183,124 @@ pub async fn watch(conf: &Settings, k8s_version: String) -> Result<EventsChannel
         }
     };
 
-    let discovery = discovery::new(&cli).await?;
-    // Common discovery, parameters, and api configuration for a single resource
-    let api_res = discovery::resolve_api_resources( 
-                        &discovery, 
-                        &conf.kube.resources);
-
-    for (ares, caps) in api_res {
-        println!("\n\n ApiRes {:?}\n CAP: {:?}", ares, caps); 
-
-        let dyn_apis = discovery::dynamic_api(
-                                            ares, 
-                                            caps,
-                                            cli.clone(), 
-                                            &conf.kube.resources);
-
-        for apisel in dyn_apis { 
-            let k8s_ver = k8s_version.clone();
-            let tx2 = tx.clone();
-            let resource_url: String = apisel.api_dyn.resource_url().to_owned();
-
-            // start watching API Resource in a dedicated thread
-            tokio::spawn(async move {
-                let mut wc = watcher::Config::default();
-                if let Some(sel) = apisel.field_selectors {
-                    if sel.len() > 0 {
-                        wc.field_selector = Some(sel.join(","));
-                        println!("Added field selectors {:?} url: {}", 
-                            wc.field_selector, 
-                            resource_url);
-                    }
+    let mut watchers = Watchers::new(cli, k8s_version, tx.clone(), supervisor);
+    watchers.start(&conf.kube.resources).await?;
+
+    Ok((EventsChannels{
+        queue: tx,
+    }, watchers))
+}
+
+// start watching API Resource in a dedicated thread, until the token is cancelled
+fn spawn_watch(supervisor: &Supervisor,
+    token: CancellationToken,
+    apisel: ApiWithSelectors, 
+    watch_id: String,
+    k8s_ver: String, 
+    tx2: WatchQueue) {
+    let resource_url: String = apisel.api_dyn.resource_url().to_owned();
+    let name = format!("watch {}", watch_id);
+
+    supervisor.spawn(&name, token, move |token| {
+        let apisel = apisel.clone();
+        let k8s_ver = k8s_ver.clone();
+        let tx2 = tx2.clone();
+        let resource_url = resource_url.clone();
+        let watch_id = watch_id.clone();
+
+        async move {
+            let mut wc = watcher::Config::default();
+            if let Some(sel) = apisel.field_selectors {
+                if sel.len() > 0 {
+                    wc.field_selector = Some(sel.join(","));
+                    tracing::debug!("field selectors {:?} of {}", wc.field_selector, resource_url);
                 }
+            }
 
-                if let Some(sel) = apisel.label_selectors {
-                    if sel.len() > 0 {
-                        wc.label_selector = Some(sel.join(","));
-                        println!("Added label selectors {:?} url: {}", 
-                            wc.label_selector, 
-                            resource_url);
-                    }
+            if let Some(sel) = apisel.label_selectors {
+                if sel.len() > 0 {
+                    wc.label_selector = Some(sel.join(","));
+                    tracing::debug!("label selectors {:?} of {}", wc.label_selector, resource_url);
                 }
+            }
 
-                // applied_objects().
-                let stream_applied = watcher(apisel.api_dyn.clone(), 
-                                                wc.clone()).
-                                                map_ok(SelectedEvents::Applied);
+            // applied_objects().
+            let stream_applied = watcher(apisel.api_dyn.clone(), 
+                                            wc.clone()).
+                                            map_ok(SelectedEvents::Applied);
 
-                let stream_deleted = watcher(apisel.api_dyn.clone(), 
-                                                wc.clone()).
-                                                    map_ok(SelectedEvents::Deleted);
+            let stream_deleted = watcher(apisel.api_dyn.clone(), 
+                                            wc.clone()).
+                                                map_ok(SelectedEvents::Deleted);
 
-                let stream_restarted = watcher(apisel.api_dyn.clone(), 
-                                                    wc.clone()).
-                                                        map_ok(SelectedEvents::Restarted);
+            let stream_restarted = watcher(apisel.api_dyn.clone(), 
+                                                wc.clone()).
+                                                    map_ok(SelectedEvents::Restarted);
     
-                let mut stream_all =  stream::select_all(vec![
-                    stream_applied.boxed(),
-                    stream_deleted.boxed(),
-                    stream_restarted.boxed(),
-                ]);
-
-                loop {
-                    let cmds: Vec<WatchCommand> = match stream_all.try_next().await {
-                            Ok(sel_event) => {
-                                // TODO test new watch::Event types
-                                match sel_event {
-                                    Some(SelectedEvents::Applied(watcher::Event::Apply(o))) => {
-                                        println!(" >> SEL add {:?}", o.name_any());
-                                        dbg!(&o.types);
-                                        vec![WatchCommand::Add(o)]
-                                    },
-                                    Some(SelectedEvents::Deleted(watcher::Event::Delete(o))) => {
-                                        println!(" >> SEL del {:?}", o.name_any());
-                                        vec![WatchCommand::Delete(o)]
-                                    },
-                                    Some(SelectedEvents::Restarted(watcher::Event::InitApply(o))) => {
-                                        let mut cmds: Vec<WatchCommand> = Vec::new();
-                                        // for o in objs.iter() {
-                                        //     println!(" >> SEL res {:?} types: {:?}", &o.name_any(), &o.types);
-                                        //     cmds.push(WatchCommand::Add(o.clone()));
-                                        // }
-                                        println!(" >> SEL res {:?} types: {:?}", &o.name_any(), &o.types);
-                                        cmds.push(WatchCommand::Add(o.clone()));
-                                        cmds
-                                    },
-                                    _ => {
-                                        continue;
-                                    }
+            let mut stream_all =  stream::select_all(vec![
+                stream_applied.boxed(),
+                stream_deleted.boxed(),
+                stream_restarted.boxed(),
+            ]);
+
+            loop {
+                let next = tokio::select! {
+                    _ = token.cancelled() => {
+                        tracing::info!("Stopped watching {}", resource_url);
+                        return;
+                    },
+                    next = stream_all.try_next() => next,
+                };
+
+                let cmds: Vec<WatchCommand> = match next {
+                        Ok(sel_event) => {
+                            // TODO test new watch::Event types
+                            match sel_event {
+                                Some(SelectedEvents::Applied(watcher::Event::Apply(o))) => {
+                                    tracing::debug!("applied {} {:?} of {}", o.name_any(), o.types, resource_url);
+                                    vec![WatchCommand::Add(o)]
+                                },
+                                Some(SelectedEvents::Deleted(watcher::Event::Delete(o))) => {
+                                    tracing::debug!("deleted {} of {}", o.name_any(), resource_url);
+                                    vec![WatchCommand::Delete(o)]
+                                },
+                                Some(SelectedEvents::Restarted(watcher::Event::InitApply(o))) => {
+                                    tracing::debug!("relisted {} {:?} of {}", o.name_any(), o.types, resource_url);
+                                    vec![WatchCommand::Add(o)]
+                                },
+                                _ => {
+                                    continue;
                                 }
-                            },
-                            Err(why) => {
-                                tracing::error!("failed to get stream_all response: {:?}", why); 
-                                continue;
-                            },
-                        };
-
-                    for cmd in cmds.iter() {
-                        let we = WatchEvent{
-                            k8s_version: k8s_ver.clone(),
-                            resource_url: resource_url.clone(),
-                            event_type: apisel.event_type.clone(),
-                            command: cmd.clone(),
-                        };
-                        tx2.send(we).await.unwrap();
+                            }
+                        },
+                        Err(why) => {
+                            tracing::error!("failed to get stream_all response: {:?}", why); 
+                            continue;
+                        },
                     };
-                }
-            });
+
+                for cmd in cmds.iter() {
+                    let we = WatchEvent{
+                        k8s_version: k8s_ver.clone(),
+                        resource_url: resource_url.clone(),
+                        watch_id: watch_id.clone(),
+                        event_type: apisel.event_type.clone(),
+                        api_resource: Some(apisel.api_resource.clone()),
+                        command: cmd.clone(),
+                    };
+                    if tx2.send(we).await.is_err() {
+                        // ingest stopped, nothing to report to
+                        tracing::info!("Events channel closed, stopped watching {}", resource_url);
+                        return;
+                    }
+                };
+            }
         }
-    }
-    return Ok(EventsChannels{
-        rx,
-        tx: tx.clone(),
     });
 }
 
 // Check if k8s resources is still ready in the cluster.
-pub async fn check_objects(objs: Vec<DynamicObject>, conf: &Settings) -> Result<Vec<DynamicObject>> {
+pub async fn check_objects(objs: Vec<(DynamicObject, ApiResource)>, conf: &Settings) -> Result<Vec<DynamicObject>> {
     let cli = match client::client(&conf.kube).await {
             Err(why) => {
                 tracing::error!("k8s Client failed {:?}", why);
@@ -173,7 +314,7 @@ pub async fn check_objects(objs: Vec<DynamicObject>, conf: &Settings) -> Result<
 
     let mut missing: Vec<DynamicObject> = Vec::new();
 
-    for o in objs.iter() {
+    for (o, ar) in objs.iter() {
         let name = o.name_any();
         let namespace = match &o.metadata.namespace {
             Some(ns) => ns.clone(),
@@ -184,45 +325,10 @@ pub async fn check_objects(objs: Vec<DynamicObject>, conf: &Settings) -> Result<
             }
         };
 
-        let tp = match &o.types {
-            Some(tp) => tp.clone(),
-            None => {
-                tracing::error!("check_obj missing TypeMeta for {:?}/{:?}", 
-                    namespace.clone(),
-                    o.name_any());
-                continue;
-            },
-        };
-
-        let gr_ver: Vec<&str> = tp.api_version.split("/").collect();
-        let (group, ver) = match gr_ver.len() {
-            1 => {
-                (String::from(""), gr_ver[0].to_owned())
-            },
-            2 => {
-                (gr_ver[0].to_owned(), gr_ver[1].to_owned())
-            },
-            _ => {
-                tracing::error!("check_obj incorrect apiVersion for {:?}/{:?}/{:?}", 
-                namespace.clone(),
-                o.name_any(),
-                tp.api_version);
-                continue;
-            }
-        };
-
-        let ar = ApiResource { 
-            group: group, 
-            version: ver, 
-            api_version: tp.api_version, 
-            kind: tp.kind.clone(), 
-            plural: format!("{:}s", tp.kind.to_lowercase()),
-        };
-
         let api: Api<DynamicObject> = Api::namespaced_with(
             cli.clone(), 
             namespace.as_str(),
-            &ar);
+            ar);
 
         match api.get_opt(name.as_str()).await {
             Ok(k8s_obj) => {
@@ -245,3 +351,32 @@ pub async fn check_objects(objs: Vec<DynamicObject>, conf: &Settings) -> Result<
 
     Ok(missing)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resource(name: &str, label_selectors: &[&str]) -> config::Resource {
+        config::Resource {
+            name: name.to_owned(),
+            namespaces: vec!["shop".to_owned()],
+            api_groups: None,
+            label_selectors: label_selectors.iter().map(|s| s.to_string()).collect(),
+            field_selectors: Vec::new(),
+            event_type: "k8s".to_owned(),
+        }
+    }
+
+    #[test]
+    fn diff_adds_and_removes_changed_resources() {
+        let watched = vec![resource("pods", &["app=redis"]), resource("deployments", &[]), resource("services", &[])];
+        let wanted = vec![resource("services", &[]), resource("pods", &["app=web"]), resource("deployments", &[])];
+
+        let (added, removed) = diff(&watched, &wanted);
+        assert_eq!(added, [resource("pods", &["app=web"])]);
+        assert_eq!(removed, [resource("pods", &["app=redis"])]);
+
+        let (added, removed) = diff(&watched, &watched);
+        assert!(added.is_empty() && removed.is_empty());
+    }
+}
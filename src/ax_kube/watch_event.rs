@@ -1,10 +1,16 @@
-use kube::api::DynamicObject;
+use kube::api::{DynamicObject, ResourceExt};
+use kube::core::ApiResource;
 
 #[derive(serde::Deserialize, Debug, Clone)]
 pub struct WatchEvent{
     pub k8s_version: String,
     pub resource_url:  String,
+    /// Id of the watch of a configured resource, watches of resources with
+    /// the same resource_url and other selectors have ids of their own
+    pub watch_id: String,
     pub event_type: String,
+    /// discovered resource of the watch, sets the TypeMeta of its objects
+    pub api_resource: Option<ApiResource>,
     // pub dynamic_object: Option<DynamicObject>,
     pub command: WatchCommand,
 }
@@ -14,18 +20,33 @@ impl Default for WatchEvent {
         WatchEvent{
             k8s_version: "".to_owned(),
             resource_url: "".to_owned(),
+            watch_id: "".to_owned(),
             event_type: "".to_owned(),
-            command: WatchCommand::PrintAll,
+            api_resource: None,
+            command: WatchCommand::None,
         }
     }
 }
 
+impl WatchEvent {
+    /// Watched object of the event, none for commands which are not about one object
+    pub fn object_key(&self) -> Option<String> {
+        let obj = match self.command {
+            WatchCommand::Add(ref obj) | WatchCommand::Update(ref obj) | WatchCommand::Delete(ref obj) => obj,
+            _ => return None,
+        };
+
+        Some(format!("{}/{}/{}", self.watch_id, obj.namespace().unwrap_or_default(), obj.name_any()))
+    }
+}
+
 #[derive(serde::Deserialize, Debug, Clone)]
 pub enum WatchCommand {
     Add(DynamicObject),
     Delete(DynamicObject),
     Update(DynamicObject),
-    PrintAll,
     Purge,
+    // drop the cached objects of the watch_id which is no longer watched
+    Forget,
     None,
 }
\ No newline at end of file
@@ -0,0 +1,307 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+
+use crate::ax_kube::watch_event::{WatchCommand, WatchEvent};
+use crate::configuration::QueuePolicy;
+
+/// Counters of the watch event queue
+#[derive(serde::Serialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueStats {
+    pub policy: String,
+    pub capacity: usize,
+    /// Events waiting for ingestion
+    pub depth: usize,
+    /// Highest depth since the start
+    pub high_water_mark: usize,
+    pub enqueued: u64,
+    /// Sends which waited for free capacity
+    pub blocked: u64,
+    /// Add and Update events dropped by the drop_oldest policy
+    pub dropped: u64,
+    /// Events merged into a queued event of the same object
+    pub coalesced: u64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    events: VecDeque<WatchEvent>,
+    closed: bool,
+    full: bool,
+    stats: QueueStats,
+}
+
+#[derive(Debug)]
+struct Inner {
+    policy: QueuePolicy,
+    capacity: usize,
+    state: Mutex<State>,
+    items: Notify,
+    space: Notify,
+}
+
+/// Bounded queue of WatchEvents between the watches and the ingest thread.
+///
+/// When ingestion falls behind, e.g. during a relist of a large resource, the
+/// policy decides whether watches wait, the oldest Add or Update is dropped, or
+/// events of the same object are merged. Deletes, Purges and Forgets are never
+/// dropped, and events are not merged across a Purge or Forget, so that the
+/// objects of a resource watched again follow its Forget.
+#[derive(Debug, Clone)]
+pub struct WatchQueue {
+    inner: Arc<Inner>,
+}
+
+impl WatchQueue {
+    pub fn new(capacity: usize, policy: QueuePolicy) -> Self {
+        let stats = QueueStats {
+            policy: policy.to_string(),
+            capacity,
+            ..QueueStats::default()
+        };
+
+        Self {
+            inner: Arc::new(Inner {
+                policy,
+                capacity,
+                state: Mutex::new(State { stats, ..State::default() }),
+                items: Notify::new(),
+                space: Notify::new(),
+            }),
+        }
+    }
+
+    /// Queue the event, waiting for capacity if needed. Returns the event
+    /// back once the queue is closed.
+    pub async fn send(&self, we: WatchEvent) -> Result<(), WatchEvent> {
+        let key = we.object_key();
+        let mut waited = false;
+
+        loop {
+            let space = self.inner.space.notified();
+            {
+                let mut state = self.inner.state.lock().unwrap();
+                if state.closed {
+                    return Err(we);
+                }
+
+                if self.inner.policy == QueuePolicy::Coalesce {
+                    if let Some(ref key) = key {
+                        let queued = state.events.iter_mut()
+                            .rev()
+                            .take_while(|queued| queued.object_key().is_some())
+                            .find(|queued| queued.object_key().as_ref() == Some(key));
+                        if let Some(queued) = queued {
+                            *queued = we;
+                            state.stats.coalesced += 1;
+                            return Ok(());
+                        }
+                    }
+                }
+
+                let full = state.events.len() >= self.inner.capacity;
+                if full && self.inner.policy == QueuePolicy::DropOldest {
+                    let oldest = state.events.iter().position(|queued| {
+                        matches!(queued.command, WatchCommand::Add(_) | WatchCommand::Update(_))
+                    });
+                    if let Some(pos) = oldest {
+                        state.events.remove(pos);
+                        state.stats.dropped += 1;
+                    }
+                }
+
+                if state.events.len() < self.inner.capacity {
+                    state.events.push_back(we);
+                    state.stats.enqueued += 1;
+                    let depth = state.events.len();
+                    state.stats.depth = depth;
+                    state.stats.high_water_mark = state.stats.high_water_mark.max(depth);
+                    drop(state);
+                    self.inner.items.notify_one();
+                    return Ok(());
+                }
+
+                if !state.full {
+                    // logged once each time ingestion falls behind
+                    tracing::warn!("watch event queue is full with {} events, policy {}",
+                        self.inner.capacity,
+                        self.inner.policy);
+                    state.full = true;
+                }
+                if !waited {
+                    state.stats.blocked += 1;
+                    waited = true;
+                }
+            }
+            space.await;
+        }
+    }
+
+    /// Next event, none once the queue is closed and drained
+    pub async fn recv(&self) -> Option<WatchEvent> {
+        loop {
+            let items = self.inner.items.notified();
+            {
+                let mut state = self.inner.state.lock().unwrap();
+                if let Some(we) = state.events.pop_front() {
+                    state.stats.depth = state.events.len();
+                    if state.events.is_empty() {
+                        state.full = false;
+                    }
+                    drop(state);
+                    self.inner.space.notify_one();
+                    return Some(we);
+                }
+                if state.closed {
+                    return None;
+                }
+            }
+            items.await;
+        }
+    }
+
+    /// Stop accepting events, the queued ones can still be received
+    pub fn close(&self) {
+        self.inner.state.lock().unwrap().closed = true;
+        self.inner.items.notify_waiters();
+        self.inner.space.notify_waiters();
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.inner.state.lock().unwrap().stats.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kube::api::DynamicObject;
+
+    const URL: &str = "/api/v1/namespaces/shop/services";
+
+    fn event(command: fn(DynamicObject) -> WatchCommand, name: &str, version: &str) -> WatchEvent {
+        let obj: DynamicObject = serde_json::from_value(serde_json::json!({
+            "apiVersion": "v1",
+            "kind": "Service",
+            "metadata": { "name": name, "namespace": "shop", "resourceVersion": version },
+        })).unwrap();
+
+        WatchEvent {
+            resource_url: URL.to_owned(),
+            watch_id: format!("{}#0", URL),
+            command: command(obj),
+            ..WatchEvent::default()
+        }
+    }
+
+    fn forget() -> WatchEvent {
+        WatchEvent {
+            resource_url: URL.to_owned(),
+            watch_id: format!("{}#0", URL),
+            command: WatchCommand::Forget,
+            ..WatchEvent::default()
+        }
+    }
+
+    // Commands and resource versions of the queued events
+    async fn drain(queue: &WatchQueue) -> Vec<String> {
+        queue.close();
+        let mut events = Vec::new();
+        while let Some(we) = queue.recv().await {
+            events.push(match we.command {
+                WatchCommand::Add(ref obj) => format!("add {}", obj.metadata.resource_version.clone().unwrap()),
+                WatchCommand::Update(ref obj) => format!("update {}", obj.metadata.resource_version.clone().unwrap()),
+                WatchCommand::Delete(ref obj) => format!("delete {}", obj.metadata.resource_version.clone().unwrap()),
+                WatchCommand::Forget => "forget".to_owned(),
+                WatchCommand::Purge => "purge".to_owned(),
+                WatchCommand::None => "none".to_owned(),
+            });
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn coalesce_merges_events_of_an_object() {
+        let queue = WatchQueue::new(10, QueuePolicy::Coalesce);
+        queue.send(event(WatchCommand::Add, "orders", "1")).await.unwrap();
+        queue.send(event(WatchCommand::Add, "billing", "2")).await.unwrap();
+        queue.send(event(WatchCommand::Update, "orders", "3")).await.unwrap();
+
+        assert_eq!(queue.stats().coalesced, 1);
+        assert_eq!(drain(&queue).await, vec!["update 3", "add 2"]);
+    }
+
+    #[tokio::test]
+    async fn coalesce_keeps_events_after_forget() {
+        let queue = WatchQueue::new(10, QueuePolicy::Coalesce);
+        queue.send(event(WatchCommand::Add, "orders", "1")).await.unwrap();
+        queue.send(forget()).await.unwrap();
+        // the resource is watched again with other selectors
+        queue.send(event(WatchCommand::Add, "orders", "2")).await.unwrap();
+        queue.send(event(WatchCommand::Update, "orders", "3")).await.unwrap();
+
+        assert_eq!(queue.stats().coalesced, 1);
+        assert_eq!(drain(&queue).await, vec!["add 1", "forget", "update 3"]);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_never_drops_deletes() {
+        let queue = WatchQueue::new(3, QueuePolicy::DropOldest);
+        queue.send(event(WatchCommand::Delete, "orders", "1")).await.unwrap();
+        queue.send(event(WatchCommand::Add, "billing", "2")).await.unwrap();
+        queue.send(forget()).await.unwrap();
+        queue.send(event(WatchCommand::Add, "cart", "3")).await.unwrap();
+
+        assert_eq!(queue.stats().dropped, 1);
+        assert_eq!(drain(&queue).await, vec!["delete 1", "forget", "add 3"]);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_waits_without_droppable_events() {
+        let queue = WatchQueue::new(2, QueuePolicy::DropOldest);
+        queue.send(event(WatchCommand::Delete, "orders", "1")).await.unwrap();
+        queue.send(forget()).await.unwrap();
+
+        let sender = queue.clone();
+        let send = tokio::spawn(async move {
+            sender.send(event(WatchCommand::Add, "cart", "2")).await
+        });
+        tokio::task::yield_now().await;
+        assert!(!send.is_finished());
+
+        assert_eq!(queue.recv().await.map(|we| we.object_key().is_some()), Some(true));
+        send.await.unwrap().unwrap();
+        assert_eq!(queue.stats().dropped, 0);
+        assert_eq!(queue.stats().blocked, 1);
+        assert_eq!(drain(&queue).await, vec!["forget", "add 2"]);
+    }
+
+    #[tokio::test]
+    async fn block_waits_for_capacity() {
+        let queue = WatchQueue::new(1, QueuePolicy::Block);
+        queue.send(event(WatchCommand::Add, "orders", "1")).await.unwrap();
+
+        let sender = queue.clone();
+        let send = tokio::spawn(async move {
+            sender.send(event(WatchCommand::Add, "orders", "2")).await
+        });
+        tokio::task::yield_now().await;
+        assert!(!send.is_finished());
+
+        queue.recv().await.unwrap();
+        send.await.unwrap().unwrap();
+        let stats = queue.stats();
+        assert_eq!((stats.enqueued, stats.blocked, stats.high_water_mark), (2, 1, 1));
+        assert_eq!(drain(&queue).await, vec!["add 2"]);
+    }
+
+    #[tokio::test]
+    async fn closed_queue_returns_the_event() {
+        let queue = WatchQueue::new(1, QueuePolicy::Block);
+        queue.close();
+        assert!(queue.send(event(WatchCommand::Add, "orders", "1")).await.is_err());
+        assert!(queue.recv().await.is_none());
+    }
+}
@@ -1,5 +1,30 @@
+use k8s_openapi::chrono::{DateTime, Utc};
 use kube::api::DynamicObject;
-use std::collections::BTreeMap;
+use kube::core::ApiResource;
+use std::collections::{BTreeMap, BTreeSet};
 use std::sync::{Arc, Mutex};
+use crate::backstage::entities::RawEntity;
+use crate::backstage::org::OrgEntities;
 
-pub type Db = Arc<Mutex<BTreeMap<String, DynamicObject>>>;
\ No newline at end of file
+pub type Db = Arc<Mutex<BTreeMap<String, DynamicObject>>>;
+// Bookkeeping of the objects in the Db, under the same keys
+pub type CacheIndex = Arc<Mutex<BTreeMap<String, CacheEntry>>>;
+// Backstage entities declared in-cluster, keyed by their source object
+pub type EntityDb = Arc<Mutex<BTreeMap<String, RawEntity>>>;
+// Groups, Users and Domains declared in ConfigMaps and Secrets, keyed by their source object
+pub type OrgDb = Arc<Mutex<BTreeMap<String, OrgEntities>>>;
+
+/// How an object came into the cache
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    /// resource_url the object is watched from
+    pub resource_url: String,
+    /// ids of the watches which reported the object, it is dropped with the last one
+    pub watches: BTreeSet<String>,
+    /// event type of the configured resource
+    pub event_type: String,
+    /// time of the last add or update
+    pub cached_at: DateTime<Utc>,
+    /// discovered resource of the object
+    pub api_resource: Option<ApiResource>,
+}
@@ -0,0 +1,255 @@
+use std::collections::{BTreeMap, HashMap};
+
+use kube::{core::DynamicObject, ResourceExt};
+use serde_json::Value;
+
+use crate::backstage::owners::{entity_ref, kind_of};
+
+/// Index linking network objects (Services, Ingresses, HTTPRoutes) to the
+/// Deployments serving them.
+pub struct ApiIndex<'a> {
+    services: HashMap<(String, String), &'a DynamicObject>,
+    deployments: Vec<&'a DynamicObject>,
+    provided: HashMap<String, Vec<String>>,
+}
+
+impl<'a> ApiIndex<'a> {
+    pub fn new(db: &'a BTreeMap<String, DynamicObject>) -> Self {
+        let mut index = Self {
+            services: HashMap::new(),
+            deployments: Vec::new(),
+            provided: HashMap::new(),
+        };
+
+        for obj in db.values() {
+            match kind_of(obj).as_str() {
+                "service" => {
+                    index.services.insert(
+                        (obj.namespace().unwrap_or_default(), obj.name_any()),
+                        obj);
+                },
+                "deployment" => index.deployments.push(obj),
+                _ => {},
+            }
+        }
+
+        for obj in db.values() {
+            if !is_network_kind(&kind_of(obj)) {
+                continue;
+            }
+
+            if let Some(component) = index.provided_by(obj).and_then(entity_ref) {
+                index.provided
+                    .entry(component)
+                    .or_default()
+                    .push(api_ref(obj));
+            }
+        }
+
+        index
+    }
+
+    /// Returns the Deployment serving traffic of a Service, Ingress or HTTPRoute.
+    pub fn provided_by(&self, obj: &DynamicObject) -> Option<&'a DynamicObject> {
+        let ns = obj.namespace().unwrap_or_default();
+        let service = match kind_of(obj).as_str() {
+            "service" => *self.services.get(&(ns.clone(), obj.name_any()))?,
+            _ => {
+                // first backend Service found in the routing rules
+                backend_services(obj)
+                    .iter()
+                    .find_map(|name| self.services.get(&(ns.clone(), name.clone())))
+                    .copied()?
+            }
+        };
+
+        let selector = match service.data.pointer("/spec/selector") {
+            Some(Value::Object(sel)) if !sel.is_empty() => sel,
+            _ => return None,
+        };
+
+        self.deployments
+            .iter()
+            .filter(|d| d.namespace().unwrap_or_default() == ns)
+            .find(|d| {
+                let labels = match d.data.pointer("/spec/template/metadata/labels") {
+                    Some(Value::Object(labels)) => labels,
+                    _ => return false,
+                };
+                selector.iter().all(|(k, v)| labels.get(k) == Some(v))
+            })
+            .copied()
+    }
+
+    /// Returns the API entity references provided by a Deployment's Component.
+    pub fn apis_of(&self, deployment: &DynamicObject) -> Vec<String> {
+        let mut apis = entity_ref(deployment)
+            .and_then(|component| self.provided.get(&component))
+            .cloned()
+            .unwrap_or_default();
+        apis.sort();
+        apis
+    }
+}
+
+/// Name of the API entity generated for a network object, e.g. checkout-ingress.
+pub fn api_name(obj: &DynamicObject) -> String {
+    format!("{}-{}", obj.name_any(), kind_of(obj))
+}
+
+/// Backstage entity reference of the API entity generated for a network object.
+pub fn api_ref(obj: &DynamicObject) -> String {
+    format!("api:default/{}", api_name(obj))
+}
+
+/// URLs under which a Service, Ingress or HTTPRoute can be reached.
+pub fn urls(obj: &DynamicObject) -> Vec<String> {
+    match kind_of(obj).as_str() {
+        "service" => service_urls(obj),
+        "ingress" => ingress_urls(obj),
+        "httproute" => httproute_urls(obj),
+        _ => Vec::new(),
+    }
+}
+
+// Lowercase kinds of kind_of which get an API entity
+pub fn is_network_kind(kind: &str) -> bool {
+    matches!(kind, "service" | "ingress" | "httproute")
+}
+
+fn service_urls(obj: &DynamicObject) -> Vec<String> {
+    let ns = obj.namespace().unwrap_or_default();
+    let mut hosts = vec![format!("{}.{}.svc", obj.name_any(), ns)];
+
+    // externally reachable LoadBalancer addresses
+    if let Some(Value::Array(lbs)) = obj.data.pointer("/status/loadBalancer/ingress") {
+        for lb in lbs {
+            if let Some(Value::String(host)) = lb.get("hostname").or_else(|| lb.get("ip")) {
+                hosts.push(host.clone());
+            }
+        }
+    }
+
+    let mut res = Vec::new();
+    if let Some(Value::Array(ports)) = obj.data.pointer("/spec/ports") {
+        for p in ports {
+            let port = match p.get("port").and_then(Value::as_u64) {
+                Some(port) => port,
+                None => continue,
+            };
+            let name = p.get("name").and_then(Value::as_str).unwrap_or_default();
+            let scheme = if port == 443 || name.contains("https") {
+                "https"
+            } else {
+                "http"
+            };
+
+            for host in hosts.iter() {
+                res.push(format!("{}://{}:{}", scheme, host, port));
+            }
+        }
+    }
+
+    res
+}
+
+fn ingress_urls(obj: &DynamicObject) -> Vec<String> {
+    let mut tls_hosts: Vec<String> = Vec::new();
+    if let Some(Value::Array(tls)) = obj.data.pointer("/spec/tls") {
+        for t in tls {
+            if let Some(Value::Array(hosts)) = t.get("hosts") {
+                tls_hosts.extend(hosts.iter().filter_map(|h| h.as_str().map(String::from)));
+            }
+        }
+    }
+
+    let mut res = Vec::new();
+    if let Some(Value::Array(rules)) = obj.data.pointer("/spec/rules") {
+        for rule in rules {
+            let host = match rule.get("host").and_then(Value::as_str) {
+                Some(host) => host,
+                None => continue,
+            };
+            let scheme = if tls_hosts.iter().any(|h| h == host) { "https" } else { "http" };
+
+            match rule.pointer("/http/paths") {
+                Some(Value::Array(paths)) if !paths.is_empty() => {
+                    for p in paths {
+                        let path = p.get("path").and_then(Value::as_str).unwrap_or("/");
+                        res.push(format!("{}://{}{}", scheme, host, path));
+                    }
+                },
+                _ => res.push(format!("{}://{}/", scheme, host)),
+            }
+        }
+    }
+
+    res
+}
+
+fn httproute_urls(obj: &DynamicObject) -> Vec<String> {
+    let hostnames: Vec<String> = match obj.data.pointer("/spec/hostnames") {
+        Some(Value::Array(hosts)) => hosts.iter()
+            .filter_map(|h| h.as_str().map(String::from))
+            .collect(),
+        _ => Vec::new(),
+    };
+
+    let mut paths: Vec<String> = Vec::new();
+    if let Some(Value::Array(rules)) = obj.data.pointer("/spec/rules") {
+        for rule in rules {
+            if let Some(Value::Array(matches)) = rule.get("matches") {
+                for m in matches {
+                    if let Some(path) = m.pointer("/path/value").and_then(Value::as_str) {
+                        paths.push(path.to_owned());
+                    }
+                }
+            }
+        }
+    }
+    if paths.is_empty() {
+        paths.push("/".to_owned());
+    }
+
+    // the route's listener may terminate TLS or not, assume https for public hosts
+    hostnames.iter()
+        .flat_map(|h| paths.iter().map(move |p| format!("https://{}{}", h, p)))
+        .collect()
+}
+
+// names of the Services referenced as backends by Ingress or HTTPRoute rules
+fn backend_services(obj: &DynamicObject) -> Vec<String> {
+    let mut res = Vec::new();
+
+    if let Some(name) = obj.data.pointer("/spec/defaultBackend/service/name").and_then(Value::as_str) {
+        res.push(name.to_owned());
+    }
+
+    if let Some(Value::Array(rules)) = obj.data.pointer("/spec/rules") {
+        for rule in rules {
+            // Ingress
+            if let Some(Value::Array(paths)) = rule.pointer("/http/paths") {
+                for p in paths {
+                    if let Some(name) = p.pointer("/backend/service/name").and_then(Value::as_str) {
+                        res.push(name.to_owned());
+                    }
+                }
+            }
+
+            // HTTPRoute, backendRefs default to kind Service
+            if let Some(Value::Array(refs)) = rule.get("backendRefs") {
+                for r in refs {
+                    let kind = r.get("kind").and_then(Value::as_str).unwrap_or("Service");
+                    if kind != "Service" {
+                        continue;
+                    }
+                    if let Some(name) = r.get("name").and_then(Value::as_str) {
+                        res.push(name.to_owned());
+                    }
+                }
+            }
+        }
+    }
+
+    res
+}
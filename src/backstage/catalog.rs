@@ -0,0 +1,363 @@
+use std::collections::{BTreeMap, HashMap};
+
+use futures::{stream, StreamExt};
+use kube::api::{DynamicObject, ResourceExt};
+use serde::Serialize;
+
+use crate::ax_kube::client;
+use crate::backstage::entities::{self, BackstageEntity, Domain, Group, User};
+use crate::backstage::owners::OwnerGraph;
+use crate::backstage::org::OrgEntities;
+use crate::backstage::apis::{self, ApiIndex};
+use crate::backstage::definitions::{ApiDefinition, ApiType, DefinitionCache, DefinitionSource};
+use crate::configuration::Settings;
+
+// Definitions fetched at once while building the entities
+const MAX_CONCURRENT_FETCHES: usize = 8;
+
+#[derive(Debug)]
+pub enum K8sKinds {
+    StatefulSet,
+    Deployment,
+    Pod,
+    ReplicaSet,
+    DaemonSet,
+    Job,
+    CronJob,
+    Service,
+    Ingress,
+    HttpRoute,
+    Unknown,
+}
+
+impl K8sKinds {
+    pub fn get_kind(name: &String) -> Self {
+        match name.as_str() {
+            "StatefulSet" => K8sKinds::StatefulSet,
+            "Deployment" => K8sKinds::Deployment,
+            "Pod" => K8sKinds::Pod,
+            "ReplicaSet" => K8sKinds::ReplicaSet,
+            "DaemonSet" => K8sKinds::DaemonSet,
+            "Job" => K8sKinds::Job,
+            "CronJob" => K8sKinds::CronJob,
+            "Service" => K8sKinds::Service,
+            "Ingress" => K8sKinds::Ingress,
+            "HTTPRoute" => K8sKinds::HttpRoute,
+            _ => K8sKinds::Unknown,
+        }
+    }
+}
+
+/// Reference to the k8s object an entity was derived from
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct SourceRef {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_version: Option<String>,
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resource_version: Option<String>,
+}
+
+impl SourceRef {
+    pub fn from_object(obj: &DynamicObject) -> Self {
+        Self {
+            api_version: obj.types.as_ref().map(|t| t.api_version.clone()),
+            kind: obj.types.as_ref().map(|t| t.kind.clone()).unwrap_or_default(),
+            namespace: obj.namespace(),
+            name: obj.name_any(),
+            uid: obj.uid(),
+            resource_version: obj.resource_version(),
+        }
+    }
+
+    /// Source of the entities stored under a {kind}/{namespace}/{name} key,
+    /// like the CatalogEntity and org ConfigMap stores
+    pub fn from_store_key(key: &str) -> Self {
+        let mut parts = key.splitn(3, '/');
+        let kind = parts.next().unwrap_or_default().to_owned();
+        let namespace = parts.next().filter(|ns| !ns.is_empty()).map(str::to_owned);
+        let name = parts.next().unwrap_or_default().to_owned();
+        Self {
+            api_version: None,
+            kind,
+            namespace,
+            name,
+            uid: None,
+            resource_version: None,
+        }
+    }
+}
+
+/// Source objects of the entities, keyed by entity reference
+pub type EntitySources = BTreeMap<String, Vec<SourceRef>>;
+
+// Record obj as a source of the entity
+fn add_source(sources: &mut EntitySources, kind: &str, md: &entities::Metadata, obj: &DynamicObject) {
+    let refs = sources.entry(md.entity_ref(kind)).or_default();
+    let src = SourceRef::from_object(obj);
+    if !refs.contains(&src) {
+        refs.push(src);
+    }
+}
+
+// Convert the cached k8s objects into Backstage entities
+pub fn cluster_entities(web_config: &Settings, 
+    db: &BTreeMap<String, DynamicObject>,
+    api_defs: &HashMap<String, (ApiType, ApiDefinition)>) -> Vec<Box<dyn BackstageEntity>> {
+    cluster_entities_with_sources(web_config, db, api_defs).0
+}
+
+// Convert the cached k8s objects into Backstage entities, along with the
+// objects each entity was derived from
+pub fn cluster_entities_with_sources(web_config: &Settings, 
+    db: &BTreeMap<String, DynamicObject>,
+    api_defs: &HashMap<String, (ApiType, ApiDefinition)>) -> (Vec<Box<dyn BackstageEntity>>, EntitySources) {
+    let owners = OwnerGraph::new(db);
+    let api_index = ApiIndex::new(db);
+
+    // let mut res: Vec<entities:::Resource> = Vec::new();
+    let mut res: Vec<Box<dyn BackstageEntity>> = Vec::new();
+    // ordered for a stable output
+    let mut seen: BTreeMap<String, entities::Resource> = BTreeMap::new();
+    let mut seen_system: HashMap<String, u8> = HashMap::new();
+    let mut sources = EntitySources::new();
+    for (_, obj) in db.iter() {
+        let obj_kind: K8sKinds = match &obj.types {
+            Some(t) => {
+                K8sKinds::get_kind(&t.kind)
+            },
+            None => {
+                tracing::debug!("unknown k8s resource {:?}", obj.name_any());
+                continue;
+            }
+        };
+
+        match obj_kind {
+            K8sKinds::StatefulSet => {
+                // Create Resource for Redis Shard
+                let mut redis_shard = match entities::Resource::redis_shard_from_statefulset(web_config, obj){
+                    Ok(res) => res,
+                    Err(why) => {
+                        tracing::error!("Resource Entity conversion failed {:?}", why);
+                        continue;
+                    }
+                };
+                let cluster_result = entities::Resource::redis_cluster_from_shard(web_config, redis_shard.clone());
+                redis_shard.add_relations(&owners.relations_for(obj));
+                add_source(&mut sources, &redis_shard.kind, &redis_shard.metadata, obj);
+                res.push(Box::new(redis_shard));
+
+                // Create Redis cluster Resource
+                match cluster_result {
+                    Ok(cluster) => {
+                        add_source(&mut sources, &cluster.kind, &cluster.metadata, obj);
+                        let sname = format!("redis_cluster/{}", cluster.metadata.name.clone());
+                        match seen.get_mut(&sname) {
+                            Some(seen_cluster) => {
+                                // append new dependencies to seen cluster's dependencies
+                                let mut dep_new = cluster.spec.depends_on.clone().unwrap();
+                                let mut dep_seen = seen_cluster.spec.depends_on.clone().unwrap();
+                                dep_seen.append(&mut dep_new);
+                                seen_cluster.spec.depends_on = Some(dep_seen);
+                            },
+                            None => {
+                                seen.insert(sname, cluster);
+                            },
+                        }
+                    },
+                    Err(why) => {
+                        tracing::error!("System Entity conversion failed {:?}", why);
+                    }
+                }
+
+                // create System for the Redis cluster
+                match entities::System::from_stateful_set(web_config, obj) {
+                    Ok(system) => {
+                        add_source(&mut sources, &system.kind, &system.metadata, obj);
+                        let sname = format!("system/{}", system.metadata.name.clone());
+                        if seen_system.contains_key(&sname) {
+                            continue;
+                        }else{
+                            seen_system.insert(sname, 1);
+                        }
+                        res.push(Box::new(system));
+                    },
+                    Err(why) => {
+                        tracing::error!("System Entity conversion failed {:?}", why);
+                    },
+                }
+                
+            },
+            K8sKinds::Pod => {
+                let is_redis_node = obj.labels().contains_key(entities::REDIS_LABEL_SHARD);
+                let node = if is_redis_node {
+                    entities::Resource::redis_node_from_pod(web_config, obj)
+                } else {
+                    entities::Resource::from_workload(web_config, obj)
+                };
+                let mut node = match node {
+                    Ok(node) => node,
+                    Err(why) => {
+                        tracing::error!("Resource Entity conversion failed {:?}", why);
+                        continue;
+                    }
+                };
+                node.add_relations(&owners.relations_for(obj));
+                add_source(&mut sources, &node.kind, &node.metadata, obj);
+                res.push(Box::new(node));
+            },
+            K8sKinds::Deployment => {
+                let mut component = match entities::Component::from_deployment(web_config.backstage.clone(), obj) {
+                    Ok(component) => component,
+                    Err(why) => {
+                        tracing::error!("Component Entity conversion failed {:?}", why);
+                        continue;
+                    }
+                };
+                component.add_relations(&owners.relations_for(obj));
+                component.add_provided_apis(&api_index.apis_of(obj));
+                add_source(&mut sources, &component.kind, &component.metadata, obj);
+                res.push(Box::new(component));
+            },
+            K8sKinds::Service | K8sKinds::Ingress | K8sKinds::HttpRoute => {
+                // APIs are part of the same System as the Deployment serving them
+                let system = api_index.provided_by(obj)
+                    .and_then(|d| owners.relations_for(d).system);
+                match entities::Api::from_network_object(web_config, obj, apis::urls(obj), system) {
+                    Ok(mut api) => {
+                        if let Some((api_type, def)) = api_defs.get(&apis::api_name(obj)) {
+                            api.set_definition(*api_type, def.clone());
+                        }
+                        add_source(&mut sources, &api.kind, &api.metadata, obj);
+                        res.push(Box::new(api))
+                    },
+                    Err(why) => {
+                        tracing::debug!("API Entity conversion skipped {:?}", why);
+                    }
+                }
+            },
+            K8sKinds::DaemonSet | K8sKinds::Job | K8sKinds::CronJob => {
+                let mut workload = match entities::Resource::from_workload(web_config, obj) {
+                    Ok(workload) => workload,
+                    Err(why) => {
+                        tracing::error!("Resource Entity conversion failed {:?}", why);
+                        continue;
+                    }
+                };
+                workload.add_relations(&owners.relations_for(obj));
+                add_source(&mut sources, &workload.kind, &workload.metadata, obj);
+                res.push(Box::new(workload));
+            },
+            K8sKinds::ReplicaSet => {
+                // ReplicaSets are collapsed into their Deployment
+                tracing::debug!("k8s kind linked through its owner: {:?}", obj_kind);
+            },
+            _ => {
+                tracing::debug!("k8s kind not supported: {:?}", obj_kind);
+            }
+        }
+    }
+
+    for (_key, redis_cluster) in seen {
+        res.push(Box::new(redis_cluster.clone()));
+    }
+
+    (res, sources)
+}
+
+// Groups, Users and Domains of the app config, overridden by the org entities of the cluster
+pub fn org_entities(groups: &[Group], 
+    users: &[User], 
+    domains: &[Domain], 
+    org: OrgEntities) -> Vec<Box<dyn BackstageEntity>> {
+    let mut res: Vec<Box<dyn BackstageEntity>> = Vec::new();
+
+    for g in groups.iter().filter(|g| !org.contains("Group", &g.metadata.name)) {
+        res.push(Box::new(g.clone()));
+    }
+
+    for u in users.iter().filter(|u| !org.contains("User", &u.metadata.name)) {
+        res.push(Box::new(u.clone()));
+    }
+
+    for d in domains.iter().filter(|d| !org.contains("Domain", &d.metadata.name)) {
+        res.push(Box::new(d.clone()));
+    }
+
+    for g in org.groups {
+        res.push(Box::new(g));
+    }
+
+    for u in org.users {
+        res.push(Box::new(u));
+    }
+
+    for d in org.domains {
+        res.push(Box::new(d));
+    }
+
+    res
+}
+
+// Services annotated with an API definition, keyed by API entity name
+pub fn definition_sources(db: &BTreeMap<String, DynamicObject>) -> Vec<(String, DefinitionSource)> {
+    db.values()
+        .filter(|obj| obj.types.as_ref().is_some_and(|t| t.kind == "Service"))
+        .filter_map(|obj| DefinitionSource::from_service(obj).map(|src| (apis::api_name(obj), src)))
+        .collect()
+}
+
+// Fetch OpenAPI/AsyncAPI definitions of annotated Services, keyed by API entity name.
+// Up to MAX_CONCURRENT_FETCHES definitions are fetched at once, each within
+// fetch_timeout_secs, and the definitions of Services gone are dropped.
+pub async fn api_definitions(config: &Settings, 
+    sources: Vec<(String, DefinitionSource)>,
+    cache: &DefinitionCache) -> HashMap<String, (ApiType, ApiDefinition)> {
+    let current: Vec<DefinitionSource> = sources.iter().map(|(_, src)| src.clone()).collect();
+    cache.retain(&current);
+
+    if sources.is_empty() {
+        return HashMap::new();
+    }
+
+    let cli = match client::client(&config.kube).await {
+        Ok(cli) => cli,
+        Err(why) => {
+            tracing::error!("k8s Client failed {:?}", why);
+            return HashMap::new();
+        }
+    };
+
+    stream::iter(sources)
+        .map(|(name, src)| {
+            let cli = cli.clone();
+            async move {
+                let def = cache
+                    .get(&cli, &config.backstage.api_definitions, &src)
+                    .await;
+                (name, (src.api_type, def))
+            }
+        })
+        .buffer_unordered(MAX_CONCURRENT_FETCHES)
+        .collect()
+        .await
+}
+
+// Render entities as a multi-document catalog-info.yaml.
+// Maps are sorted by key, so that the same entities always render the same.
+pub fn to_yaml_documents<T: Serialize>(entities: &[T]) -> anyhow::Result<String> {
+    let mut out = String::new();
+    for entity in entities {
+        let value = serde_json::to_value(entity)?;
+        out.push_str("---\n");
+        out.push_str(&serde_yaml::to_string(&value)?);
+    }
+
+    Ok(out)
+}
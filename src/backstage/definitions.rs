@@ -0,0 +1,381 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Result};
+use kube::{core::DynamicObject, Client, ResourceExt};
+use serde_json::Value;
+
+use crate::configuration::ApiDefinitionSettings;
+use crate::errors::EntityError;
+
+// Service annotations pointing at the API definition served by the workload
+pub const ANN_OPENAPI_PATH: &str = "backstage.acme.com/openapi-path";
+pub const ANN_ASYNCAPI_PATH: &str = "backstage.acme.com/asyncapi-path";
+// optional Service port name or number serving the definition
+pub const ANN_API_PORT: &str = "backstage.acme.com/api-port";
+
+/// Definition of a Backstage API entity, either the document itself or a
+/// `$text` substitution Backstage resolves when ingesting the entity.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum ApiDefinition {
+    Embedded(String),
+    Text {
+        #[serde(rename = "$text")]
+        text: String,
+    },
+}
+
+impl Default for ApiDefinition {
+    fn default() -> Self {
+        Self::Embedded(String::new())
+    }
+}
+
+/// Kind of API definition a Service points to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ApiType {
+    OpenApi,
+    AsyncApi,
+}
+
+impl ApiType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiType::OpenApi => "openapi",
+            ApiType::AsyncApi => "asyncapi",
+        }
+    }
+}
+
+/// Location of an API definition derived from the Service annotations.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DefinitionSource {
+    pub api_type: ApiType,
+    pub namespace: String,
+    pub service: String,
+    pub scheme: String,
+    pub port: String,
+    pub path: String,
+}
+
+impl DefinitionSource {
+    /// Returns the definition source of a Service, None if it isn't annotated.
+    pub fn from_service(obj: &DynamicObject) -> Option<Self> {
+        let anns = obj.annotations();
+        let (api_type, path) = match (anns.get(ANN_OPENAPI_PATH), anns.get(ANN_ASYNCAPI_PATH)) {
+            (Some(path), _) => (ApiType::OpenApi, path),
+            (None, Some(path)) => (ApiType::AsyncApi, path),
+            (None, None) => return None,
+        };
+
+        // explicit port, otherwise the first port of the Service
+        let port = match anns.get(ANN_API_PORT) {
+            Some(port) => port.clone(),
+            None => match obj.data.pointer("/spec/ports/0/port") {
+                Some(Value::Number(port)) => port.to_string(),
+                _ => return None,
+            },
+        };
+        let scheme = if port == "443" || port.contains("https") { "https" } else { "http" };
+
+        Some(Self {
+            api_type,
+            namespace: obj.namespace().unwrap_or_else(|| "default".to_owned()),
+            service: obj.name_any(),
+            scheme: scheme.to_owned(),
+            port,
+            path: format!("/{}", path.trim_start_matches('/')),
+        })
+    }
+
+    /// Path of the definition through the k8s API server service proxy
+    pub fn proxy_path(&self) -> String {
+        format!("/api/v1/namespaces/{}/services/{}:{}:{}/proxy{}",
+            self.namespace,
+            self.scheme,
+            self.service,
+            self.port,
+            self.path)
+    }
+
+    /// In-cluster URL of the definition, used for `$text` substitutions
+    pub fn service_url(&self) -> String {
+        format!("{}://{}.{}.svc:{}{}",
+            self.scheme,
+            self.service,
+            self.namespace,
+            self.port,
+            self.path)
+    }
+}
+
+/// Fetches the API definition through the k8s API server service proxy.
+pub async fn fetch(client: &Client, source: &DefinitionSource) -> Result<String> {
+    let req = http::Request::get(source.proxy_path())
+        .body(Default::default())?;
+
+    client.request_text(req)
+        .await
+        .map_err(|why| anyhow!("failed fetching {} definition of {}/{}: {}",
+            source.api_type.as_str(),
+            source.namespace,
+            source.service,
+            why))
+}
+
+/// Validates a fetched OpenAPI or AsyncAPI document, JSON or YAML encoded.
+pub fn validate(api_type: ApiType, body: &str) -> std::result::Result<(), EntityError> {
+    let doc: Value = match serde_json::from_str(body) {
+        Ok(doc) => doc,
+        Err(_) => serde_yaml::from_str(body)
+            .map_err(|why| EntityError::conversion(format!("definition is neither JSON nor YAML: {}", why)))?,
+    };
+
+    let doc = match doc {
+        Value::Object(doc) => doc,
+        _ => return Err(EntityError::invalid_value("definition", "not a document")),
+    };
+
+    let version = match api_type {
+        ApiType::OpenApi => doc.get("openapi").or_else(|| doc.get("swagger")),
+        ApiType::AsyncApi => doc.get("asyncapi"),
+    };
+    match version {
+        Some(Value::String(_)) => {},
+        _ => return Err(EntityError::missing_field(format!("{} version", api_type.as_str()))),
+    }
+
+    match doc.get("info") {
+        Some(Value::Object(info)) if info.contains_key("title") => Ok(()),
+        _ => Err(EntityError::missing_field("info.title")),
+    }
+}
+
+/// Resolves the definition of an API entity, embedding the fetched document
+/// if it validates, otherwise falling back to a `$text` substitution.
+pub async fn resolve(client: &Client,
+    settings: &ApiDefinitionSettings,
+    source: &DefinitionSource) -> ApiDefinition {
+    let text = ApiDefinition::Text { text: source.service_url() };
+    if !settings.embed {
+        return text;
+    }
+
+    let timeout = Duration::from_secs(settings.fetch_timeout_secs);
+    let fetched = tokio::time::timeout(timeout, fetch(client, source))
+        .await
+        .unwrap_or_else(|_| Err(anyhow!("timed out fetching {} definition of {}/{} after {}s",
+            source.api_type.as_str(),
+            source.namespace,
+            source.service,
+            settings.fetch_timeout_secs)));
+
+    match fetched {
+        Ok(body) if body.len() > settings.max_size_bytes => {
+            tracing::warn!("{} definition of {}/{} exceeds {} bytes",
+                source.api_type.as_str(),
+                source.namespace,
+                source.service,
+                settings.max_size_bytes);
+            text
+        },
+        Ok(body) => match validate(source.api_type, &body) {
+            Ok(_) => ApiDefinition::Embedded(body),
+            Err(why) => {
+                tracing::warn!("invalid {} definition of {}/{}: {}",
+                    source.api_type.as_str(),
+                    source.namespace,
+                    source.service,
+                    why);
+                text
+            }
+        },
+        Err(why) => {
+            tracing::warn!("{:?}", why);
+            text
+        }
+    }
+}
+
+/// Resolved definitions kept for a while to avoid fetching them on every request.
+pub struct DefinitionCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Instant, DefinitionSource, ApiDefinition)>>,
+}
+
+impl DefinitionCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns a resolved definition for the Service, fetching it if stale.
+    pub async fn get(&self,
+        client: &Client,
+        settings: &ApiDefinitionSettings,
+        source: &DefinitionSource) -> ApiDefinition {
+        let key = format!("{}/{}", source.namespace, source.service);
+        if let Some((at, cached_source, def)) = self.entries.lock().unwrap().get(&key) {
+            if at.elapsed() < self.ttl && cached_source == source {
+                return def.clone();
+            }
+        }
+
+        let def = resolve(client, settings, source).await;
+        self.entries.lock().unwrap().insert(key, (Instant::now(), source.clone(), def.clone()));
+        def
+    }
+
+    /// Drops the definitions of Services which are no longer annotated or cached
+    pub fn retain(&self, sources: &[DefinitionSource]) {
+        self.entries.lock().unwrap().retain(|_, (_, cached_source, _)| sources.contains(cached_source));
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const OPENAPI: &str = r#"{"openapi": "3.0.0", "info": {"title": "orders", "version": "1"}, "paths": {}}"#;
+
+    fn service(annotations: serde_json::Value) -> DynamicObject {
+        serde_json::from_value(serde_json::json!({
+            "apiVersion": "v1",
+            "kind": "Service",
+            "metadata": { "name": "orders", "namespace": "shop", "annotations": annotations },
+            "spec": { "ports": [{ "port": 8080 }] },
+        })).unwrap()
+    }
+
+    fn source() -> DefinitionSource {
+        DefinitionSource::from_service(&service(serde_json::json!({ ANN_OPENAPI_PATH: "openapi.json" }))).unwrap()
+    }
+
+    // Client of a local API server answering the proxy path of source() with
+    // the body after the delay, and 404 otherwise
+    async fn stub(body: &'static str, delay: Duration) -> Client {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let expected = format!("GET {} ", source().proxy_path());
+
+        tokio::spawn(async move {
+            while let Ok((mut sock, _)) = listener.accept().await {
+                let expected = expected.clone();
+                tokio::spawn(async move {
+                    let mut buf = vec![0u8; 4096];
+                    let n = sock.read(&mut buf).await.unwrap_or(0);
+                    let (status, body) = if String::from_utf8_lossy(&buf[..n]).starts_with(&expected) {
+                        ("200 OK", body)
+                    } else {
+                        ("404 Not Found", "")
+                    };
+                    tokio::time::sleep(delay).await;
+                    let resp = format!("HTTP/1.1 {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                        status,
+                        body.len(),
+                        body);
+                    let _res = sock.write_all(resp.as_bytes()).await;
+                });
+            }
+        });
+
+        let config = kube::Config::new(format!("http://{}", addr).parse().unwrap());
+        Client::try_from(config).unwrap()
+    }
+
+    #[test]
+    fn source_of_annotated_service() {
+        let src = source();
+        assert_eq!(src.api_type, ApiType::OpenApi);
+        assert_eq!(src.proxy_path(), "/api/v1/namespaces/shop/services/http:orders:8080/proxy/openapi.json");
+        assert_eq!(src.service_url(), "http://orders.shop.svc:8080/openapi.json");
+
+        let src = DefinitionSource::from_service(&service(serde_json::json!({
+            ANN_ASYNCAPI_PATH: "/asyncapi.yaml",
+            ANN_API_PORT: "https",
+        }))).unwrap();
+        assert_eq!(src.api_type, ApiType::AsyncApi);
+        assert_eq!(src.service_url(), "https://orders.shop.svc:https/asyncapi.yaml");
+
+        assert!(DefinitionSource::from_service(&service(serde_json::json!({}))).is_none());
+    }
+
+    #[test]
+    fn validate_documents() {
+        assert!(validate(ApiType::OpenApi, OPENAPI).is_ok());
+        assert!(validate(ApiType::OpenApi, "swagger: '2.0'\ninfo:\n  title: orders\n").is_ok());
+        assert!(validate(ApiType::AsyncApi, "asyncapi: 2.6.0\ninfo:\n  title: events\n").is_ok());
+
+        assert!(validate(ApiType::AsyncApi, OPENAPI).is_err());
+        assert!(validate(ApiType::OpenApi, r#"{"openapi": "3.0.0", "info": {}}"#).is_err());
+        assert!(validate(ApiType::OpenApi, "- not\n- a document\n").is_err());
+        assert!(validate(ApiType::OpenApi, "{ not: [yaml").is_err());
+    }
+
+    #[tokio::test]
+    async fn resolve_embeds_valid_definition() {
+        let cli = stub(OPENAPI, Duration::ZERO).await;
+        match resolve(&cli, &ApiDefinitionSettings::default(), &source()).await {
+            ApiDefinition::Embedded(body) => assert_eq!(body, OPENAPI),
+            def => panic!("unexpected definition {:?}", def),
+        }
+    }
+
+    #[tokio::test]
+    async fn resolve_falls_back_to_text() {
+        let url = source().service_url();
+        let is_text = |def: ApiDefinition| matches!(def, ApiDefinition::Text { ref text } if *text == url);
+
+        let cli = stub(r#"{"info": {"title": "orders"}}"#, Duration::ZERO).await;
+        assert!(is_text(resolve(&cli, &ApiDefinitionSettings::default(), &source()).await));
+
+        let cli = stub(OPENAPI, Duration::ZERO).await;
+        let small = ApiDefinitionSettings { max_size_bytes: 16, ..ApiDefinitionSettings::default() };
+        assert!(is_text(resolve(&cli, &small, &source()).await));
+        let text = ApiDefinitionSettings { embed: false, ..ApiDefinitionSettings::default() };
+        assert!(is_text(resolve(&cli, &text, &source()).await));
+
+        let mut other = source();
+        other.service = "billing".to_owned();
+        let def = resolve(&cli, &ApiDefinitionSettings::default(), &other).await;
+        assert!(matches!(def, ApiDefinition::Text { .. }));
+    }
+
+    #[tokio::test]
+    async fn resolve_gives_up_after_timeout() {
+        let cli = stub(OPENAPI, Duration::from_secs(30)).await;
+        let settings = ApiDefinitionSettings { fetch_timeout_secs: 1, ..ApiDefinitionSettings::default() };
+
+        let started = Instant::now();
+        let def = resolve(&cli, &settings, &source()).await;
+        assert!(matches!(def, ApiDefinition::Text { .. }));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn cache_keeps_current_sources() {
+        let cli = stub(OPENAPI, Duration::ZERO).await;
+        let cache = DefinitionCache::new(Duration::from_secs(60));
+        let settings = ApiDefinitionSettings::default();
+        cache.get(&cli, &settings, &source()).await;
+        assert_eq!(cache.len(), 1);
+
+        cache.retain(&[source()]);
+        assert_eq!(cache.len(), 1);
+        cache.retain(&[]);
+        assert!(cache.is_empty());
+    }
+}
@@ -9,6 +9,9 @@ use kube::{core::DynamicObject, ResourceExt};
 use anyhow::Result;
 use std::fmt;
 use crate::configuration::{BackstageSettings, Settings};
+use crate::backstage::owners::{OwnerRelations, merge_refs};
+use crate::backstage::apis::api_name;
+use crate::backstage::definitions::{ApiDefinition, ApiType};
 
 const BACKSTAGE_DEFAULT_OWNER: &str = "platform"; 
 const BACKSTAGE_ENTITY_API_VERSION: &str = "backstage.io/v1alpha1";
@@ -18,12 +21,13 @@ const BACKSTAGE_ENTITY_USER: &str = "User";
 const BACKSTAGE_ENTITY_GROUP: &str = "Group";
 const BACKSTAGE_ENTITY_DOMAIN: &str = "Domain";
 const BACKSTAGE_ENTITY_SYSTEM: &str = "System";
+const BACKSTAGE_ENTITY_API: &str = "API";
 const BACKSTAGE_ENTITY_NONE: &str = "none";
 const BACKSTAGE_ANN_LABEL_SELECTOR: &str = "backstage.io/kubernetes-label-selector";
 const BACKSTAGE_ANN_NAMESPACE: &str = "backstage.io/kubernetes-namespace";
 const AXYOMCORE_ANN_CLUSTER: &str = "acme.com/kubernetes-cluster";
 const REDIS_LABEL_CLUSTER: &str = "redis.acme.com/name";
-const REDIS_LABEL_SHARD: &str = "shard.acme.com/name";
+pub const REDIS_LABEL_SHARD: &str = "shard.acme.com/name";
 const REDIS_LABEL_K8S_NAME: &str = "app.kubernetes.io/component";
 
 // custom annotations to convey state
@@ -80,6 +84,14 @@ impl Metadata {
         }
     }
 
+    // Backstage entity reference of an entity of the kind, e.g. system:default/payments
+    pub fn entity_ref(&self, kind: &str) -> String {
+        format!("{}:{}/{}",
+            kind.to_lowercase(),
+            self.namespace.as_deref().unwrap_or("default"),
+            self.name)
+    }
+
     // add global settings to those configured for the static entity like Group
     pub fn from_static_config(bsc: BackstageSettings, md: Metadata) -> Self {
             // glbal annotations
@@ -135,15 +147,21 @@ pub struct ComponentSpec {
     pub r#type: String,
     pub lifecycle: String,
     pub owner: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub system: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename(serialize = "subcomponentOf", deserialize = "subcomponentOf"))]
     pub subcomponent_of: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename(serialize = "providesApis", deserialize = "providesApis"))]
     pub provides_apis: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename(serialize = "consumesApis", deserialize = "consumesApis"))]
     pub consumes_apis: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename(serialize = "dependsOn", deserialize = "dependsOn"))]
     pub depends_on: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     #[serde(rename(serialize = "dependencyOf", deserialize = "dependencyOf"))]
     pub dependency_of: Option<Vec<String>>
 }
@@ -167,7 +185,7 @@ impl Component {
         // validations
         //check if StatefulSet
         if let Some(ref tp) = obj.types {
-            if tp.kind.to_lowercase() != "deployment" {
+            if tp.kind != "Deployment" {
                 return Err(EntityError{ 
                     kind: BACKSTAGE_ENTITY_COMPONENT.to_owned(),
                     name: obj.name_any().clone(),
@@ -231,6 +249,10 @@ impl Component {
             if !lbls.is_empty() {
                 m.labels = Some(lbls);
             }
+
+            // namespace of the Deployment, also when it is no Redis shard
+            anns.entry(BACKSTAGE_ANN_NAMESPACE.to_string())
+                .or_insert_with(|| ns.to_string());
     
             if let Some(ref bs_anns) = m.annotations {
                 for (a, v) in bs_anns.iter(){
@@ -245,11 +267,26 @@ impl Component {
                 metadata: m,
                 spec: ComponentSpec {
                     r#type: spec_type,
+                    lifecycle: String::from("experimental"),
                     owner: String::from(BACKSTAGE_DEFAULT_OWNER.to_owned()),
                     ..Default::default()
                 }
             })
     }
+
+    // Add relations derived from k8s ownerReferences
+    pub fn add_relations(&mut self, rel: &OwnerRelations) {
+        self.spec.depends_on = merge_refs(self.spec.depends_on.take(), &rel.depends_on);
+        self.spec.dependency_of = merge_refs(self.spec.dependency_of.take(), &rel.dependency_of);
+        if self.spec.system.is_none() {
+            self.spec.system = rel.system.clone();
+        }
+    }
+
+    // Add APIs served through the Services selecting the Deployment
+    pub fn add_provided_apis(&mut self, apis: &[String]) {
+        self.spec.provides_apis = merge_refs(self.spec.provides_apis.take(), apis);
+    }
 }
 
 #[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
@@ -296,7 +333,7 @@ impl Resource {
         //check if StatefulSet
         let bsc = &config.backstage;
         if let Some(ref tp) = obj.types {
-            if tp.kind.to_lowercase() != "statefulset" {
+            if tp.kind != "StatefulSet" {
                 return Err(EntityError{ 
                     kind: BACKSTAGE_ENTITY_RESOURCE.to_owned(),
                     name: obj.name_any().clone(),
@@ -498,7 +535,7 @@ impl Resource {
         // validations
         let bsc = &config.backstage;
         if let Some(ref tp) = obj.types {
-            if tp.kind.to_lowercase() != "pod" {
+            if tp.kind != "Pod" {
                 return Err(EntityError{ 
                     kind: BACKSTAGE_ENTITY_RESOURCE.to_owned(),
                     name: obj.name_any().clone(),
@@ -514,8 +551,13 @@ impl Resource {
         }
 
         let en_ref_prefix = String::from("resource:default");
-        let m = Metadata::from_annotations(bsc,
+        let mut m = Metadata::from_annotations(bsc,
             obj.name_any().clone());
+        if let Some(ns) = obj.metadata.namespace.clone() {
+            let mut anns = m.annotations.clone().unwrap_or_default();
+            anns.insert(BACKSTAGE_ANN_NAMESPACE.to_string(), ns);
+            m.annotations = Some(anns);
+        }
         if m.name.len() == 0 {
             return Err(EntityError{ 
                 kind: BACKSTAGE_ENTITY_RESOURCE.to_owned(),
@@ -555,6 +597,66 @@ impl Resource {
             }
         })
     }
+
+    // Converts any k8s workload (Pod, Job, CronJob, DaemonSet...) to Backstage Resource
+    pub fn from_workload(config: &Settings,
+        obj: &DynamicObject) -> Result<Self, EntityError> {
+        let spec_type = match obj.types {
+            Some(ref tp) => tp.kind.to_lowercase(),
+            None => {
+                return Err(EntityError{ 
+                    kind: BACKSTAGE_ENTITY_RESOURCE.to_owned(),
+                    name: obj.name_any().clone(),
+                    message: "Resource lacks TypeMeta data".to_owned(),
+                });
+            }
+        };
+
+        let mut m = Metadata::from_annotations(&config.backstage,
+            obj.name_any().clone());
+        if m.name.is_empty() {
+            return Err(EntityError{ 
+                kind: BACKSTAGE_ENTITY_RESOURCE.to_owned(),
+                name: obj.name_any().clone(),
+                message: "Resource lacks Metadata name".to_owned(),
+            });
+        }
+
+        let mut anns: HashMap<String, String> = m.annotations.clone().unwrap_or_default();
+        anns.insert(AXYOMCORE_ANN_CLUSTER.into(), config.cluster.clone());
+        if let Some(ns) = obj.namespace() {
+            anns.insert(BACKSTAGE_ANN_NAMESPACE.to_string(), ns);
+        }
+        m.annotations = Some(anns);
+
+        let lbls: HashMap<String, String> = obj.labels()
+            .iter()
+            .map(|(l, v)| (l.clone(), v.clone()))
+            .collect();
+        if !lbls.is_empty() {
+            m.labels = Some(lbls);
+        }
+
+        Ok(Self {
+            api_version: BACKSTAGE_ENTITY_API_VERSION.to_string(),
+            kind: BACKSTAGE_ENTITY_RESOURCE.to_string(),
+            metadata: m,
+            spec: ResourceSpec {
+                r#type: spec_type,
+                owner: BACKSTAGE_DEFAULT_OWNER.to_owned(),
+                ..Default::default()
+            }
+        })
+    }
+
+    // Add relations derived from k8s ownerReferences
+    pub fn add_relations(&mut self, rel: &OwnerRelations) {
+        self.spec.depends_on = merge_refs(self.spec.depends_on.take(), &rel.depends_on);
+        self.spec.dependency_of = merge_refs(self.spec.dependency_of.take(), &rel.dependency_of);
+        if self.spec.system.is_none() {
+            self.spec.system = rel.system.clone();
+        }
+    }
 }
 
 // impl fmt::Display for Resource {
@@ -590,31 +692,21 @@ impl Group {
         Return an empty list if no config is provided.
      */
     pub fn groups_from_config(bsc: BackstageSettings) -> Vec<Self>{
-        let mut res:Vec<Self> = Vec::new();
-
-        for g in bsc.groups.iter() {
-            // let parent = match g.spec.parent.clone() {
-            //     Some(p) => p,
-            //     None => "".to_owned(),
-            // };
-
-            // let children: Vec<String> = match &g.spec.children {
-            //     Some(c) => c.to_vec(),
-            //     None => Vec::new(),
-            // };
+        bsc.groups.iter()
+            .map(|g| Self::from_static(&bsc, g))
+            .collect()
+    }
 
-            let m = Metadata::from_static_config(bsc.clone(),
-                g.metadata.clone());
+    // Add global settings to a Group declared in config or a ConfigMap
+    pub fn from_static(bsc: &BackstageSettings, g: &Group) -> Self {
+        let m = Metadata::from_static_config(bsc.clone(),
+            g.metadata.clone());
 
-            res.push(
-                Self { 
-                    api_version: BACKSTAGE_ENTITY_API_VERSION.to_string(), 
-                    kind: BACKSTAGE_ENTITY_GROUP.to_string(), 
-                    metadata: m, 
-                    spec: g.spec.clone()});
-        }
-        
-        res
+        Self { 
+            api_version: BACKSTAGE_ENTITY_API_VERSION.to_string(), 
+            kind: BACKSTAGE_ENTITY_GROUP.to_string(), 
+            metadata: m, 
+            spec: g.spec.clone()}
     }
 }
 
@@ -631,7 +723,7 @@ pub struct User {
 pub struct UserSpec {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub profile: Option<HashMap<String, String>>,
-    #[serde(rename(serialize = "memberOf"))]
+    #[serde(rename(serialize = "memberOf"), alias = "memberOf")]
     pub member_of: Vec<String>,
 }
 
@@ -641,28 +733,21 @@ impl User {
         Return an empty list if no config is provided.
      */
     pub fn users_from_config(bsc: BackstageSettings) -> Vec<Self>{
-        let mut res:Vec<Self> = Vec::new();
-        
-        for u in bsc.users.iter() {
-            // let member_of: Vec<String> = match &u.spec.member_of {
-            //     Some(m) => m.to_vec(),
-            //     None => Vec::new(),
-            // };
+        bsc.users.iter()
+            .map(|u| Self::from_static(&bsc, u))
+            .collect()
+    }
 
-            let m = Metadata::from_static_config(bsc.clone(),
-                u.metadata.clone());
+    // Add global settings to a User declared in config or a ConfigMap
+    pub fn from_static(bsc: &BackstageSettings, u: &User) -> Self {
+        let m = Metadata::from_static_config(bsc.clone(),
+            u.metadata.clone());
 
-            // let mut mt = u.metadata.clone();
-            res.push(
-                Self { 
-                    api_version: BACKSTAGE_ENTITY_API_VERSION.to_string(), 
-                    kind: BACKSTAGE_ENTITY_USER.to_string(), 
-                    metadata: m, 
-                    spec: u.spec.clone() }
-            );
-        }
-        
-        res
+        Self { 
+            api_version: BACKSTAGE_ENTITY_API_VERSION.to_string(), 
+            kind: BACKSTAGE_ENTITY_USER.to_string(), 
+            metadata: m, 
+            spec: u.spec.clone() }
     }
 }
 
@@ -679,7 +764,7 @@ pub struct Domain {
 pub struct DomainSpec {
     pub owner: String,
     #[serde(skip_serializing_if = "Option::is_none", 
-        rename(serialize = "subdomainOf"))]
+        rename(serialize = "subdomainOf"), alias = "subdomainOf")]
     pub subdomain_of: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub r#type: Option<String>,
@@ -687,23 +772,24 @@ pub struct DomainSpec {
 
 impl Domain {
     pub fn domains_from_config(bsc: BackstageSettings) -> Vec<Self> {
-        let mut domains: Vec<Self> = Vec::new();
-
-        if let Some(ref conf_domains) = bsc.domains {
-            for d in conf_domains.iter() {
-                let m = Metadata::from_static_config(bsc.clone(),
-                    d.metadata.clone());
-                
-                domains.push(
-                    Self { 
-                        api_version: BACKSTAGE_ENTITY_API_VERSION.to_string(), 
-                        kind: BACKSTAGE_ENTITY_DOMAIN.to_string(), 
-                        metadata: m, 
-                        spec: d.spec.clone() }
-                );
-            }
+        match bsc.domains {
+            Some(ref conf_domains) => conf_domains.iter()
+                .map(|d| Self::from_static(&bsc, d))
+                .collect(),
+            None => Vec::new(),
         }
-        domains
+    }
+
+    // Add global settings to a Domain declared in config or a ConfigMap
+    pub fn from_static(bsc: &BackstageSettings, d: &Domain) -> Self {
+        let m = Metadata::from_static_config(bsc.clone(),
+            d.metadata.clone());
+
+        Self { 
+            api_version: BACKSTAGE_ENTITY_API_VERSION.to_string(), 
+            kind: BACKSTAGE_ENTITY_DOMAIN.to_string(), 
+            metadata: m, 
+            spec: d.spec.clone() }
     }
 }
 
@@ -729,7 +815,7 @@ impl System {
     // Creates a System from k8s Redis StatefulSet
     pub fn from_stateful_set(config: &Settings, obj: &DynamicObject) -> Result<Self, EntityError> {
         if let Some(ref tp) = obj.types {
-            if tp.kind.to_lowercase() != "statefulset" {
+            if tp.kind != "StatefulSet" {
                 return Err(EntityError{ 
                     kind: BACKSTAGE_ENTITY_SYSTEM.to_owned(),
                     name: obj.name_any().clone(),
@@ -795,6 +881,194 @@ impl System {
         )
     } 
 }
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+pub struct Api {
+    #[serde(rename(serialize = "apiVersion", deserialize = "apiVersion"))]
+    pub api_version: String,
+    pub kind: String,
+    pub metadata: Metadata,
+    pub spec: ApiSpec,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+pub struct ApiSpec {
+    pub r#type: String,
+    pub lifecycle: String,
+    pub owner: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    pub definition: ApiDefinition,
+}
+
+impl Api {
+    // Creates an API from k8s Service, Ingress or HTTPRoute and the URLs it is reachable at
+    pub fn from_network_object(config: &Settings, 
+        obj: &DynamicObject,
+        urls: Vec<String>,
+        system: Option<String>) -> Result<Self, EntityError> {
+        let k8s_kind = match obj.types {
+            Some(ref tp) => tp.kind.clone(),
+            None => {
+                return Err(EntityError{ 
+                    kind: BACKSTAGE_ENTITY_API.to_owned(),
+                    name: obj.name_any().clone(),
+                    message: "Resource lacks TypeMeta data".to_owned(),
+                });
+            }
+        };
+
+        if urls.is_empty() {
+            return Err(EntityError{ 
+                kind: BACKSTAGE_ENTITY_API.to_owned(),
+                name: obj.name_any().clone(),
+                message: format!("{} exposes no URLs", k8s_kind),
+            });
+        }
+
+        let mut m = Metadata::from_annotations(&config.backstage,
+            api_name(obj));
+        m.title = Some(obj.name_any());
+
+        let mut anns: HashMap<String, String> = m.annotations.clone().unwrap_or_default();
+        anns.insert(AXYOMCORE_ANN_CLUSTER.into(), config.cluster.clone());
+        if let Some(ns) = obj.namespace() {
+            anns.insert(BACKSTAGE_ANN_NAMESPACE.to_string(), ns);
+        }
+        m.annotations = Some(anns);
+
+        m.links = Some(urls.iter()
+            .map(|u| Link {
+                url: u.clone(),
+                title: Some(format!("{} {}", k8s_kind, obj.name_any())),
+                icon: None,
+                r#type: Some("endpoint".to_owned()),
+            })
+            .collect());
+
+        Ok(Self {
+            api_version: BACKSTAGE_ENTITY_API_VERSION.to_string(),
+            kind: BACKSTAGE_ENTITY_API.to_string(),
+            metadata: m,
+            spec: ApiSpec {
+                r#type: "http".to_owned(),
+                lifecycle: String::from("experimental"),
+                owner: BACKSTAGE_DEFAULT_OWNER.to_owned(),
+                system,
+                // the URLs are in the links until a definition is published
+                definition: ApiDefinition::Embedded(format!(
+                    "# no API definition published for {} {}\n", k8s_kind, obj.name_any())),
+            }
+        })
+    }
+
+    // Replace the placeholder with an OpenAPI or AsyncAPI definition
+    pub fn set_definition(&mut self, api_type: ApiType, definition: ApiDefinition) {
+        self.spec.r#type = api_type.as_str().to_owned();
+        self.spec.definition = definition;
+    }
+}
+
+// Backstage entity names: letters and digits separated by [-_.], at most 63 characters
+const BACKSTAGE_NAME_MAX_LEN: usize = 63;
+
+/// Entity declared verbatim, e.g. through a CatalogEntity custom resource.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+pub struct RawEntity {
+    #[serde(rename(serialize = "apiVersion", deserialize = "apiVersion"))]
+    pub api_version: String,
+    pub kind: String,
+    pub metadata: Metadata,
+    #[serde(default)]
+    pub spec: Value,
+}
+
+impl RawEntity {
+    // Validates a raw Backstage entity and adds the global annotations
+    pub fn from_value(bsc: &BackstageSettings, value: Value) -> Result<Self, EntityError> {
+        let name = value.pointer("/metadata/name")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_owned();
+        let kind = value.get("kind")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_owned();
+
+        let mut raw: Self = match serde_json::from_value(value) {
+            Ok(raw) => raw,
+            Err(why) => {
+                return Err(EntityError{ 
+                    kind,
+                    name,
+                    message: format!("invalid entity: {}", why),
+                });
+            }
+        };
+
+        let invalid = |message: String| EntityError{ 
+            kind: raw.kind.clone(),
+            name: raw.metadata.name.clone(),
+            message,
+        };
+
+        if !raw.api_version.contains('/') {
+            return Err(invalid(format!("invalid apiVersion {:?}", raw.api_version)));
+        }
+
+        if raw.kind.is_empty() {
+            return Err(invalid("missing kind".to_owned()));
+        }
+
+        if !is_valid_name(&raw.metadata.name) {
+            return Err(invalid(format!("invalid metadata.name {:?}", raw.metadata.name)));
+        }
+
+        if !raw.spec.is_object() && !raw.spec.is_null() {
+            return Err(invalid("spec must be an object".to_owned()));
+        }
+
+        // global annotations, overridden by the entity's own
+        let mut anns: HashMap<String, String> = bsc.annotations.clone().unwrap_or_default();
+        if let Some(ref en_anns) = raw.metadata.annotations {
+            for (a, v) in en_anns.iter() {
+                anns.insert(a.clone(), v.clone());
+            }
+        }
+        raw.metadata.annotations = Some(anns);
+
+        if raw.metadata.namespace.is_none() {
+            raw.metadata.namespace = Some("default".to_owned());
+        }
+
+        Ok(raw)
+    }
+
+    // Backstage entity reference, e.g. system:default/payments
+    pub fn entity_ref(&self) -> String {
+        self.metadata.entity_ref(&self.kind)
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    if name.is_empty() || name.len() > BACKSTAGE_NAME_MAX_LEN {
+        return false;
+    }
+
+    let mut prev_sep = true;
+    for c in name.chars() {
+        let is_sep = matches!(c, '-' | '_' | '.');
+        if is_sep && prev_sep {
+            return false;
+        }
+        if !is_sep && !c.is_ascii_alphanumeric() {
+            return false;
+        }
+        prev_sep = is_sep;
+    }
+
+    !prev_sep
+}
+
 // common trait for all Entities
 pub trait BackstageEntity {
     // needed for dynamic casting to underlying types
@@ -815,6 +1089,27 @@ impl Serialize for Box<dyn BackstageEntity> {
             state.serialize_field("metadata", &bs_res.metadata)?;
             state.serialize_field("spec", &bs_res.spec)?;
             state.end()
+        } else if let Some(bs_comp) = self.as_any().downcast_ref::<Component>() {
+            let mut state = serializer.serialize_struct("Component", 4)?;
+            state.serialize_field("apiVersion", &bs_comp.api_version)?;
+            state.serialize_field("kind", &bs_comp.kind)?;
+            state.serialize_field("metadata", &bs_comp.metadata)?;
+            state.serialize_field("spec", &bs_comp.spec)?;
+            state.end()
+        } else if let Some(bs_api) = self.as_any().downcast_ref::<Api>() {
+            let mut state = serializer.serialize_struct("API", 4)?;
+            state.serialize_field("apiVersion", &bs_api.api_version)?;
+            state.serialize_field("kind", &bs_api.kind)?;
+            state.serialize_field("metadata", &bs_api.metadata)?;
+            state.serialize_field("spec", &bs_api.spec)?;
+            state.end()
+        } else if let Some(bs_raw) = self.as_any().downcast_ref::<RawEntity>() {
+            let mut state = serializer.serialize_struct("RawEntity", 4)?;
+            state.serialize_field("apiVersion", &bs_raw.api_version)?;
+            state.serialize_field("kind", &bs_raw.kind)?;
+            state.serialize_field("metadata", &bs_raw.metadata)?;
+            state.serialize_field("spec", &bs_raw.spec)?;
+            state.end()
         } else if let Some(bs_gr) = self.as_any().downcast_ref::<Group>() {
             let mut state = serializer.serialize_struct("Group", 4)?;
             state.serialize_field("apiVersion", &bs_gr.api_version)?;
@@ -867,6 +1162,57 @@ impl BackstageEntity for Resource {
     }
 }
 
+impl BackstageEntity for Component {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn entity_type(&self) -> String {
+        String::from("Component")
+    }
+
+    fn bse_to_string(&self) -> String {
+        match serde_json::to_string(&self) {
+            Ok(res) => res,
+            Err(_why) => "".to_owned()
+        }
+    }
+}
+
+impl BackstageEntity for Api {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn entity_type(&self) -> String {
+        String::from("API")
+    }
+
+    fn bse_to_string(&self) -> String {
+        match serde_json::to_string(&self) {
+            Ok(res) => res,
+            Err(_why) => "".to_owned()
+        }
+    }
+}
+
+impl BackstageEntity for RawEntity {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn entity_type(&self) -> String {
+        self.kind.clone()
+    }
+
+    fn bse_to_string(&self) -> String {
+        match serde_json::to_string(&self) {
+            Ok(res) => res,
+            Err(_why) => "".to_owned()
+        }
+    }
+}
+
 impl BackstageEntity for Group {
     fn as_any(&self) -> &dyn Any {
         self
@@ -949,4 +1295,34 @@ impl fmt::Display for EntityError {
             self.name, 
             self.message)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::get_configuration;
+
+    #[test]
+    fn network_api_links_urls_with_placeholder_definition() {
+        let config = get_configuration().unwrap();
+        let service: DynamicObject = serde_json::from_value(serde_json::json!({
+            "apiVersion": "v1",
+            "kind": "Service",
+            "metadata": { "name": "orders", "namespace": "shop" },
+        })).unwrap();
+        let urls = vec!["http://orders.shop:8080".to_owned(), "http://orders.example.com".to_owned()];
+
+        let api = Api::from_network_object(&config, &service, urls.clone(), None).unwrap();
+        let links: Vec<String> = api.metadata.links.unwrap().into_iter().map(|l| l.url).collect();
+        assert_eq!(links, urls);
+        match api.spec.definition {
+            ApiDefinition::Embedded(ref def) => {
+                assert!(def.starts_with('#'));
+                assert!(urls.iter().all(|u| !def.contains(u.as_str())));
+            },
+            ref def => panic!("unexpected definition {:?}", def),
+        }
+
+        let err = Api::from_network_object(&config, &service, Vec::new(), None);
+        assert!(err.is_err());
+    }
+}
@@ -1,103 +1,42 @@
-use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::Instant;
 
-use regex::Regex;
-use kube::core::{TypeMeta, DynamicObject};
+use kube::core::{ApiResource, TypeMeta, DynamicObject};
 use kube::api::ResourceExt;
 use tokio::{
-    sync::mpsc::{Sender, Receiver, channel},
+    sync::Mutex as AsyncMutex,
     time::{self, Duration}
 };
 
-use anyhow::Result;
-use crate::ax_types::Db;
+use crate::ax_types::{CacheEntry, CacheIndex, Db};
 use crate::ax_kube::{
     watch::{EventsChannels, check_objects}, 
-    watch_event::{WatchCommand, WatchEvent}};
+    debounce::Debouncer,
+    watch_event::{WatchCommand, WatchEvent},
+    WatchQueue};
 use crate::configuration::Settings;
-use crate::backstage::{capitalize, format_creation_since};
+use crate::supervisor::Supervisor;
+use crate::backstage::format_creation_since;
+use k8s_openapi::chrono::Utc;
 
 // Cache reported k8s resource 
-//rx_we: Receiver<WatchEvent>,
 pub async fn process_k8s_resources(conf: &Settings, 
                         events_channels: EventsChannels,
-                        cache: Db) -> Result<bool, regex::Error> {
-    let (tx_api, rx_api): (Sender<String>, Receiver<String>) = channel(32);
-    let (tx_type, rx_type): (Sender<Option<TypeMeta>>, Receiver<Option<TypeMeta>>) = channel(32);
-
-    //todo improve error handling and passing
-    let result = match parse_type_meta(rx_api, tx_type).await {
-        Ok(_) => {
-            let _result = process_watch_event(&conf, 
-                                        events_channels, 
-                                        tx_api, 
-                                        rx_type,
-                                        cache).await;
-            true
-        },
-        Err(why) => {
-            tracing::error!("Starting TypeMeta parser failed {:?}", why);
-            false
-        },
-    };
-
-    Ok(result)
+                        cache: Db,
+                        index: CacheIndex,
+                        supervisor: Arc<Supervisor>) -> std::io::Result<()> {
+    process_watch_event(conf, 
+        events_channels, 
+        cache,
+        index,
+        supervisor).await
 }
 
-// Receives a k8s API path and returns a TypeMeta structure.
-// rx(/api/v1/events) -> parse -> sn(TypeMeta{ api_version: v1, kind: Event}) 
-// todo add caching for a given path to avoid repeated regex matching.
-pub async fn parse_type_meta(mut rx: Receiver<String>, 
-                                tx: Sender<Option<TypeMeta>>) -> Result<bool, regex::Error> {
-    let k8s_api_pattern = vec![
-        r"/api/(?<ver>[a-z0-9]*)/(?<resource>[a-zA-Z0-9-]*)s$",
-        r"/api/(?<ver>[a-z0-9]*)/namespaces/(?<ns>[a-zA-Z0-9-]*)/(?<resource>[a-zA-Z0-9]*)s$",
-        r"/apis/(?<apigroup>[a-z0-9\.]*)/(?<ver>[a-z0-9]*)/(?<resource>[a-zA-Z0-9]*)s$",
-        r"/apis/(?<apigroup>[a-z0-9\.]*)/(?<ver>[a-z0-9]*)/namespaces/(?<ns>[a-zA-Z0-9-]*)/(?<resource>[a-zA-Z0-9]*)s$",
-    ];
-        // /apis/apps/v1/namespaces/app-health-5g/deployments
-    let mut k8s_api_rex: Vec<Regex> = Vec::new();
-
-    for p in k8s_api_pattern {
-        match Regex::new(p) {
-            Ok(r) => {
-                k8s_api_rex.push(r);
-            },
-            Err(err) => { 
-                return Err(err)
-            },
-        }
-    }
-
-    tokio::spawn(async move {
-        while let Some(hay) = rx.recv().await {
-            let mut result: Option<TypeMeta> = None;
-
-            'k8sapi: for r in &k8s_api_rex {
-                if let Some(caps) = r.captures(&hay) {
-                    // todo add apigrpup to api_version
-                    let api_version = caps
-                                    .name("apigroup")
-                                    .map_or(caps["ver"].to_string(), 
-                                        |v| format!("{}/{}", v.as_str(), 
-                                                            caps["ver"].to_string()));
-
-                    result = Some(TypeMeta{
-                                // api_version: caps["ver"].to_string(),
-                                api_version,
-                                kind: capitalize(&caps["resource"]),
-                            });          
-                    // skip the remaining patterns
-                    break 'k8sapi;
-                };
-            };
-
-            if let Err(why) = tx.send(result).await{
-                tracing::error!("Failed to send TypeMeta: {:?}", why);
-            };
-        };
-    });
-
-    Ok(true)
+// State of the ingest thread, kept when it is restarted
+struct IngestState {
+    rx_we: WatchQueue,
+    debouncer: Debouncer,
 }
 
 /*
@@ -106,257 +45,366 @@ Process WatchEvents stream
 // mut rx_we: Receiver<WatchEvent>,
 pub async fn process_watch_event(conf: &Settings,
     events_channels: EventsChannels,
-    tx_api: Sender<String>,
-    mut rx_type: Receiver<Option<TypeMeta>>,
-    cache: Db) -> std::io::Result<()> {
-
-    let mut rx_we = events_channels.rx;
-    let tx_poll = events_channels.tx.clone();
-    let tx_purge = events_channels.tx.clone();
-    let mut ipoll = time::interval(Duration::from_secs(conf.cache.poll_interval)); 
-    let mut ipurge = time::interval(Duration::from_secs(conf.cache.purge_cache_interval)); 
+    cache: Db,
+    index: CacheIndex,
+    supervisor: Arc<Supervisor>) -> std::io::Result<()> {
+
+    let tx_purge = events_channels.queue.clone();
+    let purge_interval = Duration::from_secs(conf.cache.purge_cache_interval); 
     let conf2 = conf.clone();
+    let state = Arc::new(AsyncMutex::new(IngestState {
+        rx_we: events_channels.queue,
+        debouncer: Debouncer::new(Duration::from_millis(conf.cache.debounce_ms)),
+    }));
+
     // ingest thread
-    tokio::spawn(async move {  
-        // println!("{0:<20} {1:<20} {2:<20} {3:<5} {4:<width$}", "KIND", "NAMESPACE", "AGE", "K8S", "NAME", width = 63);
-        while let Some(we) = rx_we.recv().await {
-            match we.command {
-                WatchCommand::Add(obj) | WatchCommand::Update(obj) => {
-                    let obj_to_add = match process_dynobj(obj.clone(),
-                                            we.resource_url.clone(),
-                                            tx_api.clone(),
-                                            &mut rx_type).await {
-                        Ok(obj) => obj,
-                        Err(why) => {
-                            tracing::error!("processing dynobj failed: {:?}", why);
-                            continue
+    supervisor.spawn("ingest", supervisor.token(), move |token| {  
+        let state = state.clone();
+        let cache = cache.clone();
+        let index = index.clone();
+        let conf2 = conf2.clone();
+
+        async move {
+            let mut guard = state.lock().await;
+            let IngestState { rx_we, debouncer } = &mut *guard;
+            let mut draining = false;
+            loop {
+                let next_deadline = debouncer.next_deadline();
+                let we = tokio::select! {
+                    _ = token.cancelled(), if !draining => {
+                        // stop accepting events and process the ones already queued
+                        rx_we.close();
+                        draining = true;
+                        continue;
+                    },
+                    _ = time::sleep_until(next_deadline.unwrap_or_else(Instant::now).into()), if next_deadline.is_some() => {
+                        match debouncer.pop_due(Instant::now()) {
+                            Some(we) => we,
+                            None => continue,
                         }
-                    };
-
-                    let name = obj_to_add.name_any().clone();
-                    let ns = match obj_to_add.metadata.namespace.clone() {
-                        Some(ref namespace) => namespace.to_string(),
-                        None => "none".to_string(),
-                    };
-                
-                    let tm_kind = match obj_to_add.types {
-                        Some(ref tm) => tm.kind.clone(),
-                        None => "none".to_owned(),
-                    };
-
-                    let age = format_creation_since(obj_to_add.creation_timestamp());
-                    let key = &format!("{}/{}", ns, name);
-                    let mut db = cache.lock().unwrap();
-                    // insert or update DynamicObject in the cash
-                    db.insert(key.to_string(), obj_to_add);
-
-                    println!(" >> DB ins {0:<20} {1:<20} {2:<20} {3:<5} {4:<width$}", 
-                                tm_kind, 
-                                ns.clone(), 
-                                age, 
-                                we.k8s_version,
-                                name, 
-                                width = 80);
-                },
-                WatchCommand::Delete(obj) => {
-                    let name = obj.name_any().clone();
-                    let ns = match obj.metadata.namespace {
-                        Some(ref namespace) => namespace.to_string(),
-                        None => "none".to_string(),
-                    };
-                
-                    let tm_kind = match obj.types {
-                        Some(ref tm) => tm.kind.clone(),
-                        None => "none".to_owned(),
-                    };
-
-                    let age = format_creation_since(obj.creation_timestamp());
-
-                    let mut db = cache.lock().unwrap();
-                    let key = &format!("{}/{}", ns, name);
-                    db.remove(key);
+                    },
+                    we = rx_we.recv() => match we {
+                        Some(we) => match debouncer.push(we) {
+                            Some(we) => we,
+                            None => continue,
+                        },
+                        // flush the held events before stopping
+                        None => match debouncer.pop() {
+                            Some(we) => we,
+                            None => break,
+                        },
+                    },
+                };
 
-                    println!(" >> DB del {0:<20} {1:<20} {2:<20} {3:<5} {4:<width$}", 
-                                        tm_kind, 
-                                        ns.clone(), 
-                                        age, 
-                                        we.k8s_version,
-                                        name, 
-                                        width = 80);
-                },
-                WatchCommand::Purge => {
-                    let mut db: BTreeMap<String, DynamicObject> = BTreeMap::new();
-                    cache.lock().unwrap().clone_into(&mut db);
-                    let mut check_objs: Vec<DynamicObject> = Vec::new();
-                    for (_, obj) in db.iter(){
-                        check_objs.push(obj.clone());
-                    }
-                    
-                    // find inactive objects
-                    let objs = match check_objects(check_objs, &conf2).await {
-                        Ok(objs) => objs,
-                        Err(_)=> vec![],
-                    };
-
-                    let mut db = cache.lock().unwrap();
-                    for obj in objs.iter() {
-                        let name = obj.name_any().clone();
-                        let ns = match obj.metadata.namespace {
-                            Some(ref namespace) => namespace.to_string(),
-                            None => "none".to_string(),
+                match we.command {
+                    WatchCommand::Purge if draining => {
+                        tracing::debug!("Skipping cache purge on shutdown");
+                    },
+                    WatchCommand::Purge => {
+                        // objects with the resource they were watched from
+                        let check_objs: Vec<(DynamicObject, ApiResource)> = {
+                            let db = cache.lock().unwrap();
+                            let index = index.lock().unwrap();
+                            db.iter()
+                                .filter_map(|(key, obj)| {
+                                    let ar = index.get(key)?.api_resource.clone()?;
+                                    Some((obj.clone(), ar))
+                                })
+                                .collect()
                         };
                     
-                        let tm_kind = match obj.types {
-                            Some(ref tm) => tm.kind.clone(),
-                            None => "none".to_owned(),
+                        // find inactive objects
+                        let objs = match check_objects(check_objs, &conf2).await {
+                            Ok(objs) => objs,
+                            Err(why) => {
+                                tracing::error!("failed to check the cached objects, none purged: {:?}", why);
+                                vec![]
+                            },
                         };
 
-                        let age = format_creation_since(obj.creation_timestamp());
-
-                        
-                        let key = &format!("{}/{}", ns, name);
+                        let mut db = cache.lock().unwrap();
+                        for obj in objs.iter() {
+                            let name = obj.name_any().clone();
+                            let ns = match obj.metadata.namespace {
+                                Some(ref namespace) => namespace.to_string(),
+                                None => "none".to_string(),
+                            };
                     
-                        db.remove(key);
-
-                        println!(" >> DB purge {0:<20} {1:<20} {2:<20} {3:<5} {4:<width$}", 
-                                            tm_kind, 
-                                            ns.clone(), 
-                                            age, 
-                                            we.k8s_version,
-                                            name, 
-                                            width = 80);
-                    }
-                },
-                WatchCommand::PrintAll => {
-                    println!("\n>> Printing Cache DynamicObjects");
-                    let db = cache.lock().unwrap();
-                    for (_, obj) in db.iter() {
-                        let name = obj.name_any();
-                        let namespace = match obj.namespace() {
-                            Some(ns) => ns,
-                            None => "unknown".to_string(),
-                        };
-                        let kind = match obj.types {
-                            Some(ref tp) => tp.kind.clone(),
-                            None => "none".to_owned(),
-                        };
+                            let tm_kind = match obj.types {
+                                Some(ref tm) => tm.kind.clone(),
+                                None => "none".to_owned(),
+                            };
+
+                            let age = format_creation_since(obj.creation_timestamp());
 
-                        println!(">> kind: {0:<20} name: {1:<40} ns: {2:}",
-                            kind,
-                            name,
-                            namespace);
-                    }
-                    println!("\n");
-                },
-                WatchCommand::None => {
-                    tracing::debug!("No OPS");
-                },
+                            let key = &cache_key(obj);
+                            db.remove(key);
+                            index.lock().unwrap().remove(key);
+
+                            tracing::debug!("purged {} {}/{}, age {}, k8s {}", tm_kind, ns, name, age, we.k8s_version);
+                        }
+                    },
+                    WatchCommand::None => {
+                        tracing::debug!("No OPS");
+                    },
+                    _ => cache_event(&cache, &index, we),
+                }
             }
+
+            tracing::info!("Ingest stopped with {} cached objects, {} events coalesced",
+                cache.lock().unwrap().len(),
+                debouncer.coalesced());
         }
     });
     
-    // print in regular intervals the contents of the cache
-    tokio::spawn(async move {
-        loop {
-            tokio::select!{
-                _ = async {
-                    ipoll.tick().await;
-                }=>{
-                    let _res = tx_poll.send(WatchEvent { 
-                            command: WatchCommand::PrintAll,
+    // purge the cache in regular intervals
+    supervisor.spawn("cache purge timer", supervisor.token(), move |token| {
+        let tx_purge = tx_purge.clone();
+
+        async move {
+            let mut ipurge = time::interval(purge_interval);
+            loop {
+                tokio::select!{
+                    _ = token.cancelled() => break,
+                    _ = async {
+                        ipurge.tick().await;
+                    }=>{
+                        let _res = tx_purge.send(WatchEvent { 
+                            command: WatchCommand::Purge,
                             ..WatchEvent::default()
                         }).await;
-                }           
+                    }           
+                }
             }
         }
     });
 
-    // purge the cache in regular intervals
-    tokio::spawn(async move {
-        loop {
-            tokio::select!{
-                _ = async {
-                    ipurge.tick().await;
-                }=>{
-                    let _res = tx_purge.send(WatchEvent { 
-                        command: WatchCommand::Purge,
-                        ..WatchEvent::default()
-                    }).await;
-                }           
+    Ok(())
+}
+
+// Cache the object of an Add, Update or Delete, or forget the objects of a
+// stopped watch. An object is kept as long as a watch still reports it.
+fn cache_event(cache: &Db, index: &CacheIndex, we: WatchEvent) {
+    match we.command {
+        WatchCommand::Add(obj) | WatchCommand::Update(obj) => {
+            let obj_to_add = process_dynobj(obj, we.api_resource.as_ref());
+
+            let name = obj_to_add.name_any().clone();
+            let ns = match obj_to_add.metadata.namespace.clone() {
+                Some(ref namespace) => namespace.to_string(),
+                None => "none".to_string(),
+            };
+
+            let tm_kind = match obj_to_add.types {
+                Some(ref tm) => tm.kind.clone(),
+                None => "none".to_owned(),
+            };
+
+            let age = format_creation_since(obj_to_add.creation_timestamp());
+            let key = &cache_key(&obj_to_add);
+            let mut db = cache.lock().unwrap();
+            // insert or update DynamicObject in the cash
+            db.insert(key.to_string(), obj_to_add);
+            let mut index = index.lock().unwrap();
+            let entry = index.entry(key.to_string()).or_insert_with(|| CacheEntry {
+                resource_url: String::new(),
+                watches: BTreeSet::new(),
+                event_type: String::new(),
+                cached_at: Utc::now(),
+                api_resource: None,
+            });
+            entry.resource_url = we.resource_url.clone();
+            entry.watches.insert(we.watch_id.clone());
+            entry.event_type = we.event_type.clone();
+            entry.cached_at = Utc::now();
+            entry.api_resource = we.api_resource.clone();
+
+            tracing::debug!("cached {} {}/{}, age {}, k8s {}", tm_kind, ns, name, age, we.k8s_version);
+        },
+        WatchCommand::Delete(obj) => {
+            let obj = process_dynobj(obj, we.api_resource.as_ref());
+            let name = obj.name_any().clone();
+            let ns = match obj.metadata.namespace {
+                Some(ref namespace) => namespace.to_string(),
+                None => "none".to_string(),
+            };
+
+            let tm_kind = match obj.types {
+                Some(ref tm) => tm.kind.clone(),
+                None => "none".to_owned(),
+            };
+
+            let age = format_creation_since(obj.creation_timestamp());
+
+            // the object may still be reported by another watch of the resource_url,
+            // e.g. when it no longer matches the label selector of this one
+            let mut db = cache.lock().unwrap();
+            let mut index = index.lock().unwrap();
+            let key = &cache_key(&obj);
+            let watched = index.get_mut(key).is_some_and(|entry| {
+                entry.watches.remove(&we.watch_id);
+                !entry.watches.is_empty()
+            });
+            if !watched {
+                db.remove(key);
+                index.remove(key);
             }
-        }
-    });
 
-    Ok(())
+            tracing::debug!("deleted {} {}/{}, age {}, k8s {}", tm_kind, ns, name, age, we.k8s_version);
+        },
+        WatchCommand::Forget => {
+            let mut db = cache.lock().unwrap();
+            let before = db.len();
+            index.lock().unwrap().retain(|key, entry| {
+                if entry.watches.remove(&we.watch_id) && entry.watches.is_empty() {
+                    db.remove(key);
+                    false
+                } else {
+                    true
+                }
+            });
+
+            tracing::info!("Forgot {} cached objects of {}",
+                before - db.len(),
+                we.watch_id);
+        },
+        _ => {},
+    }
 }
 
-// Process the watched DynamicObject before caching
-async fn process_dynobj(obj: DynamicObject, 
-    res_url: String,
-    tx_api: Sender<String>,
-    rx_type: &mut Receiver<Option<TypeMeta>>) -> Result<DynamicObject> {
-
-    let mut obj_with_type: DynamicObject = if let Some(_type_meta) = &obj.types {
-                        dbg!(&obj.types);
-                        DynamicObject {
-                            types: obj.types,
-                            metadata: obj.metadata,
-                            data: obj.data,
-                        }
-                    }else{ 
-                        let types = match tx_api.send(res_url.clone()).await{
-                            Ok(_) => { 
-                                if let Some(res) = rx_type.recv().await {
-                                    if let Some(tm) = res {
-                                        Some(tm)
-                                    }else{
-                                        None
-                                    }
-                                }else{
-                                    None
-                                }
-                            },        
-                            Err(why) => {
-                                tracing::error!("Failed extracting k8s type from URL: {:?}", why);
-                                return Err(why.into())
-                            },
-                        };
+// Process the watched DynamicObject before caching, the TypeMeta is the one of
+// the discovered resource as list responses leave it out
+fn process_dynobj(mut obj: DynamicObject, api_resource: Option<&ApiResource>) -> DynamicObject {
+    if let Some(ar) = api_resource {
+        obj.types = Some(TypeMeta {
+            api_version: ar.api_version.clone(),
+            kind: ar.kind.clone(),
+        });
+    }
+    trim_object(&mut obj);
 
-                        // Some(tm.kind.clone())
-                        DynamicObject {
-                            types,
-                            metadata: obj.metadata,
-                            data: obj.data,
-                        }
-                    };
-    obj_with_type.
-            annotations_mut().
-            remove("kubectl.kubernetes.io/last-applied-configuration");
+    obj
+}
 
-    obj_with_type.managed_fields_mut().clear();
-    
-     Ok(obj_with_type)
+// Drop the bulky metadata which is of no use for the entities
+pub fn trim_object(obj: &mut DynamicObject) {
+    obj.
+        annotations_mut().
+        remove("kubectl.kubernetes.io/last-applied-configuration");
+
+    obj.managed_fields_mut().clear();
 }
 
-// print to stdout the contents of the cache
-async fn _print_cache_db(cache: &Db) {
-    println!("\n>> Printing Cache DynamicObjects");
-    let db = cache.lock().unwrap();
-    for (_, obj) in db.iter() {
-        let name = obj.name_any();
-        let namespace = match obj.namespace() {
-            Some(ns) => ns,
-            None => "unknown".to_string(),
-        };
-        let kind = match obj.types {
-            Some(ref tp) => tp.kind.clone(),
-            None => "none".to_owned(),
+// Key of the object in the cache, group/kind/namespace/name as objects of
+// different kinds, e.g. a Service and its Deployment, usually share the name.
+// The core group is spelled core, cluster scoped objects are in namespace none.
+pub fn cache_key(obj: &DynamicObject) -> String {
+    let (group, kind) = match obj.types {
+        Some(ref tm) => (
+            tm.api_version.rsplit_once('/').map(|(group, _)| group).unwrap_or("core"),
+            tm.kind.as_str(),
+        ),
+        None => ("none", "none"),
+    };
+    let ns = match obj.metadata.namespace {
+        Some(ref namespace) => namespace.as_str(),
+        None => "none",
+    };
+
+    format!("{}/{}/{}/{}", group, kind, ns, obj.name_any())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(api_version: &str, kind: &str, namespace: Option<&str>, name: &str) -> DynamicObject {
+        serde_json::from_value(serde_json::json!({
+            "apiVersion": api_version,
+            "kind": kind,
+            "metadata": { "name": name, "namespace": namespace },
+        })).unwrap()
+    }
+
+    #[test]
+    fn cache_key_separates_kinds_of_same_name() {
+        let service = object("v1", "Service", Some("shop"), "orders");
+        let deployment = object("apps/v1", "Deployment", Some("shop"), "orders");
+        let ingress = object("networking.k8s.io/v1", "Ingress", Some("shop"), "orders");
+        let namespace = object("v1", "Namespace", None, "shop");
+
+        assert_eq!(cache_key(&service), "core/Service/shop/orders");
+        assert_eq!(cache_key(&deployment), "apps/Deployment/shop/orders");
+        assert_eq!(cache_key(&ingress), "networking.k8s.io/Ingress/shop/orders");
+        assert_eq!(cache_key(&namespace), "core/Namespace/none/shop");
+    }
+
+    #[test]
+    fn process_dynobj_sets_type_of_resource() {
+        let mut obj = object("v1", "Service", Some("shop"), "orders");
+        obj.types = None;
+        let ar = ApiResource {
+            group: "".to_owned(),
+            version: "v1".to_owned(),
+            api_version: "v1".to_owned(),
+            kind: "Service".to_owned(),
+            plural: "services".to_owned(),
         };
 
-        println!(">> kind: {0:<20} name: {1:<40} ns: {2:}",
-            kind,
-            name,
-            namespace);
+        let obj = process_dynobj(obj, Some(&ar));
+        assert_eq!(cache_key(&obj), "core/Service/shop/orders");
+    }
+
+    fn event(watch: u32, command: fn(DynamicObject) -> WatchCommand, name: &str) -> WatchEvent {
+        WatchEvent {
+            resource_url: "/api/v1/namespaces/shop/pods".to_owned(),
+            watch_id: format!("/api/v1/namespaces/shop/pods#{}", watch),
+            command: command(object("v1", "Pod", Some("shop"), name)),
+            ..WatchEvent::default()
+        }
+    }
+
+    fn forget(watch: u32) -> WatchEvent {
+        WatchEvent {
+            watch_id: format!("/api/v1/namespaces/shop/pods#{}", watch),
+            command: WatchCommand::Forget,
+            ..WatchEvent::default()
+        }
+    }
+
+    fn cached(cache: &Db, index: &CacheIndex) -> Vec<String> {
+        let keys: Vec<String> = cache.lock().unwrap().keys().cloned().collect();
+        assert_eq!(keys, index.lock().unwrap().keys().cloned().collect::<Vec<_>>());
+        keys.iter().map(|k| k.rsplit('/').next().unwrap().to_owned()).collect()
+    }
+
+    #[test]
+    fn forget_keeps_objects_of_watches_sharing_the_url() {
+        let (cache, index) = (Db::default(), CacheIndex::default());
+        // watches of the same resource_url with other label selectors
+        cache_event(&cache, &index, event(0, WatchCommand::Add, "redis-0"));
+        cache_event(&cache, &index, event(1, WatchCommand::Add, "web-0"));
+        cache_event(&cache, &index, event(0, WatchCommand::Add, "both-0"));
+        cache_event(&cache, &index, event(1, WatchCommand::Update, "both-0"));
+
+        cache_event(&cache, &index, forget(0));
+        assert_eq!(cached(&cache, &index), ["both-0", "web-0"]);
+
+        cache_event(&cache, &index, forget(1));
+        assert!(cached(&cache, &index).is_empty());
+    }
+
+    #[test]
+    fn delete_drops_objects_no_watch_reports() {
+        let (cache, index) = (Db::default(), CacheIndex::default());
+        cache_event(&cache, &index, event(0, WatchCommand::Add, "both-0"));
+        cache_event(&cache, &index, event(1, WatchCommand::Add, "both-0"));
+
+        // no longer matches the selectors of watch 0
+        cache_event(&cache, &index, event(0, WatchCommand::Delete, "both-0"));
+        assert_eq!(cached(&cache, &index), ["both-0"]);
+
+        cache_event(&cache, &index, event(1, WatchCommand::Delete, "both-0"));
+        assert!(cached(&cache, &index).is_empty());
     }
-    println!("");
 }
@@ -1,22 +1,21 @@
 pub mod ingest;
 pub mod entities;
+pub mod owners;
+pub mod apis;
+pub mod definitions;
+pub mod org;
+pub mod catalog;
+pub mod query;
 
 use k8s_openapi::{
     apimachinery::pkg::apis::meta::v1::Time,
     chrono::{Duration, Utc},
 };
 
-/// Capitalizes the first character in s.
-pub fn capitalize(s: &str) -> String {
-    let mut c = s.chars();
-    match c.next() {
-        None => String::new(),
-        Some(f) => f.to_uppercase().collect::<String>() + c.as_str(),
-    }
-}
-
+// Age of an object, empty when it has no creationTimestamp
 pub fn format_creation_since(time: Option<Time>) -> String {
-    format_duration(Utc::now().signed_duration_since(time.unwrap().0))
+    time.map(|t| format_duration(Utc::now().signed_duration_since(t.0)))
+        .unwrap_or_default()
 }
 
 fn format_duration(dur: Duration) -> String {
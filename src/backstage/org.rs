@@ -0,0 +1,148 @@
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::backstage::entities::{Domain, EntityError, Group, User};
+use crate::configuration::BackstageSettings;
+
+/// Groups, Users and Domains describing the organization
+#[derive(Debug, Clone, Default)]
+pub struct OrgEntities {
+    pub groups: Vec<Group>,
+    pub users: Vec<User>,
+    pub domains: Vec<Domain>,
+}
+
+impl OrgEntities {
+    /// Parses org entities from YAML or JSON documents, e.g. a ConfigMap value.
+    ///
+    /// A document holds a single entity or a list of entities. Entities are
+    /// dispatched on their `kind`, any kind other than Group, User or Domain
+    /// is rejected.
+    pub fn from_documents(bsc: &BackstageSettings, text: &str) -> Result<Self, EntityError> {
+        let mut org = Self::default();
+
+        for doc in serde_yaml::Deserializer::from_str(text) {
+            let value = Value::deserialize(doc).map_err(|why| EntityError{
+                kind: "".to_owned(),
+                name: "".to_owned(),
+                message: format!("invalid YAML document: {}", why),
+            })?;
+
+            let items = match value {
+                Value::Array(items) => items,
+                Value::Null => continue,
+                item => vec![item],
+            };
+
+            for item in items {
+                org.push(bsc, item)?;
+            }
+        }
+
+        Ok(org)
+    }
+
+    /// Appends entities of another source
+    pub fn extend(&mut self, other: OrgEntities) {
+        self.groups.extend(other.groups);
+        self.users.extend(other.users);
+        self.domains.extend(other.domains);
+    }
+
+    /// Returns true if an entity with the given kind and name is declared
+    pub fn contains(&self, kind: &str, name: &str) -> bool {
+        match kind {
+            "Group" => self.groups.iter().any(|g| g.metadata.name == name),
+            "User" => self.users.iter().any(|u| u.metadata.name == name),
+            "Domain" => self.domains.iter().any(|d| d.metadata.name == name),
+            _ => false,
+        }
+    }
+
+    fn push(&mut self, bsc: &BackstageSettings, item: Value) -> Result<(), EntityError> {
+        let kind = item.get("kind").and_then(Value::as_str).unwrap_or_default().to_owned();
+        let name = item.pointer("/metadata/name").and_then(Value::as_str).unwrap_or_default().to_owned();
+        let invalid = |why: serde_json::Error| EntityError{
+            kind: kind.clone(),
+            name: name.clone(),
+            message: why.to_string(),
+        };
+
+        match kind.as_str() {
+            "Group" => {
+                let g: Group = serde_json::from_value(item).map_err(invalid)?;
+                self.groups.push(Group::from_static(bsc, &g));
+            },
+            "User" => {
+                let u: User = serde_json::from_value(item).map_err(invalid)?;
+                self.users.push(User::from_static(bsc, &u));
+            },
+            "Domain" => {
+                let d: Domain = serde_json::from_value(item).map_err(invalid)?;
+                self.domains.push(Domain::from_static(bsc, &d));
+            },
+            _ => {
+                return Err(EntityError{
+                    kind,
+                    name,
+                    message: "only Group, User and Domain entities are supported".to_owned(),
+                });
+            },
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::configuration::get_configuration;
+
+    const ORG: &str = r#"
+- apiVersion: backstage.io/v1alpha1
+  kind: Group
+  metadata:
+    name: team-a
+  spec:
+    type: team
+    children: []
+- apiVersion: backstage.io/v1alpha1
+  kind: User
+  metadata:
+    name: jdoe
+  spec:
+    memberOf: [team-a]
+---
+{"apiVersion": "backstage.io/v1alpha1", "kind": "Domain", "metadata": {"name": "payments"}, "spec": {"owner": "team-a"}}
+---
+"#;
+
+    #[test]
+    fn parses_lists_and_single_entities() {
+        let bsc = get_configuration().unwrap().backstage;
+        let org = OrgEntities::from_documents(&bsc, ORG).unwrap();
+
+        assert_eq!((org.groups.len(), org.users.len(), org.domains.len()), (1, 1, 1));
+        assert!(org.contains("Group", "team-a"));
+        assert!(org.contains("User", "jdoe"));
+        assert!(org.contains("Domain", "payments"));
+        assert!(!org.contains("Component", "payments"));
+        assert_eq!(org.users[0].spec.member_of, vec!["team-a"]);
+    }
+
+    #[test]
+    fn rejects_bad_documents() {
+        let bsc = get_configuration().unwrap().backstage;
+
+        let why = OrgEntities::from_documents(&bsc, "kind: Group\nmetadata: [").unwrap_err();
+        assert!(why.message.starts_with("invalid YAML document"), "{}", why.message);
+
+        // a Domain needs an owner
+        let why = OrgEntities::from_documents(&bsc, "kind: Domain\napiVersion: backstage.io/v1alpha1\nmetadata: {name: payments}\nspec: {}").unwrap_err();
+        assert_eq!((why.kind.as_str(), why.name.as_str()), ("Domain", "payments"));
+
+        let why = OrgEntities::from_documents(&bsc, "kind: Component\nmetadata: {name: orders}").unwrap_err();
+        assert_eq!(why.message, "only Group, User and Domain entities are supported");
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::{BTreeMap, HashMap};
+
+use kube::{core::DynamicObject, ResourceExt};
+
+// k8s label which names the higher level application a workload is part of
+const K8S_LABEL_PART_OF: &str = "app.kubernetes.io/part-of";
+// label added by the Deployment controller to its ReplicaSets and Pods
+const K8S_LABEL_POD_TEMPLATE_HASH: &str = "pod-template-hash";
+
+/// Relations of a single entity derived from the k8s controller hierarchy.
+#[derive(Debug, Default, Clone)]
+pub struct OwnerRelations {
+    /// Entities created by the k8s controllers this entity maps to
+    pub depends_on: Vec<String>,
+    /// Entity of the k8s controller owning this entity
+    pub dependency_of: Vec<String>,
+    /// System the top level controller is part of
+    pub system: Option<String>,
+}
+
+/// Index of cached DynamicObjects by their `metadata.ownerReferences`.
+///
+/// Controllers that don't map to a Backstage entity, like ReplicaSets, are
+/// collapsed so that Pods are linked directly to their Deployment.
+pub struct OwnerGraph<'a> {
+    by_uid: HashMap<String, &'a DynamicObject>,
+    by_name: HashMap<(String, String, String), &'a DynamicObject>,
+    children: HashMap<String, Vec<&'a DynamicObject>>,
+}
+
+impl<'a> OwnerGraph<'a> {
+    pub fn new(db: &'a BTreeMap<String, DynamicObject>) -> Self {
+        let mut graph = Self {
+            by_uid: HashMap::new(),
+            by_name: HashMap::new(),
+            children: HashMap::new(),
+        };
+
+        for obj in db.values() {
+            if let Some(uid) = obj.uid() {
+                graph.by_uid.insert(uid, obj);
+            }
+            graph.by_name.insert(
+                (kind_of(obj), obj.namespace().unwrap_or_default(), obj.name_any()),
+                obj);
+        }
+
+        for obj in db.values() {
+            if is_collapsed(&kind_of(obj)) {
+                continue;
+            }
+
+            if let Some(owner) = graph.owner_of(obj) {
+                if let Some(uid) = owner.uid() {
+                    graph.children.entry(uid).or_default().push(obj);
+                }
+            }
+        }
+
+        graph
+    }
+
+    /// Returns the closest cached owner of obj which maps to a Backstage entity.
+    pub fn owner_of(&self, obj: &DynamicObject) -> Option<&'a DynamicObject> {
+        let owner_ref = obj.owner_references()
+            .iter()
+            .find(|r| r.controller == Some(true))
+            .or_else(|| obj.owner_references().first())?;
+
+        match self.by_uid.get(&owner_ref.uid) {
+            Some(owner) if is_collapsed(&kind_of(owner)) => self.owner_of(owner),
+            Some(owner) => Some(*owner),
+            None => {
+                // ReplicaSets are rarely watched. Infer the Deployment from
+                // the ReplicaSet name, which is <deployment>-<pod-template-hash>.
+                if !is_collapsed(&owner_ref.kind.to_lowercase()) {
+                    return None;
+                }
+
+                let hash = obj.labels().get(K8S_LABEL_POD_TEMPLATE_HASH)?;
+                let deployment = owner_ref.name.strip_suffix(&format!("-{}", hash))?;
+                self.by_name
+                    .get(&("deployment".to_owned(),
+                        obj.namespace().unwrap_or_default(),
+                        deployment.to_owned()))
+                    .copied()
+            }
+        }
+    }
+
+    /// Returns dependsOn, dependencyOf and partOf relations for the entity of obj.
+    pub fn relations_for(&self, obj: &DynamicObject) -> OwnerRelations {
+        let mut rel = OwnerRelations::default();
+
+        if let Some(owner_ref) = self.owner_of(obj).and_then(entity_ref) {
+            rel.dependency_of.push(owner_ref);
+        }
+
+        if let Some(uid) = obj.uid() {
+            if let Some(children) = self.children.get(&uid) {
+                rel.depends_on = children.iter().filter_map(|c| entity_ref(c)).collect();
+                rel.depends_on.sort();
+            }
+        }
+
+        // walk up to the top level controller, guarding against ownership cycles
+        let mut top = obj;
+        for _ in 0..self.by_uid.len() {
+            match self.owner_of(top) {
+                Some(owner) => top = owner,
+                None => break,
+            }
+        }
+        rel.system = top.labels().get(K8S_LABEL_PART_OF).cloned();
+
+        rel
+    }
+}
+
+/// Backstage entity reference of the entity created for a k8s object, none
+/// for kinds which are not converted to an entity.
+pub fn entity_ref(obj: &DynamicObject) -> Option<String> {
+    match kind_of(obj).as_str() {
+        "deployment" => Some(format!("component:default/{}", obj.name_any())),
+        "statefulset" | "pod" | "daemonset" | "job" | "cronjob" => {
+            Some(format!("resource:default/{}", obj.name_any()))
+        },
+        _ => None,
+    }
+}
+
+/// Merges relations into an existing list, skipping duplicates.
+pub fn merge_refs(current: Option<Vec<String>>, refs: &[String]) -> Option<Vec<String>> {
+    let mut merged = current.unwrap_or_default();
+    for r in refs {
+        if !merged.contains(r) {
+            merged.push(r.clone());
+        }
+    }
+
+    if merged.is_empty() {
+        None
+    } else {
+        Some(merged)
+    }
+}
+
+/// Lowercase k8s kind of a cached object, empty if TypeMeta is missing.
+pub fn kind_of(obj: &DynamicObject) -> String {
+    match obj.types {
+        Some(ref tp) => tp.kind.to_lowercase(),
+        None => "".to_owned(),
+    }
+}
+
+// controllers without a Backstage entity of their own
+fn is_collapsed(kind: &str) -> bool {
+    kind == "replicaset"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn object(api_version: &str, kind: &str, name: &str, uid: &str,
+        labels: serde_json::Value, owner: Option<(&str, &str, &str)>) -> DynamicObject {
+        let owners: Vec<_> = owner.into_iter()
+            .map(|(kind, name, uid)| json!({
+                "apiVersion": "apps/v1", "kind": kind, "name": name, "uid": uid, "controller": true,
+            }))
+            .collect();
+        serde_json::from_value(json!({
+            "apiVersion": api_version,
+            "kind": kind,
+            "metadata": {
+                "name": name,
+                "namespace": "shop",
+                "uid": uid,
+                "labels": labels,
+                "ownerReferences": owners,
+            },
+        })).unwrap()
+    }
+
+    fn db(objs: Vec<DynamicObject>) -> BTreeMap<String, DynamicObject> {
+        objs.into_iter().map(|o| (format!("{}/{}", kind_of(&o), o.name_any()), o)).collect()
+    }
+
+    #[test]
+    fn replicasets_are_collapsed() {
+        let db = db(vec![
+            object("apps/v1", "Deployment", "orders", "d1", json!({ K8S_LABEL_PART_OF: "shop" }), None),
+            object("apps/v1", "ReplicaSet", "orders-5d8f", "r1", json!({}), Some(("Deployment", "orders", "d1"))),
+            object("v1", "Pod", "orders-5d8f-x2x", "p1", json!({}), Some(("ReplicaSet", "orders-5d8f", "r1"))),
+        ]);
+        let graph = OwnerGraph::new(&db);
+
+        let pod = graph.relations_for(&db["pod/orders-5d8f-x2x"]);
+        assert_eq!(pod.dependency_of, ["component:default/orders"]);
+        assert_eq!(pod.system.as_deref(), Some("shop"));
+
+        let deployment = graph.relations_for(&db["deployment/orders"]);
+        assert_eq!(deployment.depends_on, ["resource:default/orders-5d8f-x2x"]);
+        assert!(deployment.dependency_of.is_empty());
+    }
+
+    #[test]
+    fn deployment_is_inferred_from_pod_template_hash() {
+        let db = db(vec![
+            object("apps/v1", "Deployment", "orders", "d1", json!({}), None),
+            object("v1", "Pod", "orders-5d8f-x2x", "p1", json!({ K8S_LABEL_POD_TEMPLATE_HASH: "5d8f" }),
+                Some(("ReplicaSet", "orders-5d8f", "r1"))),
+            object("v1", "Pod", "billing-5d8f-x2x", "p2", json!({ K8S_LABEL_POD_TEMPLATE_HASH: "77aa" }),
+                Some(("ReplicaSet", "billing-5d8f", "r2"))),
+        ]);
+        let graph = OwnerGraph::new(&db);
+
+        assert_eq!(graph.owner_of(&db["pod/orders-5d8f-x2x"]).map(|o| o.name_any()).as_deref(), Some("orders"));
+        assert!(graph.owner_of(&db["pod/billing-5d8f-x2x"]).is_none());
+    }
+
+    #[test]
+    fn owners_without_entity_are_skipped() {
+        let db = db(vec![
+            object("redis.acme.com/v1", "RedisCluster", "orders", "c1", json!({ K8S_LABEL_PART_OF: "shop" }), None),
+            object("apps/v1", "StatefulSet", "orders-0", "s1", json!({}), Some(("RedisCluster", "orders", "c1"))),
+        ]);
+        let graph = OwnerGraph::new(&db);
+
+        let sts = graph.relations_for(&db["statefulset/orders-0"]);
+        assert!(sts.dependency_of.is_empty());
+        assert_eq!(sts.system.as_deref(), Some("shop"));
+        assert_eq!(entity_ref(&db["statefulset/orders-0"]).as_deref(), Some("resource:default/orders-0"));
+    }
+}
@@ -0,0 +1,331 @@
+use serde::Deserialize;
+use serde_aux::field_attributes::deserialize_option_number_from_string;
+use serde_json::{Map, Value};
+
+use crate::backstage::entities::BackstageEntity;
+use crate::errors::ServerError;
+
+// Largest page of entities
+const MAX_LIMIT: usize = 10_000;
+// Annotation with the k8s namespace of the objects an entity was derived from
+const K8S_NAMESPACE_ANNOTATION: &str = "backstage.io/kubernetes-namespace";
+
+/// Query of the entity set, evaluated against the serialized entities.
+///
+/// All given conditions have to match. `namespace` is the k8s namespace of the
+/// `backstage.io/kubernetes-namespace` annotation, `label` a comma separated list of
+/// `key`, `!key`, `key=value` and `key!=value` requirements like a k8s label
+/// selector, `fields` a comma separated list of dotted paths like
+/// `kind,metadata.name,spec.owner` to return instead of the whole entity.
+#[derive(Deserialize, Debug, Default, Clone)]
+pub struct EntityQuery {
+    pub kind: Option<String>,
+    pub namespace: Option<String>,
+    pub system: Option<String>,
+    pub owner: Option<String>,
+    pub label: Option<String>,
+    pub fields: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_option_number_from_string")]
+    pub limit: Option<usize>,
+    /// Opaque position returned as the next cursor of the previous page
+    pub cursor: Option<String>,
+}
+
+/// Entities matching a query, and the cursor of the next page if any
+#[derive(Debug, Default)]
+pub struct EntityPage {
+    pub entities: Vec<Value>,
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum LabelRequirement {
+    Exists(String),
+    NotExists(String),
+    Equals(String, String),
+    NotEquals(String, String),
+}
+
+impl LabelRequirement {
+    fn parse(s: &str) -> Result<Self, ServerError> {
+        let s = s.trim();
+        let req = if let Some((k, v)) = s.split_once("!=") {
+            LabelRequirement::NotEquals(k.trim().to_owned(), v.trim().to_owned())
+        } else if let Some((k, v)) = s.split_once("==").or_else(|| s.split_once('=')) {
+            LabelRequirement::Equals(k.trim().to_owned(), v.trim().to_owned())
+        } else if let Some(k) = s.strip_prefix('!') {
+            LabelRequirement::NotExists(k.trim().to_owned())
+        } else {
+            LabelRequirement::Exists(s.to_owned())
+        };
+
+        match req {
+            LabelRequirement::Exists(ref k)
+            | LabelRequirement::NotExists(ref k)
+            | LabelRequirement::Equals(ref k, _)
+            | LabelRequirement::NotEquals(ref k, _) if k.is_empty() => {
+                Err(ServerError::validation(format!("invalid label selector {:?}", s)))
+            },
+            req => Ok(req),
+        }
+    }
+
+    fn matches(&self, labels: Option<&Map<String, Value>>) -> bool {
+        let get = |k: &str| labels.and_then(|l| l.get(k)).and_then(Value::as_str);
+        match self {
+            LabelRequirement::Exists(k) => get(k).is_some(),
+            LabelRequirement::NotExists(k) => get(k).is_none(),
+            LabelRequirement::Equals(k, v) => get(k) == Some(v.as_str()),
+            LabelRequirement::NotEquals(k, v) => get(k) != Some(v.as_str()),
+        }
+    }
+}
+
+impl EntityQuery {
+    /// Filter, order and page the entities.
+    ///
+    /// Entities are ordered by their reference when paging, so that cursors stay
+    /// valid while entities come and go. Entities sharing a reference are told
+    /// apart by their position among them, e.g. `resource:default/redis#1`.
+    pub fn apply(&self, entities: &[Box<dyn BackstageEntity>]) -> Result<EntityPage, ServerError> {
+        let labels = match self.label.as_deref() {
+            Some(sel) => sel.split(',')
+                .filter(|s| !s.trim().is_empty())
+                .map(LabelRequirement::parse)
+                .collect::<Result<Vec<_>, _>>()?,
+            None => Vec::new(),
+        };
+        let limit = match self.limit {
+            Some(0) => return Err(ServerError::validation("limit has to be greater than 0")),
+            Some(limit) => Some(limit.min(MAX_LIMIT)),
+            None => None,
+        };
+        let cursor = self.cursor.as_deref().map(parse_cursor).transpose()?;
+        let paged = limit.is_some() || cursor.is_some();
+
+        let mut matching = Vec::new();
+        for entity in entities {
+            let value = serde_json::to_value(entity)
+                .map_err(|why| ServerError::serialization(why.to_string()))?;
+            if self.matches(&value, &labels) {
+                matching.push((entity_ref(&value), value));
+            }
+        }
+
+        let mut keyed: Vec<((String, usize), Value)> = Vec::with_capacity(matching.len());
+        if paged {
+            // the serialized entity orders those sharing a reference
+            matching.sort_by_cached_key(|(key, value)| (key.clone(), value.to_string()));
+            for (key, value) in matching {
+                let nth = match keyed.last() {
+                    Some(((last, n), _)) if *last == key => n + 1,
+                    _ => 0,
+                };
+                keyed.push(((key, nth), value));
+            }
+        } else {
+            keyed.extend(matching.into_iter().map(|(key, value)| ((key, 0), value)));
+        }
+        if let Some(ref cursor) = cursor {
+            keyed.retain(|((key, nth), _)| (key.as_str(), *nth) > (cursor.0, cursor.1));
+        }
+
+        let mut next_cursor = None;
+        if let Some(limit) = limit {
+            if keyed.len() > limit {
+                keyed.truncate(limit);
+                next_cursor = keyed.last().map(|((key, nth), _)| format!("{}#{}", key, nth));
+            }
+        }
+
+        let fields: Vec<&str> = self.fields.as_deref()
+            .map(|f| f.split(',').map(str::trim).filter(|f| !f.is_empty()).collect())
+            .unwrap_or_default();
+
+        Ok(EntityPage {
+            entities: keyed.into_iter()
+                .map(|(_, value)| if fields.is_empty() { value } else { project(&value, &fields) })
+                .collect(),
+            next_cursor,
+        })
+    }
+
+    fn matches(&self, entity: &Value, labels: &[LabelRequirement]) -> bool {
+        if let Some(ref kind) = self.kind {
+            if !entity["kind"].as_str().is_some_and(|k| k.eq_ignore_ascii_case(kind)) {
+                return false;
+            }
+        }
+        if let Some(ref namespace) = self.namespace {
+            if entity["metadata"]["annotations"][K8S_NAMESPACE_ANNOTATION].as_str() != Some(namespace.as_str()) {
+                return false;
+            }
+        }
+        if let Some(ref system) = self.system {
+            if !entity["spec"]["system"].as_str().is_some_and(|s| ref_matches(s, system)) {
+                return false;
+            }
+        }
+        if let Some(ref owner) = self.owner {
+            if !entity["spec"]["owner"].as_str().is_some_and(|o| ref_matches(o, owner)) {
+                return false;
+            }
+        }
+
+        let entity_labels = entity["metadata"]["labels"].as_object();
+        labels.iter().all(|req| req.matches(entity_labels))
+    }
+}
+
+// Position of a cursor, the reference and the position among the entities
+// sharing it
+fn parse_cursor(cursor: &str) -> Result<(&str, usize), ServerError> {
+    cursor.rsplit_once('#')
+        .and_then(|(key, nth)| Some((key, nth.parse().ok()?)))
+        .ok_or_else(|| ServerError::validation(format!("invalid cursor {:?}", cursor)))
+}
+
+// Namespace of an entity, Backstage defaults it to "default"
+fn namespace_of(entity: &Value) -> &str {
+    entity["metadata"]["namespace"].as_str().unwrap_or("default")
+}
+
+/// Backstage entity reference of a serialized entity, e.g. system:default/payments.
+/// It is also the order of the entities when paging.
+pub fn entity_ref(entity: &Value) -> String {
+    format!("{}:{}/{}",
+        entity["kind"].as_str().unwrap_or_default().to_lowercase(),
+        namespace_of(entity),
+        entity["metadata"]["name"].as_str().unwrap_or_default())
+}
+
+// Whether an entity reference like group:default/team-a is the wanted one,
+// either of them given in full or by name
+fn ref_matches(entity_ref: &str, wanted: &str) -> bool {
+    if entity_ref.eq_ignore_ascii_case(wanted) {
+        return true;
+    }
+    let name_of = |r: &str| r.rsplit(['/', ':']).next().unwrap_or(r).to_owned();
+    let is_name = |r: &str| !r.contains(['/', ':']);
+    (is_name(wanted) || is_name(entity_ref)) && name_of(entity_ref).eq_ignore_ascii_case(&name_of(wanted))
+}
+
+// Copy of the entity with only the given dotted paths
+fn project(entity: &Value, fields: &[&str]) -> Value {
+    let mut out = Value::Object(Map::new());
+    for field in fields {
+        let path: Vec<&str> = field.split('.').collect();
+        let Some(value) = path.iter().try_fold(entity, |v, key| v.get(key)) else {
+            continue;
+        };
+
+        // overlapping fields like spec and spec.owner yield the whole spec
+        let mut target = Some(&mut out);
+        for key in &path[..path.len() - 1] {
+            target = target
+                .and_then(Value::as_object_mut)
+                .map(|obj| obj.entry(key.to_string()).or_insert_with(|| Value::Object(Map::new())));
+        }
+        if let Some(obj) = target.and_then(Value::as_object_mut) {
+            obj.insert(path[path.len() - 1].to_owned(), value.clone());
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backstage::entities::Resource;
+    use serde_json::json;
+
+    fn resource(name: &str, namespace: &str, system: &str, labels: Value) -> Box<dyn BackstageEntity> {
+        let res: Resource = serde_json::from_value(json!({
+            "apiVersion": "backstage.io/v1alpha1",
+            "kind": "Resource",
+            "metadata": {
+                "name": name,
+                "namespace": "default",
+                "labels": labels,
+                "annotations": { K8S_NAMESPACE_ANNOTATION: namespace },
+            },
+            "spec": { "type": "redis-cluster-node", "owner": "platform", "system": system },
+        })).unwrap();
+        Box::new(res)
+    }
+
+    fn names(page: &EntityPage) -> Vec<&str> {
+        page.entities.iter().map(|e| e["metadata"]["name"].as_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn filters_by_k8s_namespace_system_and_labels() {
+        let entities = vec![
+            resource("orders-0", "shop", "orders", json!({ "app": "redis", "tier": "cache" })),
+            resource("orders-1", "shop", "orders", json!({ "app": "redis" })),
+            resource("billing-0", "billing", "billing", json!({ "app": "redis", "canary": "true" })),
+        ];
+
+        let query = EntityQuery { namespace: Some("shop".into()), ..Default::default() };
+        assert_eq!(names(&query.apply(&entities).unwrap()), ["orders-0", "orders-1"]);
+
+        let query = EntityQuery { namespace: Some("default".into()), ..Default::default() };
+        assert!(query.apply(&entities).unwrap().entities.is_empty());
+
+        let query = EntityQuery {
+            kind: Some("RESOURCE".into()),
+            system: Some("system:default/orders".into()),
+            label: Some("app=redis,tier!=cache,!canary".into()),
+            ..Default::default()
+        };
+        assert_eq!(names(&query.apply(&entities).unwrap()), ["orders-1"]);
+
+        let query = EntityQuery { label: Some("=redis".into()), ..Default::default() };
+        assert!(query.apply(&entities).is_err());
+    }
+
+    #[test]
+    fn projects_fields() {
+        let entities = vec![resource("orders-0", "shop", "orders", json!({}))];
+        let query = EntityQuery { fields: Some("kind, metadata.name,spec.owner,spec.missing".into()), ..Default::default() };
+
+        let page = query.apply(&entities).unwrap();
+        assert_eq!(page.entities, [json!({
+            "kind": "Resource",
+            "metadata": { "name": "orders-0" },
+            "spec": { "owner": "platform" },
+        })]);
+    }
+
+    #[test]
+    fn pages_over_entities_sharing_a_reference() {
+        let entities = vec![
+            resource("redis", "shop", "orders", json!({})),
+            resource("redis", "billing", "billing", json!({})),
+            resource("a", "shop", "orders", json!({})),
+            resource("redis", "stock", "stock", json!({})),
+        ];
+
+        let mut seen = Vec::new();
+        let mut query = EntityQuery { limit: Some(1), ..Default::default() };
+        loop {
+            let page = query.apply(&entities).unwrap();
+            assert!(page.entities.len() <= 1);
+            seen.extend(page.entities.iter().map(|e| {
+                e["metadata"]["annotations"][K8S_NAMESPACE_ANNOTATION].as_str().unwrap().to_owned()
+            }));
+            match page.next_cursor {
+                Some(cursor) => query.cursor = Some(cursor),
+                None => break,
+            }
+        }
+        assert_eq!(seen, ["shop", "billing", "shop", "stock"]);
+
+        query.cursor = Some("resource:default/redis".into());
+        assert!(query.apply(&entities).is_err());
+        query.limit = Some(0);
+        query.cursor = None;
+        assert!(query.apply(&entities).is_err());
+    }
+}
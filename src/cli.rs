@@ -0,0 +1,152 @@
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+use kube::api::DynamicObject;
+
+use crate::ax_kube::manifests::load_manifests;
+use crate::ax_kube::snapshot::{discover_resources, list_objects};
+use crate::backstage::catalog;
+use crate::backstage::definitions::{ApiDefinition, ApiType, DefinitionCache};
+use crate::backstage::entities;
+use crate::backstage::org::OrgEntities;
+use crate::configuration::{get_configuration, Settings};
+
+/// Backstage Entity Provider for Kubernetes
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Directory of base.yaml and the environment files [default: ./config]
+    #[arg(long, global = true)]
+    pub config_dir: Option<PathBuf>,
+
+    /// Runtime environment, local or production [default: $APP_ENVIRONMENT or local]
+    #[arg(long, global = true)]
+    pub environment: Option<String>,
+
+    /// Log filter, overridden by RUST_LOG
+    #[arg(long, global = true, default_value = "info")]
+    pub log_level: String,
+
+    /// Path of the kubeconfig file
+    #[arg(long, global = true)]
+    pub kubeconfig: Option<PathBuf>,
+
+    /// kubeconfig context to connect with
+    #[arg(long, global = true)]
+    pub context: Option<String>,
+}
+
+#[derive(Subcommand, Debug, Clone, Default, PartialEq, Eq)]
+pub enum Command {
+    /// Watch the cluster and serve entities over HTTP (default)
+    #[default]
+    Serve,
+    /// Validate the configuration and exit
+    ValidateConfig,
+    /// Print the entities of the current cluster as catalog-info.yaml and exit
+    Render {
+        /// Render k8s manifest files or directories instead of the cluster, - for stdin
+        #[arg(short = 'f', long = "filename")]
+        manifests: Vec<PathBuf>,
+    },
+    /// List the resources resolvable through API discovery and exit
+    Discover,
+}
+
+impl Cli {
+    /// Selected subcommand, serve if none
+    pub fn command(&self) -> Command {
+        self.command.clone().unwrap_or_default()
+    }
+
+    /// Pass the flags on as environment variables, so that they apply to
+    /// configuration reloads as well. Has to be called before any thread starts.
+    pub fn export_env(&self) {
+        if let Some(ref dir) = self.config_dir {
+            std::env::set_var("APP_CONFIG_DIR", dir);
+        }
+        if let Some(ref environment) = self.environment {
+            std::env::set_var("APP_ENVIRONMENT", environment);
+        }
+        if let Some(ref kubeconfig) = self.kubeconfig {
+            std::env::set_var("APP_KUBE_KUBECONFIG", kubeconfig);
+        }
+        if let Some(ref context) = self.context {
+            std::env::set_var("APP_KUBE_CONTEXT", context);
+        }
+    }
+}
+
+// validate-config - Loads and validates the configuration
+pub fn validate_config() -> Result<()> {
+    let config = get_configuration()?;
+    println!("configuration of {} is valid: {} resources, {} groups",
+        config.name,
+        config.kube.resources.len(),
+        config.backstage.groups.len());
+    Ok(())
+}
+
+// render - Lists the configured resources once, or reads them from manifests,
+//         and prints their entities. API definitions are fetched from the cluster only.
+pub async fn render(config: &Settings, manifests: &[PathBuf]) -> Result<()> {
+    let (objects, api_defs) = if manifests.is_empty() {
+        let objects = list_objects(config).await?;
+        let definitions = DefinitionCache::new(Duration::ZERO);
+        let api_defs = catalog::api_definitions(config,
+            catalog::definition_sources(&objects),
+            &definitions).await;
+        (objects, api_defs)
+    } else {
+        (load_manifests(manifests)?, HashMap::new())
+    };
+    tracing::info!("Rendering entities of {} k8s objects", objects.len());
+
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(render_catalog(config, &objects, &api_defs)?.as_bytes())?;
+    stdout.flush()?;
+    Ok(())
+}
+
+// render_catalog - Renders the entities of the objects and of the configuration
+//         as a multi-document catalog-info.yaml.
+pub fn render_catalog(config: &Settings,
+    objects: &BTreeMap<String, DynamicObject>,
+    api_defs: &HashMap<String, (ApiType, ApiDefinition)>) -> Result<String> {
+    let mut res = catalog::cluster_entities(config, objects, api_defs);
+    res.extend(catalog::org_entities(
+        &entities::Group::groups_from_config(config.backstage.clone()),
+        &entities::User::users_from_config(config.backstage.clone()),
+        &entities::Domain::domains_from_config(config.backstage.clone()),
+        OrgEntities::default()));
+
+    catalog::to_yaml_documents(&res)
+}
+
+// discover - Prints the resources served by the cluster
+pub async fn discover(config: &Settings) -> Result<()> {
+    let resources = discover_resources(config).await?;
+
+    let mut stdout = std::io::stdout().lock();
+    writeln!(stdout, "{0:<40} {1:<10} {2:<40} {3:<10} {4:<9} {5:<10}",
+        "GROUP", "VERSION", "KIND", "NAMESPACED", "WATCHABLE", "CONFIGURED")?;
+    for r in resources {
+        let group = if r.group.is_empty() { "core".to_owned() } else { r.group };
+        writeln!(stdout, "{0:<40} {1:<10} {2:<40} {3:<10} {4:<9} {5:<10}",
+            group,
+            r.version,
+            r.kind,
+            r.namespaced,
+            r.watchable,
+            r.configured)?;
+    }
+
+    Ok(())
+}
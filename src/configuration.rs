@@ -1,10 +1,12 @@
 use serde_aux::field_attributes::deserialize_number_from_string;
 use std::convert::{TryFrom, TryInto};
 use std::collections::HashMap;
+use std::path::PathBuf;
 use url::Url;
 use anyhow::Context;
 use crate::backstage::entities;
 use crate::errors::{ConfigError, Result};
+use crate::middleware::rate_limit::IpNetwork;
 #[derive(serde::Deserialize, Debug, Clone)]
 pub struct Settings {
     // agent name
@@ -17,6 +19,8 @@ pub struct Settings {
     pub nats: NatsProxy,
     pub kube: KubeSettings,
     pub cache: Cache,
+    #[serde(default)]
+    pub reload: ReloadSettings,
 }
 
 impl Settings {
@@ -52,6 +56,9 @@ impl Settings {
         // Validate cache settings
         self.cache.validate()?;
 
+        // Validate reload settings
+        self.reload.validate()?;
+
         Ok(())
     }
 }
@@ -63,6 +70,38 @@ pub struct Cache {
     pub poll_interval: u64,
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub purge_cache_interval: u64,
+    /// What watches do when the event queue of def_channel_size is full
+    #[serde(default)]
+    pub queue_policy: QueuePolicy,
+    /// Milliseconds an object's Add and Update events are held so that only the
+    /// latest is ingested, 0 ingests every event
+    #[serde(deserialize_with = "deserialize_number_from_string", default)]
+    pub debounce_ms: u64,
+}
+
+/// Policy of the watch event queue when ingestion falls behind
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum QueuePolicy {
+    /// Watches wait until there is room, nothing is lost
+    #[default]
+    Block,
+    /// The oldest queued Add or Update is dropped, the next relist or purge repairs the cache
+    DropOldest,
+    /// An event replaces the queued event of the same object queued after the
+    /// last Purge or Forget, watches wait when full
+    Coalesce,
+}
+
+impl std::fmt::Display for QueuePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            QueuePolicy::Block => "block",
+            QueuePolicy::DropOldest => "drop_oldest",
+            QueuePolicy::Coalesce => "coalesce",
+        };
+        write!(f, "{}", name)
+    }
 }
 
 impl Cache {
@@ -96,6 +135,49 @@ impl Cache {
     }
 }
 
+/// Configuration hot reload settings
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct ReloadSettings {
+    /// Whether to watch the configuration files for changes
+    #[serde(default = "default_reload_enabled")]
+    pub enabled: bool,
+
+    /// Interval in seconds between checks of the configuration files
+    #[serde(deserialize_with = "deserialize_number_from_string", default = "default_reload_interval")]
+    pub interval: u64,
+}
+
+fn default_reload_enabled() -> bool {
+    true
+}
+
+fn default_reload_interval() -> u64 {
+    10 // 10 seconds
+}
+
+impl Default for ReloadSettings {
+    fn default() -> Self {
+        Self {
+            enabled: default_reload_enabled(),
+            interval: default_reload_interval(),
+        }
+    }
+}
+
+impl ReloadSettings {
+    /// Validate reload settings
+    pub fn validate(&self) -> std::result::Result<(), ConfigError> {
+        if self.enabled && self.interval == 0 {
+            return Err(ConfigError::invalid(
+                "reload.interval",
+                "0".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
 #[derive(serde::Deserialize, Debug, Clone)]
 pub struct NatsProxy {
     pub proxy_url: String
@@ -134,18 +216,32 @@ pub struct RateLimitSettings {
     /// Whether to enable rate limiting
     #[serde(default = "default_rate_limit_enabled")]
     pub enabled: bool,
+
+    /// Addresses or CIDR ranges of the proxies whose X-Forwarded-For is trusted
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+
+    /// Paths which are not rate limited
+    #[serde(default = "default_rate_limit_exempt_paths")]
+    pub exempt_paths: Vec<String>,
 }
 
 fn default_rate_limit_enabled() -> bool {
     true
 }
 
+fn default_rate_limit_exempt_paths() -> Vec<String> {
+    vec!["/healthz".to_string()]
+}
+
 impl Default for RateLimitSettings {
     fn default() -> Self {
         Self {
             requests_per_second: 100,
             burst_size: 200,
             enabled: true,
+            trusted_proxies: Vec::new(),
+            exempt_paths: default_rate_limit_exempt_paths(),
         }
     }
 }
@@ -244,6 +340,279 @@ pub struct ServerSettings {
     /// Whether to enable request ID tracking
     #[serde(default = "default_request_id_enabled")]
     pub enable_request_id: bool,
+
+    /// Authentication of the clients and access policy of the routes
+    #[serde(default)]
+    pub auth: AuthSettings,
+
+    /// HTTPS and client certificate configuration
+    #[serde(default)]
+    pub tls: TlsSettings,
+}
+
+/// HTTPS of the server, e.g. with a certificate of cert-manager mounted from a Secret.
+///
+/// The certificate and key are reloaded when the files change, new connections
+/// use the rotated certificate without a restart.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct TlsSettings {
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// PEM certificate chain, e.g. /etc/tls/tls.crt
+    #[serde(default)]
+    pub cert_file: String,
+
+    /// PEM private key, e.g. /etc/tls/tls.key
+    #[serde(default)]
+    pub key_file: String,
+
+    /// PEM bundle of the CAs of client certificates. Clients without a
+    /// certificate signed by one of them are rejected when set.
+    #[serde(default)]
+    pub client_ca_file: Option<String>,
+
+    /// Seconds between checks of the files for changes
+    #[serde(deserialize_with = "deserialize_number_from_string", default = "default_tls_reload_interval")]
+    pub reload_interval_secs: u64,
+}
+
+fn default_tls_reload_interval() -> u64 {
+    30
+}
+
+impl Default for TlsSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cert_file: String::new(),
+            key_file: String::new(),
+            client_ca_file: None,
+            reload_interval_secs: default_tls_reload_interval(),
+        }
+    }
+}
+
+impl TlsSettings {
+    /// Validate TLS settings
+    pub fn validate(&self) -> std::result::Result<(), ConfigError> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        if self.cert_file.is_empty() {
+            return Err(ConfigError::missing("server.tls.cert_file"));
+        }
+
+        if self.key_file.is_empty() {
+            return Err(ConfigError::missing("server.tls.key_file"));
+        }
+
+        if self.client_ca_file.as_ref().is_some_and(|f| f.is_empty()) {
+            return Err(ConfigError::missing("server.tls.client_ca_file"));
+        }
+
+        if self.reload_interval_secs == 0 {
+            return Err(ConfigError::invalid(
+                "server.tls.reload_interval_secs",
+                "0".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Role granted to an authenticated client
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// May read entities and status
+    #[default]
+    Reader,
+    /// May also use the admin endpoints
+    Admin,
+}
+
+/// Authentication of the API clients and the access policy of the routes.
+///
+/// Public paths are always anonymous and admin paths always require the admin
+/// role. All other routes require authentication only when enabled. Bearer
+/// tokens are checked against the static tokens, the JWKS and the k8s TokenReview
+/// API in that order.
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct AuthSettings {
+    /// Whether routes other than the public ones require authentication
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Paths which never require authentication
+    #[serde(default = "default_public_paths")]
+    pub public_paths: Vec<String>,
+
+    /// Paths, and the paths below them, which require the admin role
+    #[serde(default = "default_admin_paths")]
+    pub admin_paths: Vec<String>,
+
+    /// Static bearer tokens read from files or environment variables
+    #[serde(default)]
+    pub static_tokens: Vec<StaticTokenSettings>,
+
+    /// Validation of k8s service account tokens
+    #[serde(default)]
+    pub token_review: TokenReviewSettings,
+
+    /// Validation of JWTs signed by keys of a JWKS file
+    #[serde(default)]
+    pub jwt: Option<JwtSettings>,
+}
+
+fn default_public_paths() -> Vec<String> {
+    vec!["/healthz".to_string()]
+}
+
+fn default_admin_paths() -> Vec<String> {
+    vec!["/api/v1/cache".to_string()]
+}
+
+impl Default for AuthSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            public_paths: default_public_paths(),
+            admin_paths: default_admin_paths(),
+            static_tokens: Vec::new(),
+            token_review: TokenReviewSettings::default(),
+            jwt: None,
+        }
+    }
+}
+
+impl AuthSettings {
+    /// Validate auth settings
+    pub fn validate(&self) -> std::result::Result<(), ConfigError> {
+        for token in self.static_tokens.iter() {
+            if token.name.is_empty() {
+                return Err(ConfigError::missing("server.auth.static_tokens.name"));
+            }
+            if token.token_file.is_some() == token.token_env.is_some() {
+                return Err(ConfigError::invalid(
+                    "server.auth.static_tokens",
+                    format!("{} needs either token_file or token_env", token.name),
+                ));
+            }
+        }
+
+        if self.token_review.enabled && self.token_review.cache_ttl_secs == 0 {
+            return Err(ConfigError::invalid(
+                "server.auth.token_review.cache_ttl_secs",
+                "0".to_string(),
+            ));
+        }
+
+        if let Some(ref jwt) = self.jwt {
+            if jwt.jwks_file.is_empty() {
+                return Err(ConfigError::missing("server.auth.jwt.jwks_file"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Bearer token of a client, the file is read on every request so that a
+/// rotated Secret applies without a restart
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct StaticTokenSettings {
+    /// Name of the client in the logs
+    pub name: String,
+    #[serde(default)]
+    pub token_file: Option<String>,
+    /// Environment variable holding the token
+    #[serde(default)]
+    pub token_env: Option<String>,
+    #[serde(default)]
+    pub role: Role,
+}
+
+impl StaticTokenSettings {
+    /// Current token, none if the file or variable is missing or empty
+    pub fn token(&self) -> Option<String> {
+        let token = match (&self.token_file, &self.token_env) {
+            (Some(path), _) => std::fs::read_to_string(path).ok()?,
+            (None, Some(var)) => std::env::var(var).ok()?,
+            (None, None) => return None,
+        };
+
+        Some(token.trim().to_owned()).filter(|t| !t.is_empty())
+    }
+}
+
+/// Service account tokens validated with the TokenReview API
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct TokenReviewSettings {
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Audiences the tokens have to be issued for, the API server's by default
+    #[serde(default)]
+    pub audiences: Vec<String>,
+
+    /// Users granted the reader role, e.g. system:serviceaccount:backstage:backstage.
+    /// Any authenticated user when empty.
+    #[serde(default)]
+    pub readers: Vec<String>,
+
+    /// Users granted the admin role
+    #[serde(default)]
+    pub admins: Vec<String>,
+
+    /// Seconds the outcome of a review is reused
+    #[serde(deserialize_with = "deserialize_number_from_string", default = "default_token_review_cache_ttl")]
+    pub cache_ttl_secs: u64,
+}
+
+fn default_token_review_cache_ttl() -> u64 {
+    60
+}
+
+impl Default for TokenReviewSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            audiences: Vec::new(),
+            readers: Vec::new(),
+            admins: Vec::new(),
+            cache_ttl_secs: default_token_review_cache_ttl(),
+        }
+    }
+}
+
+/// JWTs signed by one of the keys of a JWKS file, e.g. issued by an OIDC provider
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct JwtSettings {
+    /// Path of the JSON Web Key Set, reloaded when it changes
+    pub jwks_file: String,
+    /// Required iss claim
+    #[serde(default)]
+    pub issuer: Option<String>,
+    /// Required aud claim
+    #[serde(default)]
+    pub audience: Option<String>,
+    /// Claim listing the roles of the subject
+    #[serde(default = "default_jwt_roles_claim")]
+    pub roles_claim: String,
+    /// Role of the roles claim granting the admin role
+    #[serde(default = "default_jwt_admin_role")]
+    pub admin_role: String,
+}
+
+fn default_jwt_roles_claim() -> String {
+    "roles".to_string()
+}
+
+fn default_jwt_admin_role() -> String {
+    "admin".to_string()
 }
 
 fn default_request_timeout() -> u64 {
@@ -293,8 +662,20 @@ impl ServerSettings {
                     "0".to_string(),
                 ));
             }
+
+            for proxy in self.rate_limit.trusted_proxies.iter() {
+                if IpNetwork::parse(proxy).is_none() {
+                    return Err(ConfigError::invalid(
+                        "server.rate_limit.trusted_proxies",
+                        proxy.clone(),
+                    ));
+                }
+            }
         }
 
+        self.auth.validate()?;
+        self.tls.validate()?;
+
         // Validate CORS settings
         if self.cors.enabled && !self.cors.allow_all_origins && self.cors.allowed_origins.is_empty() {
             return Err(ConfigError::invalid(
@@ -303,6 +684,34 @@ impl ServerSettings {
             ));
         }
 
+        // browsers refuse credentialed responses with Access-Control-Allow-Origin: *
+        if self.cors.enabled && self.cors.allow_all_origins && self.cors.allow_credentials {
+            return Err(ConfigError::invalid(
+                "server.cors.allow_credentials",
+                "Credentials can't be allowed with allow_all_origins, list the allowed_origins instead".to_string(),
+            ));
+        }
+
+        for origin in self.cors.allowed_origins.iter() {
+            let valid = Url::parse(origin)
+                .is_ok_and(|u| u.has_host() && u.path() == "/" && !origin.ends_with('/'));
+            if !valid {
+                return Err(ConfigError::invalid("server.cors.allowed_origins", origin.clone()));
+            }
+        }
+
+        for method in self.cors.allowed_methods.iter() {
+            if http::Method::from_bytes(method.as_bytes()).is_err() {
+                return Err(ConfigError::invalid("server.cors.allowed_methods", method.clone()));
+            }
+        }
+
+        for header in self.cors.allowed_headers.iter() {
+            if http::HeaderName::from_bytes(header.as_bytes()).is_err() {
+                return Err(ConfigError::invalid("server.cors.allowed_headers", header.clone()));
+            }
+        }
+
         Ok(())
     }
 }
@@ -313,7 +722,48 @@ pub struct BackstageSettings {
     pub annotations: Option<HashMap<String, String>>,
     pub groups: Vec<entities::Group>,
     pub users: Vec<entities::User>,
-    pub domains: Option<Vec<entities::Domain>>
+    pub domains: Option<Vec<entities::Domain>>,
+    /// OpenAPI/AsyncAPI definitions discovery for API entities
+    #[serde(default)]
+    pub api_definitions: ApiDefinitionSettings,
+}
+
+/// Settings for embedding API definitions served by annotated Services
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct ApiDefinitionSettings {
+    /// Whether to fetch and embed definitions, otherwise emit `$text` URLs
+    #[serde(default = "default_api_definitions_embed")]
+    pub embed: bool,
+
+    /// Largest definition in bytes which is embedded
+    #[serde(deserialize_with = "deserialize_number_from_string", default = "default_api_definitions_max_size")]
+    pub max_size_bytes: usize,
+
+    /// Seconds a single definition may take to fetch, the `$text` URL is used after
+    #[serde(deserialize_with = "deserialize_number_from_string", default = "default_api_definitions_fetch_timeout")]
+    pub fetch_timeout_secs: u64,
+}
+
+fn default_api_definitions_embed() -> bool {
+    true
+}
+
+fn default_api_definitions_max_size() -> usize {
+    1024 * 1024 // 1 MiB
+}
+
+fn default_api_definitions_fetch_timeout() -> u64 {
+    5 // 5 seconds
+}
+
+impl Default for ApiDefinitionSettings {
+    fn default() -> Self {
+        Self {
+            embed: default_api_definitions_embed(),
+            max_size_bytes: default_api_definitions_max_size(),
+            fetch_timeout_secs: default_api_definitions_fetch_timeout(),
+        }
+    }
 }
 
 impl BackstageSettings {
@@ -332,6 +782,21 @@ impl BackstageSettings {
             ));
         }
 
+        // Validate API definitions size limit
+        if self.api_definitions.max_size_bytes == 0 {
+            return Err(ConfigError::invalid(
+                "backstage.api_definitions.max_size_bytes",
+                "0".to_string(),
+            ));
+        }
+
+        if self.api_definitions.fetch_timeout_secs == 0 {
+            return Err(ConfigError::invalid(
+                "backstage.api_definitions.fetch_timeout_secs",
+                "0".to_string(),
+            ));
+        }
+
         Ok(())
     }
 }
@@ -383,10 +848,10 @@ impl Default for KubeRetrySettings {
     }
 }
 
-/// Kubernetes connection pool settings
+/// Kubernetes API server connection settings
 #[derive(serde::Deserialize, Debug, Clone)]
 pub struct KubeConnectionSettings {
-    /// Connection pool size
+    /// Idle connections kept open to the API server
     #[serde(deserialize_with = "deserialize_number_from_string", default = "default_pool_size")]
     pub pool_size: usize,
     
@@ -394,9 +859,29 @@ pub struct KubeConnectionSettings {
     #[serde(deserialize_with = "deserialize_number_from_string", default = "default_idle_timeout_secs")]
     pub idle_timeout_secs: u64,
     
-    /// Connection keep alive interval in seconds
+    /// TCP keep alive interval in seconds
     #[serde(deserialize_with = "deserialize_number_from_string", default = "default_keep_alive_secs")]
     pub keep_alive_secs: u64,
+
+    /// Connect timeout in seconds
+    #[serde(deserialize_with = "deserialize_number_from_string", default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+
+    /// Read timeout in seconds, longer than the 290 seconds a watch request lasts
+    #[serde(deserialize_with = "deserialize_number_from_string", default = "default_read_timeout_secs")]
+    pub read_timeout_secs: u64,
+
+    /// Write timeout in seconds
+    #[serde(deserialize_with = "deserialize_number_from_string", default = "default_write_timeout_secs")]
+    pub write_timeout_secs: u64,
+
+    /// Requests per second to the API server, unlimited if 0
+    #[serde(deserialize_with = "deserialize_number_from_string", default)]
+    pub qps: f64,
+
+    /// Requests above qps sent at once
+    #[serde(deserialize_with = "deserialize_number_from_string", default = "default_burst")]
+    pub burst: u32,
 }
 
 fn default_pool_size() -> usize {
@@ -411,20 +896,100 @@ fn default_keep_alive_secs() -> u64 {
     30 // 30 seconds
 }
 
+fn default_connect_timeout_secs() -> u64 {
+    30
+}
+
+fn default_read_timeout_secs() -> u64 {
+    295 // as kube, watches last up to 290 seconds
+}
+
+fn default_write_timeout_secs() -> u64 {
+    30
+}
+
+fn default_burst() -> u32 {
+    10
+}
+
 impl Default for KubeConnectionSettings {
     fn default() -> Self {
         Self {
             pool_size: default_pool_size(),
             idle_timeout_secs: default_idle_timeout_secs(),
             keep_alive_secs: default_keep_alive_secs(),
+            connect_timeout_secs: default_connect_timeout_secs(),
+            read_timeout_secs: default_read_timeout_secs(),
+            write_timeout_secs: default_write_timeout_secs(),
+            qps: 0.0,
+            burst: default_burst(),
         }
     }
 }
 
-#[derive(serde::Deserialize, Debug, Clone)]
+impl KubeConnectionSettings {
+    /// Validate connection settings
+    pub fn validate(&self) -> std::result::Result<(), ConfigError> {
+        for (field, secs) in [
+            ("kube.connection.connect_timeout_secs", self.connect_timeout_secs),
+            ("kube.connection.read_timeout_secs", self.read_timeout_secs),
+            ("kube.connection.write_timeout_secs", self.write_timeout_secs),
+        ] {
+            if secs == 0 {
+                return Err(ConfigError::invalid(field, "0".to_string()));
+            }
+        }
+
+        if !self.qps.is_finite() || self.qps < 0.0 {
+            return Err(ConfigError::invalid("kube.connection.qps", self.qps.to_string()));
+        }
+
+        if self.qps > 0.0 && self.burst == 0 {
+            return Err(ConfigError::invalid("kube.connection.burst", "0".to_string()));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Clone, Default)]
 pub struct KubeSettings {
-    /// Whether to use TLS for Kubernetes API connection
-    pub use_tls: bool,
+    /// Accept any certificate of the API server, for test clusters only
+    #[serde(default)]
+    pub insecure_skip_tls_verify: bool,
+
+    /// Removed setting, rejected so that `use_tls: false` doesn't silently
+    /// verify the certificate again
+    #[serde(default)]
+    pub use_tls: Option<serde::de::IgnoredAny>,
+
+    /// Path of the kubeconfig file, inferred from the environment if not set
+    #[serde(default)]
+    pub kubeconfig: Option<String>,
+
+    /// kubeconfig context, the current context if not set
+    #[serde(default)]
+    pub context: Option<String>,
+
+    /// URL of the API server, instead of a kubeconfig or the in-cluster config
+    #[serde(default)]
+    pub api_server_url: Option<String>,
+
+    /// PEM bundle of the CAs of the API server certificate
+    #[serde(default)]
+    pub ca_file: Option<String>,
+
+    /// File of the bearer token, read again when it is rotated
+    #[serde(default)]
+    pub token_file: Option<String>,
+
+    /// User to act as, requires the impersonate permission
+    #[serde(default)]
+    pub impersonate_user: Option<String>,
+
+    /// Groups to act as, requires impersonate_user
+    #[serde(default)]
+    pub impersonate_groups: Vec<String>,
     
     /// Resources to watch
     pub resources: Vec<Resource>,
@@ -436,11 +1001,205 @@ pub struct KubeSettings {
     /// Connection pool settings
     #[serde(default)]
     pub connection: KubeConnectionSettings,
+
+    /// CatalogEntity custom resources watch settings
+    #[serde(default)]
+    pub catalog_entities: CatalogEntitySettings,
+
+    /// Org entities ConfigMaps and Secrets watch settings
+    #[serde(default)]
+    pub org_entities: OrgEntitySettings,
+
+    /// Lease-based leader election settings
+    #[serde(default)]
+    pub leader_election: LeaderElectionSettings,
+}
+
+/// Settings for watching CatalogEntity custom resources
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+pub struct CatalogEntitySettings {
+    /// Whether to watch CatalogEntity resources, requires the CRD to be installed
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Namespaces to watch, all namespaces if empty
+    #[serde(default)]
+    pub namespaces: Vec<String>,
+}
+
+/// Settings for watching ConfigMaps and Secrets declaring Groups, Users and Domains
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct OrgEntitySettings {
+    /// Whether to watch labelled ConfigMaps
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Label selector of the ConfigMaps and Secrets to watch
+    #[serde(default = "default_org_entities_label_selector")]
+    pub label_selector: String,
+
+    /// Namespaces to watch, all namespaces if empty
+    #[serde(default)]
+    pub namespaces: Vec<String>,
+
+    /// Whether to watch Secrets in addition to ConfigMaps
+    #[serde(default)]
+    pub secrets: bool,
+}
+
+fn default_org_entities_label_selector() -> String {
+    "backstage.acme.com/org-entities=true".to_string()
+}
+
+impl Default for OrgEntitySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            label_selector: default_org_entities_label_selector(),
+            namespaces: Vec::new(),
+            secrets: false,
+        }
+    }
+}
+
+/// Settings for electing the replica which writes to the cluster and publishes events
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct LeaderElectionSettings {
+    /// Whether to elect a leader, every replica acts as leader if disabled
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Name of the coordination.k8s.io Lease
+    #[serde(default = "default_lease_name")]
+    pub lease_name: String,
+
+    /// Namespace of the Lease, the POD_NAMESPACE environment variable if empty
+    #[serde(default)]
+    pub lease_namespace: String,
+
+    /// Seconds a leader holds the Lease without renewing it
+    #[serde(deserialize_with = "deserialize_number_from_string", default = "default_lease_duration_secs")]
+    pub lease_duration_secs: u64,
+
+    /// Seconds between attempts to acquire or renew the Lease
+    #[serde(deserialize_with = "deserialize_number_from_string", default = "default_lease_renew_secs")]
+    pub renew_interval_secs: u64,
+
+    /// Seconds a leader keeps acting without renewing the Lease, shorter than
+    /// lease_duration_secs so that it stops before another replica takes over
+    #[serde(deserialize_with = "deserialize_number_from_string", default = "default_lease_renew_deadline_secs")]
+    pub renew_deadline_secs: u64,
+}
+
+fn default_lease_name() -> String {
+    "k8s-entity-provider".to_string()
+}
+
+fn default_lease_duration_secs() -> u64 {
+    15 // 15 seconds
+}
+
+fn default_lease_renew_secs() -> u64 {
+    5 // 5 seconds
+}
+
+fn default_lease_renew_deadline_secs() -> u64 {
+    10 // 10 seconds
+}
+
+impl Default for LeaderElectionSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            lease_name: default_lease_name(),
+            lease_namespace: String::new(),
+            lease_duration_secs: default_lease_duration_secs(),
+            renew_interval_secs: default_lease_renew_secs(),
+            renew_deadline_secs: default_lease_renew_deadline_secs(),
+        }
+    }
+}
+
+impl LeaderElectionSettings {
+    /// Validate leader election settings
+    pub fn validate(&self) -> std::result::Result<(), ConfigError> {
+        if !self.enabled {
+            return Ok(());
+        }
+
+        if self.lease_name.is_empty() {
+            return Err(ConfigError::missing("kube.leader_election.lease_name"));
+        }
+
+        // the leader has to renew the Lease before it expires
+        if self.renew_interval_secs == 0 || self.renew_interval_secs >= self.lease_duration_secs {
+            return Err(ConfigError::invalid(
+                "kube.leader_election.renew_interval_secs",
+                format!("{} must be between 1 and lease_duration_secs ({})",
+                    self.renew_interval_secs,
+                    self.lease_duration_secs),
+            ));
+        }
+
+        // and step down before the others see it expired
+        if self.renew_deadline_secs <= self.renew_interval_secs || self.renew_deadline_secs >= self.lease_duration_secs {
+            return Err(ConfigError::invalid(
+                "kube.leader_election.renew_deadline_secs",
+                format!("{} must be between renew_interval_secs ({}) and lease_duration_secs ({})",
+                    self.renew_deadline_secs,
+                    self.renew_interval_secs,
+                    self.lease_duration_secs),
+            ));
+        }
+
+        Ok(())
+    }
 }
 
 impl KubeSettings {
     /// Validate Kubernetes settings
     pub fn validate(&self) -> std::result::Result<(), ConfigError> {
+        if self.use_tls.is_some() {
+            return Err(ConfigError::invalid(
+                "kube.use_tls",
+                "use_tls was removed, set insecure_skip_tls_verify to skip the certificate verification".to_string(),
+            ));
+        }
+
+        // Validate leader election
+        self.leader_election.validate()?;
+
+        self.connection.validate()?;
+
+        // Validate the API server is selected only one way
+        if let Some(ref url) = self.api_server_url {
+            if self.kubeconfig.is_some() || self.context.is_some() {
+                return Err(ConfigError::invalid(
+                    "kube.api_server_url",
+                    "api_server_url excludes kubeconfig and context".to_string(),
+                ));
+            }
+            match url.parse::<Url>() {
+                Ok(parsed) if matches!(parsed.scheme(), "http" | "https") && parsed.has_host() => {},
+                _ => return Err(ConfigError::invalid("kube.api_server_url", url.clone())),
+            }
+        }
+
+        for (field, path) in [("kube.ca_file", &self.ca_file), ("kube.token_file", &self.token_file)] {
+            if path.as_ref().is_some_and(|p| p.is_empty()) {
+                return Err(ConfigError::missing(field));
+            }
+        }
+
+        if !self.impersonate_groups.is_empty() && self.impersonate_user.is_none() {
+            return Err(ConfigError::missing("kube.impersonate_user"));
+        }
+
+        // Validate org entities are selected by label
+        if self.org_entities.enabled && self.org_entities.label_selector.is_empty() {
+            return Err(ConfigError::missing("kube.org_entities.label_selector"));
+        }
+
         // Validate resources
         for (i, resource) in self.resources.iter().enumerate() {
             resource.validate()
@@ -454,19 +1213,8 @@ impl KubeSettings {
     }
 }
 
-impl Default for KubeSettings {
-    fn default() -> KubeSettings {
-        Self {
-            use_tls: false,
-            resources: Vec::new(),
-            retry: KubeRetrySettings::default(),
-            connection: KubeConnectionSettings::default(), 
-        } 
-    }
-}
-
 /// Kubernetes resource to watch
-#[derive(serde::Deserialize, Debug, Clone, PartialEq)]
+#[derive(serde::Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct Resource {
     /// Name of the resource type (e.g., "pods", "deployments")
     pub name: String,
@@ -546,10 +1294,53 @@ impl Default for Resource {
 /// - Settings have invalid values
 /// - Settings deserialization fails
 pub fn get_configuration() -> Result<Settings> {
-    // Get current directory and configuration path
-    let base_path = std::env::current_dir()
-        .map_err(|e| ConfigError::IoError(e))?;
-    let configuration_directory = base_path.join("config");
+    let (environment, base_file_path, env_file_path) = configuration_files()?;
+    // Build configuration
+    let builder = config::Config::builder()
+        .add_source(config::File::from(base_file_path))
+        .add_source(config::File::from(env_file_path));
+
+    // Add environment variables
+    let settings = builder
+        .add_source(
+            config::Environment::with_prefix("APP")
+                .prefix_separator("_")
+                .separator("_"),
+        )
+        .build()
+        .context("Failed to build configuration")?;
+
+    // Deserialize settings
+    let config: Settings = settings
+        .try_deserialize()
+        .context("Failed to deserialize configuration")?;
+
+    // Validate settings
+    config.validate()
+        .context("Configuration validation failed")?;
+
+    // Log success and return
+    tracing::info!("Configuration loaded successfully for environment: {}", environment.as_str());
+    Ok(config)
+}
+
+/// Locate the configuration files of the running environment
+///
+/// # Returns
+/// The environment with the paths of its base.yaml and environment-specific yaml files
+///
+/// # Errors
+/// Returns an error if the environment is unsupported or a file doesn't exist
+pub fn configuration_files() -> Result<(Environment, PathBuf, PathBuf)> {
+    // Get configuration path, APP_CONFIG_DIR or the config directory in the current one
+    let configuration_directory = match std::env::var("APP_CONFIG_DIR") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => {
+            let base_path = std::env::current_dir()
+                .map_err(|e| ConfigError::IoError(e))?;
+            base_path.join("config")
+        }
+    };
 
     // Detect the running environment.
     // Default to `local` if unspecified.
@@ -586,33 +1377,7 @@ pub fn get_configuration() -> Result<Settings> {
         ).into());
     }
 
-    // Build configuration
-    let builder = config::Config::builder()
-        .add_source(config::File::from(base_file_path))
-        .add_source(config::File::from(env_file_path));
-
-    // Add environment variables
-    let settings = builder
-        .add_source(
-            config::Environment::with_prefix("APP")
-                .prefix_separator("_")
-                .separator("_"),
-        )
-        .build()
-        .context("Failed to build configuration")?;
-
-    // Deserialize settings
-    let config: Settings = settings
-        .try_deserialize()
-        .context("Failed to deserialize configuration")?;
-
-    // Validate settings
-    config.validate()
-        .context("Configuration validation failed")?;
-
-    // Log success and return
-    tracing::info!("Configuration loaded successfully for environment: {}", environment.as_str());
-    Ok(config)
+    Ok((environment, base_file_path, env_file_path))
 }
 
 /// The possible runtime environment for our application.
@@ -644,3 +1409,41 @@ impl TryFrom<String> for Environment {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renew_deadline_is_between_interval_and_lease_duration() {
+        let mut settings = LeaderElectionSettings { enabled: true, ..LeaderElectionSettings::default() };
+        assert!(settings.validate().is_ok());
+
+        settings.renew_deadline_secs = settings.lease_duration_secs;
+        assert!(settings.validate().is_err());
+        settings.renew_deadline_secs = settings.renew_interval_secs;
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn removed_use_tls_is_rejected() {
+        let settings: KubeSettings = serde_yaml::from_str("resources: []\nuse_tls: false\n").unwrap();
+        let err = settings.validate().unwrap_err();
+        assert!(err.to_string().contains("insecure_skip_tls_verify"), "{}", err);
+
+        let settings: KubeSettings = serde_yaml::from_str("resources: []\n").unwrap();
+        assert!(settings.validate().is_ok());
+    }
+
+    #[test]
+    fn credentials_need_listed_origins() {
+        let mut settings = get_configuration().unwrap().server;
+        settings.cors = CorsSettings { allow_all_origins: true, allow_credentials: true, ..CorsSettings::default() };
+        let err = settings.validate().unwrap_err();
+        assert!(err.to_string().contains("server.cors.allow_credentials"), "{}", err);
+
+        settings.cors.allow_all_origins = false;
+        settings.cors.allowed_origins = vec!["https://backstage.example.com".to_owned()];
+        assert!(settings.validate().is_ok());
+    }
+}
@@ -85,6 +85,11 @@ impl ResponseError for AppError {
             AppError::Server(e) => match e {
                 ServerError::ValidationError(_) => StatusCode::BAD_REQUEST,
                 ServerError::RoutingError(_) => StatusCode::NOT_FOUND,
+                ServerError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+                ServerError::Forbidden(_) => StatusCode::FORBIDDEN,
+                ServerError::RateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+                ServerError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+                ServerError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
                 _ => StatusCode::INTERNAL_SERVER_ERROR,
             },
             AppError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
@@ -310,6 +315,26 @@ pub enum ServerError {
     #[error("Request validation error: {0}")]
     ValidationError(String),
 
+    /// Missing or invalid credentials
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    /// Authenticated client lacks the role of the route
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
+    /// Client exceeded its rate limit
+    #[error("Rate limited: {0}")]
+    RateLimited(String),
+
+    /// Request not served, e.g. during shutdown
+    #[error("Service unavailable: {0}")]
+    Unavailable(String),
+
+    /// Request missed its deadline
+    #[error("Request timeout: {0}")]
+    Timeout(String),
+
     /// Unexpected internal server error
     #[error("Internal server error: {0}")]
     InternalError(String),
@@ -335,6 +360,31 @@ impl ServerError {
         Self::ValidationError(msg.into())
     }
 
+    /// Create an unauthorized error
+    pub fn unauthorized<S: Into<String>>(msg: S) -> Self {
+        Self::Unauthorized(msg.into())
+    }
+
+    /// Create a forbidden error
+    pub fn forbidden<S: Into<String>>(msg: S) -> Self {
+        Self::Forbidden(msg.into())
+    }
+
+    /// Create a rate limited error
+    pub fn rate_limited<S: Into<String>>(msg: S) -> Self {
+        Self::RateLimited(msg.into())
+    }
+
+    /// Create a service unavailable error
+    pub fn unavailable<S: Into<String>>(msg: S) -> Self {
+        Self::Unavailable(msg.into())
+    }
+
+    /// Create a request timeout error
+    pub fn timeout<S: Into<String>>(msg: S) -> Self {
+        Self::Timeout(msg.into())
+    }
+
     /// Create an internal server error
     pub fn internal<S: Into<String>>(msg: S) -> Self {
         Self::InternalError(msg.into())
@@ -1,9 +1,14 @@
 // Core modules
+pub mod cli;
 pub mod configuration;
 pub mod routes;
 pub mod startup;
 pub mod telemetry;
 pub mod errors;
+pub mod reload;
+pub mod supervisor;
+pub mod middleware;
+pub mod tls;
 
 // Domain-specific modules
 pub mod ax_kube;
@@ -1,20 +1,79 @@
-use k8s_entity_provider::startup::run;
-use k8s_entity_provider::ax_types::Db;
-use k8s_entity_provider::configuration::get_configuration;
+use k8s_entity_provider::startup::{run, ApplicationState};
+use k8s_entity_provider::reload::spawn_reloader;
+use k8s_entity_provider::supervisor::Supervisor;
+use k8s_entity_provider::ax_types::{CacheIndex, Db, EntityDb, OrgDb};
+use k8s_entity_provider::cli::{self, Cli, Command};
+use k8s_entity_provider::configuration::{get_configuration, Settings};
 use k8s_entity_provider::telemetry::{get_subscriber, init_subscriber};
-use k8s_entity_provider::ax_kube::{utils, watch::watch};
+use k8s_entity_provider::ax_kube::{utils, watch::watch, WatchQueue, catalog_entity::watch_catalog_entities,
+    org_config::watch_org_entities, leader::{elect_leader, Leadership}};
 use k8s_entity_provider::backstage::ingest;
+use actix_web::web;
 use std::net::TcpListener;
 use std::collections::BTreeMap;
 use std::sync::{Arc, Mutex};
+use clap::Parser;
 
-#[tokio::main]
-async fn main() -> std::io::Result<()> {
+fn main() -> std::io::Result<()> {
+    let cli = Cli::parse();
+    // flags are exported before the runtime starts its threads
+    cli.export_env();
+
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+
+    match cli.command() {
+        Command::Serve => {
+            let config = get_configuration().expect("Failed to read configuration");
+            runtime.block_on(serve(config, cli.log_level))
+        },
+        Command::ValidateConfig => exit_on_error(cli::validate_config()),
+        Command::Render { manifests } => {
+            let config = one_shot_configuration(&cli);
+            exit_on_error(runtime.block_on(cli::render(&config, &manifests)))
+        },
+        Command::Discover => {
+            let config = one_shot_configuration(&cli);
+            exit_on_error(runtime.block_on(cli::discover(&config)))
+        },
+    }
+}
+
+// Configuration of commands printing to stdout, which log to stderr instead
+fn one_shot_configuration(cli: &Cli) -> Settings {
+    let config = get_configuration().expect("Failed to read configuration");
+    let subscriber = get_subscriber(config.name.clone(), cli.log_level.clone(), std::io::stderr);
+    init_subscriber(subscriber);
+    config
+}
+
+// Report the failure of a one-shot command through the exit code
+fn exit_on_error(result: anyhow::Result<()>) -> std::io::Result<()> {
+    if let Err(why) = result {
+        eprintln!("{:#}", why);
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+// serve - Watches the cluster and serves entities until shut down
+async fn serve(config: Settings, log_level: String) -> std::io::Result<()> {
     // Shared cache across threads
     let cache: Db = Arc::new(Mutex::new(BTreeMap::new()));
+    // How the cached objects were watched
+    let index: CacheIndex = Arc::new(Mutex::new(BTreeMap::new()));
+    // Entities declared in-cluster
+    let entities: EntityDb = Arc::new(Mutex::new(BTreeMap::new()));
+    // Org entities declared in ConfigMaps and Secrets
+    let org: OrgDb = Arc::new(Mutex::new(BTreeMap::new()));
+    // Background tasks, stopped on shutdown
+    let supervisor = Supervisor::new();
+    // WatchEvents waiting for ingestion
+    let queue = WatchQueue::new(config.cache.def_channel_size, config.cache.queue_policy);
 
-    let config = get_configuration().expect("Failed to read configuration");
-    let subscriber = get_subscriber(config.name.clone(), "info".into(), std::io::stdout);
+    let subscriber = get_subscriber(config.name.clone(), log_level, std::io::stdout);
     init_subscriber(subscriber); 
 
     let k8s_version = match utils::get_k8s_version(&config).await {
@@ -27,24 +86,57 @@ async fn main() -> std::io::Result<()> {
     tracing::info!("k8s: {0}", k8s_version);
     
     // start thread for watching targetted k8s resources
-    match watch(&config, k8s_version.clone()).await {
-        Ok(events_channels) => {
+    let watchers = match watch(&config, k8s_version.clone(), queue.clone(), supervisor.clone()).await {
+        Ok((events_channels, watchers)) => {
             let _ = ingest::process_k8s_resources(&config, 
                                                 events_channels, 
-                                                cache.clone()).await;
+                                                cache.clone(),
+                                                index.clone(),
+                                                supervisor.clone()).await;
+            Some(watchers)
         },
         Err(why) => {
             tracing::error!("Failed to watch configured resources {:?}", why);
+            None
         }
     };
 
+    // only the leader writes to the cluster, all replicas serve entities
+    let leadership = match elect_leader(&config, &supervisor).await {
+        Ok(leadership) => leadership,
+        Err(why) => {
+            tracing::error!("Failed to start leader election, not writing to the cluster {:?}", why);
+            Leadership::never()
+        },
+    };
+
+    if let Err(why) = watch_catalog_entities(&config, entities.clone(), leadership.clone(), &supervisor).await {
+        tracing::error!("Failed to watch CatalogEntity resources {:?}", why);
+    }
+
+    if let Err(why) = watch_org_entities(&config, org.clone(), &supervisor).await {
+        tracing::error!("Failed to watch org entities {:?}", why);
+    }
+
     let address = format!(
         "{}:{}",
         config.server.host, 
         config.server.port
     );
     let listener = TcpListener::bind(address)?;
-    match run(listener, &config, cache.clone()).await {
+    let app_state = web::Data::new(
+        ApplicationState::new(config.clone(), 
+            cache.clone(), 
+            index.clone(), 
+            queue.clone(),
+            entities.clone(), 
+            org.clone(), 
+            supervisor.clone()));
+
+    // reload configuration changes of the mounted ConfigMap
+    spawn_reloader(app_state.clone(), watchers);
+
+    match run(listener, app_state).await {
         Ok(_) => tracing::info!("Server gracefully shut down"),
         Err(e) => tracing::error!("Server shutdown timed out: {}", e),
     }
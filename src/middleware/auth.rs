@@ -0,0 +1,456 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Instant, SystemTime};
+
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header,
+    middleware::Next,
+    web, Error, HttpMessage, ResponseError};
+use jsonwebtoken::{jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use serde_json::Value;
+
+use crate::ax_kube::{client, token_review};
+use crate::configuration::{AuthSettings, JwtSettings, KubeSettings, Role, ServerSettings};
+use crate::errors::{AppError, ServerError};
+use crate::startup::ApplicationState;
+use crate::tls::ClientCertificate;
+
+/// Authenticated client of a request, found in the request extensions
+#[derive(Debug, Clone)]
+pub struct Identity {
+    /// Name of the static token, JWT subject or k8s user
+    pub name: String,
+    pub role: Role,
+    /// static, jwt or tokenreview
+    pub mechanism: &'static str,
+}
+
+// Keys of the JWKS file with the modification time they were read at
+#[derive(Debug)]
+struct Jwks {
+    modified: Option<SystemTime>,
+    keys: JwkSet,
+}
+
+// Outcomes of TokenReviews kept at most, the oldest is dropped first. Unknown
+// tokens are cached as well, so random tokens must not grow the map unbounded.
+const MAX_CACHED_REVIEWS: usize = 4096;
+
+// Outcome of a TokenReview by digest of the token, with the time it was made
+type Reviews = HashMap<Vec<u8>, (Instant, Option<Identity>)>;
+
+/// Authenticates bearer tokens and applies the access policy of the routes.
+///
+/// Outcomes of TokenReviews are cached by the SHA-256 of the token, so that
+/// the API server is not asked on every request.
+#[derive(Debug)]
+pub struct Authenticator {
+    settings: AuthSettings,
+    jwks: Mutex<Option<Jwks>>,
+    reviews: Mutex<Reviews>,
+}
+
+impl Authenticator {
+    pub fn new(settings: &AuthSettings) -> Self {
+        Self {
+            settings: settings.clone(),
+            jwks: Mutex::new(None),
+            reviews: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Routes which never require authentication, e.g. /healthz
+    pub fn is_public(&self, path: &str) -> bool {
+        self.settings.public_paths.iter().any(|p| p == path)
+    }
+
+    /// Role required for the route pattern, none for public routes
+    pub fn required_role(&self, path: &str) -> Option<Role> {
+        if self.is_public(path) {
+            return None;
+        }
+
+        let admin = self.settings.admin_paths.iter().any(|p| {
+            path == p || path.strip_prefix(p.as_str()).is_some_and(|rest| rest.starts_with('/'))
+        });
+        if admin {
+            Some(Role::Admin)
+        } else if self.settings.enabled {
+            Some(Role::Reader)
+        } else {
+            None
+        }
+    }
+
+    /// Identity of the bearer token, none if no mechanism accepts it
+    pub async fn authenticate(&self, token: &str, kube: &KubeSettings) -> Option<Identity> {
+        if let Some(identity) = self.check_static(token) {
+            return Some(identity);
+        }
+
+        if let Some(ref jwt) = self.settings.jwt {
+            if token.split('.').count() == 3 {
+                match self.check_jwt(token, jwt) {
+                    Ok(identity) => return Some(identity),
+                    Err(why) => tracing::debug!("JWT rejected: {}", why),
+                }
+            }
+        }
+
+        if self.settings.token_review.enabled {
+            return self.check_token_review(token, kube).await;
+        }
+
+        None
+    }
+
+    fn check_static(&self, token: &str) -> Option<Identity> {
+        self.settings.static_tokens.iter()
+            .find(|st| st.token().is_some_and(|t| constant_time_eq(t.as_bytes(), token.as_bytes())))
+            .map(|st| Identity {
+                name: st.name.clone(),
+                role: st.role,
+                mechanism: "static",
+            })
+    }
+
+    fn check_jwt(&self, token: &str, settings: &JwtSettings) -> anyhow::Result<Identity> {
+        let header = jsonwebtoken::decode_header(token)?;
+        let jwks = self.load_jwks(settings)?;
+
+        let jwk = match header.kid {
+            Some(ref kid) => jwks.find(kid),
+            None if jwks.keys.len() == 1 => jwks.keys.first(),
+            None => None,
+        }
+        .ok_or_else(|| anyhow::anyhow!("no key for kid {:?}", header.kid))?;
+
+        // a key bound to an algorithm must not be used with another one
+        if let Some(alg) = jwk.common.key_algorithm {
+            if Algorithm::from_str(&alg.to_string()).ok() != Some(header.alg) {
+                anyhow::bail!("key {:?} is not for {:?}", jwk.common.key_id, header.alg);
+            }
+        }
+
+        let mut validation = Validation::new(header.alg);
+        match settings.audience {
+            Some(ref aud) => validation.set_audience(&[aud]),
+            None => validation.validate_aud = false,
+        }
+        if let Some(ref iss) = settings.issuer {
+            validation.set_issuer(&[iss]);
+        }
+
+        let claims = jsonwebtoken::decode::<Value>(token, &DecodingKey::from_jwk(jwk)?, &validation)?.claims;
+        let roles: Vec<&str> = match claims.get(&settings.roles_claim) {
+            Some(Value::Array(roles)) => roles.iter().filter_map(Value::as_str).collect(),
+            Some(Value::String(roles)) => roles.split_whitespace().collect(),
+            _ => Vec::new(),
+        };
+        let role = if roles.contains(&settings.admin_role.as_str()) { Role::Admin } else { Role::Reader };
+
+        Ok(Identity {
+            name: claims.get("sub").and_then(Value::as_str).unwrap_or_default().to_owned(),
+            role,
+            mechanism: "jwt",
+        })
+    }
+
+    // Keys of the JWKS file, read again once the file changed
+    fn load_jwks(&self, settings: &JwtSettings) -> anyhow::Result<JwkSet> {
+        let modified = std::fs::metadata(&settings.jwks_file)?.modified().ok();
+        let mut jwks = self.jwks.lock().unwrap();
+        let stale = match *jwks {
+            Some(ref loaded) => loaded.modified.is_none() || loaded.modified != modified,
+            None => true,
+        };
+        if stale {
+            let content = std::fs::read_to_string(&settings.jwks_file)?;
+            let keys: JwkSet = serde_json::from_str(&content)?;
+            tracing::info!("loaded {} keys of {}", keys.keys.len(), settings.jwks_file);
+            *jwks = Some(Jwks { modified, keys });
+        }
+
+        jwks.as_ref()
+            .map(|loaded| loaded.keys.clone())
+            .ok_or_else(|| anyhow::anyhow!("{} not loaded", settings.jwks_file))
+    }
+
+    async fn check_token_review(&self, token: &str, kube: &KubeSettings) -> Option<Identity> {
+        let settings = &self.settings.token_review;
+        let digest = ring::digest::digest(&ring::digest::SHA256, token.as_bytes()).as_ref().to_vec();
+        let ttl = std::time::Duration::from_secs(settings.cache_ttl_secs);
+
+        {
+            let mut reviews = self.reviews.lock().unwrap();
+            reviews.retain(|_, (at, _)| at.elapsed() < ttl);
+            if let Some((_, identity)) = reviews.get(&digest) {
+                return identity.clone();
+            }
+        }
+
+        let user = match client::client(kube).await {
+            Ok(cli) => token_review::review_token(&cli, token, &settings.audiences).await,
+            Err(why) => Err(why),
+        };
+        let user = match user {
+            Ok(user) => user,
+            Err(why) => {
+                // not cached, the next request asks again
+                tracing::warn!("token review failed: {:?}", why);
+                return None;
+            },
+        };
+
+        let identity = user.and_then(|name| {
+            let role = if settings.admins.contains(&name) {
+                Role::Admin
+            } else if settings.readers.is_empty() || settings.readers.contains(&name) {
+                Role::Reader
+            } else {
+                tracing::debug!("{} is neither reader nor admin", name);
+                return None;
+            };
+            Some(Identity { name, role, mechanism: "tokenreview" })
+        });
+
+        let mut reviews = self.reviews.lock().unwrap();
+        if reviews.len() >= MAX_CACHED_REVIEWS {
+            let oldest = reviews.iter()
+                .min_by_key(|(_, (at, _))| *at)
+                .map(|(digest, _)| digest.clone());
+            if let Some(oldest) = oldest {
+                reviews.remove(&oldest);
+            }
+        }
+        reviews.insert(digest, (Instant::now(), identity.clone()));
+        identity
+    }
+}
+
+// auth - Answers 401 Unauthorized without a valid bearer token and 403 Forbidden
+//         when the role of the client is not sufficient for the route.
+// With a server.tls.client_ca_file, requests outside the public paths also need
+// a connection with a client certificate.
+pub async fn auth(req: ServiceRequest,
+    next: Next<impl MessageBody>) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    // the authenticator and kube settings of the current configuration, replaced on reload
+    let snapshot = match req.app_data::<web::Data<ApplicationState>>() {
+        Some(state) => state.snapshot(),
+        None => return next.call(req).await.map(ServiceResponse::map_into_left_body),
+    };
+    let authenticator = &snapshot.authenticator;
+
+    let route = route_of(&req);
+    let client_ca = req.app_data::<web::Data<ServerSettings>>()
+        .is_some_and(|server| server.tls.enabled && server.tls.client_ca_file.is_some());
+    if client_ca && !authenticator.is_public(&route) && req.conn_data::<ClientCertificate>().is_none() {
+        let err = AppError::Server(ServerError::unauthorized("client certificate required"));
+        return Ok(req.error_response(err).map_into_right_body());
+    }
+
+    let required = match authenticator.required_role(&route) {
+        Some(role) => role,
+        None => return next.call(req).await.map(ServiceResponse::map_into_left_body),
+    };
+
+    let token = req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(str::to_owned);
+
+    let identity = match token {
+        Some(token) => authenticator.authenticate(&token, &snapshot.config.kube).await,
+        None => None,
+    };
+
+    let identity = match identity {
+        Some(identity) => identity,
+        None => {
+            let err = AppError::Server(ServerError::unauthorized("invalid or missing bearer token"));
+            let mut resp = err.error_response();
+            resp.headers_mut().insert(header::WWW_AUTHENTICATE, header::HeaderValue::from_static("Bearer"));
+            return Ok(req.into_response(resp).map_into_right_body());
+        },
+    };
+
+    if identity.role < required {
+        tracing::debug!("{} ({}) may not access {}", identity.name, identity.mechanism, route);
+        let err = AppError::Server(ServerError::forbidden(format!("{} requires the admin role", route)));
+        return Ok(req.error_response(err).map_into_right_body());
+    }
+
+    req.extensions_mut().insert(identity);
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+// Pattern of the route serving the request, e.g. /api/v1/status/{kind}. The
+// router matches the percent-decoded path, so /api/v1/%63ache is the cache route
+// as well. Paths without a route keep their decoded form.
+fn route_of(req: &ServiceRequest) -> String {
+    let path = req.match_info().as_str();
+    req.resource_map()
+        .match_pattern(path)
+        .unwrap_or_else(|| path.to_owned())
+}
+
+// Compare without leaking the position of the first difference
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, middleware::from_fn, test as actix_test, App, HttpResponse};
+
+    use crate::ax_kube::WatchQueue;
+    use crate::ax_types::{CacheIndex, Db, EntityDb, OrgDb};
+    use crate::configuration::{get_configuration, QueuePolicy, StaticTokenSettings};
+    use crate::startup::ConfigSnapshot;
+    use crate::supervisor::Supervisor;
+
+    fn settings(enabled: bool) -> AuthSettings {
+        let token = |name: &str, role| StaticTokenSettings {
+            name: name.to_string(),
+            token_file: None,
+            token_env: Some(format!("AUTH_TEST_{}_TOKEN", name.to_uppercase())),
+            role,
+        };
+        std::env::set_var("AUTH_TEST_READER_TOKEN", "reader-secret");
+        std::env::set_var("AUTH_TEST_ADMIN_TOKEN", "admin-secret");
+
+        AuthSettings {
+            enabled,
+            static_tokens: vec![token("reader", Role::Reader), token("admin", Role::Admin)],
+            ..AuthSettings::default()
+        }
+    }
+
+    fn app_state(auth: AuthSettings) -> web::Data<ApplicationState> {
+        let mut config = get_configuration().unwrap();
+        config.server.auth = auth;
+
+        web::Data::new(ApplicationState::new(config,
+            Db::default(),
+            CacheIndex::default(),
+            WatchQueue::new(1, QueuePolicy::Block),
+            EntityDb::default(),
+            OrgDb::default(),
+            Supervisor::new()))
+    }
+
+    fn request(path: &str, token: Option<&str>) -> actix_test::TestRequest {
+        let req = actix_test::TestRequest::get().uri(path);
+        match token {
+            Some(token) => req.insert_header((header::AUTHORIZATION, format!("Bearer {}", token))),
+            None => req,
+        }
+    }
+
+    macro_rules! service {
+        ($state:expr) => {
+            actix_test::init_service(
+                App::new()
+                    .app_data($state.clone())
+                    .wrap(from_fn(auth))
+                    .service(web::scope("/api/v1")
+                        .service(web::resource("/cache").to(HttpResponse::Ok))
+                        .service(web::resource("/status/{kind}").to(HttpResponse::Ok)))
+                    .route("/healthz", web::get().to(HttpResponse::Ok)),
+            ).await
+        };
+    }
+
+    async fn status(enabled: bool, path: &str, token: Option<&str>) -> StatusCode {
+        let app = service!(app_state(settings(enabled)));
+        actix_test::call_service(&app, request(path, token).to_request()).await.status()
+    }
+
+    #[test]
+    fn required_role_of_routes() {
+        let authenticator = Authenticator::new(&settings(false));
+        assert_eq!(authenticator.required_role("/healthz"), None);
+        assert_eq!(authenticator.required_role("/api/v1/status/{kind}"), None);
+        assert_eq!(authenticator.required_role("/api/v1/cache"), Some(Role::Admin));
+        assert_eq!(authenticator.required_role("/api/v1/cache/{key}"), Some(Role::Admin));
+        assert_eq!(authenticator.required_role("/api/v1/cached"), None);
+
+        let authenticator = Authenticator::new(&settings(true));
+        assert_eq!(authenticator.required_role("/healthz"), None);
+        assert_eq!(authenticator.required_role("/api/v1/status/{kind}"), Some(Role::Reader));
+    }
+
+    #[test]
+    fn constant_time_eq_compares_bytes() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(constant_time_eq(b"", b""));
+    }
+
+    #[actix_web::test]
+    async fn admin_route_requires_admin() {
+        assert_eq!(status(true, "/api/v1/cache", None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(true, "/api/v1/cache", Some("wrong")).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(true, "/api/v1/cache", Some("reader-secret")).await, StatusCode::FORBIDDEN);
+        assert_eq!(status(true, "/api/v1/cache", Some("admin-secret")).await, StatusCode::OK);
+        assert_eq!(status(true, "/api/v1/status/Deployment", Some("reader-secret")).await, StatusCode::OK);
+        assert_eq!(status(true, "/healthz", None).await, StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn client_ca_requires_certificates_outside_public_paths() {
+        let mut server = get_configuration().unwrap().server;
+        server.tls.enabled = true;
+        server.tls.client_ca_file = Some("/etc/k8s-entity-provider/tls/ca.crt".to_owned());
+        let app = actix_test::init_service(
+            App::new()
+                .app_data(app_state(settings(false)))
+                .app_data(web::Data::new(server))
+                .wrap(from_fn(auth))
+                .route("/api/v1/status/{kind}", web::get().to(HttpResponse::Ok))
+                .route("/healthz", web::get().to(HttpResponse::Ok)),
+        ).await;
+
+        // test requests come without a connection, as if no certificate was presented
+        let resp = actix_test::call_service(&app, request("/api/v1/status/Deployment", Some("reader-secret")).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let resp = actix_test::call_service(&app, request("/healthz", None).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn encoded_path_gets_policy_of_its_route() {
+        // %63 is 'c', routed to /api/v1/cache
+        assert_eq!(status(false, "/api/v1/%63ache", None).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(true, "/api/v1/%63ache", Some("reader-secret")).await, StatusCode::FORBIDDEN);
+        assert_eq!(status(true, "/api/v1/%63ache", Some("admin-secret")).await, StatusCode::OK);
+        assert_eq!(status(false, "/api/v1/status/Deployment", None).await, StatusCode::OK);
+        assert_eq!(status(true, "/api%2Fv1/cache", Some("reader-secret")).await, StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn reload_applies_revoked_tokens() {
+        let state = app_state(settings(true));
+        let app = service!(state);
+        let req = |token| request("/api/v1/status/Service", Some(token)).to_request();
+        assert_eq!(actix_test::call_service(&app, req("reader-secret")).await.status(), StatusCode::OK);
+
+        let mut config = state.snapshot().config.clone();
+        config.server.auth.static_tokens.retain(|st| st.name != "reader");
+        state.swap(ConfigSnapshot::new(config));
+        assert_eq!(actix_test::call_service(&app, req("reader-secret")).await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(actix_test::call_service(&app, req("admin-secret")).await.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,101 @@
+use actix_cors::Cors;
+
+use crate::configuration::CorsSettings;
+use crate::middleware::request_id::REQUEST_ID_HEADER;
+use crate::routes::api::v1::entities::NEXT_CURSOR_HEADER;
+
+// cors - Builds the CORS middleware of the settings, so that Backstage frontend
+//         plugins can call the provider from the browser.
+// Origins, methods and headers were validated with the settings.
+pub fn cors(settings: &CorsSettings) -> Cors {
+    let mut cors = Cors::default()
+        .allowed_methods(settings.allowed_methods.iter().map(String::as_str))
+        .allowed_headers(settings.allowed_headers.iter().map(String::as_str))
+        // readable by the frontend plugins, e.g. to fetch the next page
+        .expose_headers([NEXT_CURSOR_HEADER, REQUEST_ID_HEADER.as_str()])
+        .max_age(settings.max_age as usize);
+
+    if settings.allow_all_origins {
+        cors = cors.allow_any_origin();
+    } else {
+        for origin in settings.allowed_origins.iter() {
+            cors = cors.allowed_origin(origin);
+        }
+    }
+
+    if settings.allow_credentials {
+        cors = cors.supports_credentials();
+    }
+
+    cors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::{header, Method, StatusCode}, test, web, App, HttpResponse};
+
+    const ORIGIN: &str = "https://backstage.example.com";
+
+    fn settings() -> CorsSettings {
+        CorsSettings {
+            allowed_origins: vec![ORIGIN.to_owned()],
+            allowed_methods: vec!["GET".to_owned()],
+            allow_credentials: true,
+            max_age: 600,
+            ..CorsSettings::default()
+        }
+    }
+
+    #[actix_web::test]
+    async fn preflight_of_allowed_origin() {
+        let app = test::init_service(App::new()
+            .wrap(cors(&settings()))
+            .route("/api/v1/entities", web::get().to(HttpResponse::Ok))).await;
+
+        let req = test::TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri("/api/v1/entities")
+            .insert_header((header::ORIGIN, ORIGIN))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "GET"))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_HEADERS, "authorization"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let headers = resp.headers();
+        assert_eq!(headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), ORIGIN);
+        assert_eq!(headers.get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).unwrap(), "true");
+        assert_eq!(headers.get(header::ACCESS_CONTROL_MAX_AGE).unwrap(), "600");
+        assert!(headers.get(header::ACCESS_CONTROL_ALLOW_METHODS).unwrap().to_str().unwrap().contains("GET"));
+
+        let req = test::TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri("/api/v1/entities")
+            .insert_header((header::ORIGIN, "https://evil.example.com"))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "GET"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert!(resp.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+    }
+
+    #[actix_web::test]
+    async fn actual_request_exposes_headers() {
+        let app = test::init_service(App::new()
+            .wrap(cors(&settings()))
+            .route("/api/v1/entities", web::get().to(|| async {
+                HttpResponse::Ok().insert_header((NEXT_CURSOR_HEADER, "abc")).finish()
+            }))).await;
+
+        let req = test::TestRequest::get()
+            .uri("/api/v1/entities")
+            .insert_header((header::ORIGIN, ORIGIN))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), ORIGIN);
+        let exposed = resp.headers().get(header::ACCESS_CONTROL_EXPOSE_HEADERS).unwrap().to_str().unwrap().to_owned();
+        assert!(exposed.contains(NEXT_CURSOR_HEADER), "{}", exposed);
+        assert!(exposed.contains(REQUEST_ID_HEADER.as_str()), "{}", exposed);
+    }
+}
@@ -0,0 +1,5 @@
+pub mod rate_limit;
+pub mod cors;
+pub mod request_id;
+pub mod timeout;
+pub mod auth;
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::header,
+    middleware::Next,
+    web, Error, ResponseError};
+
+use crate::configuration::RateLimitSettings;
+use crate::errors::{AppError, ServerError};
+use crate::startup::ApplicationState;
+
+// Buckets of clients which made no request for this long are dropped
+const IDLE_BUCKET_TTL: Duration = Duration::from_secs(300);
+// Checks between sweeps of the idle buckets
+const SWEEP_EVERY: u64 = 1024;
+
+/// Address or CIDR range like 10.0.0.0/8
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    /// Parses an address or a CIDR range, none if invalid
+    pub fn parse(s: &str) -> Option<Self> {
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, Some(prefix.parse::<u8>().ok()?)),
+            None => (s.trim().parse::<IpAddr>().ok()?, None),
+        };
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+        if prefix > max {
+            return None;
+        }
+
+        Some(Self { addr, prefix })
+    }
+
+    /// Whether the address is in the range
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        let (net, ip, bits) = match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => (u32::from(net) as u128, u32::from(ip) as u128, 32),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => (u128::from(net), u128::from(ip), 128),
+            _ => return false,
+        };
+        let shift = bits - u32::from(self.prefix);
+        if shift >= bits {
+            return true;
+        }
+
+        net >> shift == ip >> shift
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket rate limiter per client IP.
+///
+/// Each client may burst up to burst_size requests, the bucket refills with
+/// requests_per_second tokens. Only trusted proxies may name the client with
+/// X-Forwarded-For, otherwise the peer address is the client.
+#[derive(Debug)]
+pub struct RateLimiter {
+    enabled: bool,
+    rate: f64,
+    burst: f64,
+    trusted_proxies: Vec<IpNetwork>,
+    exempt_paths: Vec<String>,
+    buckets: Mutex<(HashMap<IpAddr, Bucket>, u64)>,
+}
+
+impl RateLimiter {
+    pub fn new(settings: &RateLimitSettings) -> Self {
+        Self {
+            enabled: settings.enabled,
+            rate: f64::from(settings.requests_per_second),
+            burst: f64::from(settings.burst_size),
+            trusted_proxies: settings.trusted_proxies.iter()
+                .filter_map(|p| IpNetwork::parse(p))
+                .collect(),
+            exempt_paths: settings.exempt_paths.clone(),
+            buckets: Mutex::new((HashMap::new(), 0)),
+        }
+    }
+
+    /// Take a token of the client, or the time until the next one is available
+    pub fn check(&self, client: IpAddr) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut guard = self.buckets.lock().unwrap();
+        let (buckets, checks) = &mut *guard;
+
+        *checks += 1;
+        if *checks % SWEEP_EVERY == 0 {
+            buckets.retain(|_, b| now.duration_since(b.updated) < IDLE_BUCKET_TTL);
+        }
+
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate))
+        }
+    }
+
+    fn is_exempt(&self, path: &str) -> bool {
+        !self.enabled || self.exempt_paths.iter().any(|p| p == path)
+    }
+
+    fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|net| net.contains(ip))
+    }
+
+    /// Address of the client, the nearest untrusted hop of X-Forwarded-For
+    /// when the request came through trusted proxies
+    pub fn client_ip(&self, req: &ServiceRequest) -> Option<IpAddr> {
+        let peer = req.peer_addr()?.ip();
+        if !self.is_trusted(&peer) {
+            return Some(peer);
+        }
+
+        let forwarded: Vec<IpAddr> = req.headers()
+            .get_all("x-forwarded-for")
+            .filter_map(|h| h.to_str().ok())
+            .flat_map(|h| h.split(','))
+            .filter_map(|hop| hop.trim().parse::<IpAddr>().ok())
+            .collect();
+
+        let client = forwarded.iter()
+            .rev()
+            .find(|hop| !self.is_trusted(hop))
+            .or(forwarded.first())
+            .copied();
+
+        Some(client.unwrap_or(peer))
+    }
+}
+
+// rate_limit - Answers 429 Too Many Requests with Retry-After once a client
+//         used up its bucket. Requests without a peer address pass.
+pub async fn rate_limit(req: ServiceRequest,
+    next: Next<impl MessageBody>) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    // the limiter of the current configuration, replaced on reload
+    let limiter = match req.app_data::<web::Data<ApplicationState>>() {
+        Some(state) => state.snapshot().rate_limiter.clone(),
+        None => return next.call(req).await.map(ServiceResponse::map_into_left_body),
+    };
+
+    if limiter.is_exempt(req.path()) {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    }
+
+    if let Some(client) = limiter.client_ip(&req) {
+        if let Err(retry_after) = limiter.check(client) {
+            tracing::debug!("rate limited {} on {}", client, req.path());
+            let err = AppError::Server(ServerError::rate_limited(format!("too many requests from {}", client)));
+            let mut resp = err.error_response();
+            resp.headers_mut().insert(header::RETRY_AFTER,
+                header::HeaderValue::from(retry_after.as_secs_f64().ceil().max(1.0) as u64));
+            return Ok(req.into_response(resp).map_into_right_body());
+        }
+    }
+
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn networks_contain_their_addresses() {
+        let net = IpNetwork::parse("10.1.0.0/16").unwrap();
+        assert!(net.contains(&ip("10.1.255.7")));
+        assert!(!net.contains(&ip("10.2.0.1")));
+        assert!(!net.contains(&ip("::1")));
+        // IPv4-mapped IPv6 addresses are compared as IPv4
+        assert!(net.contains(&ip("::ffff:10.1.0.1")));
+
+        let host = IpNetwork::parse("192.168.1.10").unwrap();
+        assert!(host.contains(&ip("192.168.1.10")));
+        assert!(!host.contains(&ip("192.168.1.11")));
+
+        assert!(IpNetwork::parse("0.0.0.0/0").unwrap().contains(&ip("8.8.8.8")));
+        assert!(IpNetwork::parse("fd00::/8").unwrap().contains(&ip("fd12::1")));
+
+        for invalid in ["10.0.0.0/33", "fd00::/129", "10.0.0/8", "proxy", "10.0.0.0/x"] {
+            assert!(IpNetwork::parse(invalid).is_none(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn buckets_allow_bursts_per_client() {
+        let limiter = RateLimiter::new(&RateLimitSettings {
+            enabled: true,
+            requests_per_second: 1,
+            burst_size: 3,
+            ..RateLimitSettings::default()
+        });
+        let client = ip("10.0.0.1");
+
+        for _ in 0..3 {
+            assert!(limiter.check(client).is_ok());
+        }
+        let retry_after = limiter.check(client).unwrap_err();
+        assert!(retry_after > Duration::ZERO && retry_after <= Duration::from_secs(1), "{:?}", retry_after);
+
+        // other clients have buckets of their own
+        assert!(limiter.check(ip("10.0.0.2")).is_ok());
+    }
+
+    #[test]
+    fn trusted_proxies_name_the_client() {
+        let limiter = RateLimiter::new(&RateLimitSettings {
+            trusted_proxies: vec!["10.0.0.0/8".to_owned()],
+            ..RateLimitSettings::default()
+        });
+        let request = |peer: &str, forwarded: &str| actix_web::test::TestRequest::default()
+            .peer_addr(format!("{}:443", peer).parse().unwrap())
+            .insert_header(("x-forwarded-for", forwarded))
+            .to_srv_request();
+
+        assert_eq!(limiter.client_ip(&request("10.0.0.5", "203.0.113.7, 10.0.0.9")), Some(ip("203.0.113.7")));
+        assert_eq!(limiter.client_ip(&request("198.51.100.1", "203.0.113.7")), Some(ip("198.51.100.1")));
+        assert_eq!(limiter.client_ip(&request("10.0.0.5", "10.0.0.9")), Some(ip("10.0.0.9")));
+    }
+}
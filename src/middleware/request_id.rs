@@ -0,0 +1,135 @@
+use actix_web::{
+    body::MessageBody,
+    error::InternalError,
+    dev::{ServiceRequest, ServiceResponse},
+    http::header::{HeaderName, HeaderValue},
+    middleware::Next,
+    web, Error, HttpMessage};
+
+use crate::configuration::ServerSettings;
+
+/// Header carrying the id of a request across services
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+// Longest request id accepted from clients
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Id of the request, taken from X-Request-Id or generated
+#[derive(Debug, Clone)]
+pub struct RequestId(pub String);
+
+// request_id - Propagates the X-Request-Id of the client or generates one,
+//         and returns it with the response, error responses of the inner
+//         middleware included. Handlers and the root span find it in the
+//         request extensions.
+pub async fn request_id(req: ServiceRequest,
+    next: Next<impl MessageBody>) -> Result<ServiceResponse<impl MessageBody>, Error> {
+    let enabled = req.app_data::<web::Data<ServerSettings>>()
+        .is_none_or(|settings| settings.enable_request_id);
+    if !enabled {
+        return next.call(req).await;
+    }
+
+    let id = req.headers()
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .filter(|id| is_valid(id))
+        .map(str::to_owned)
+        .unwrap_or_else(generate);
+    req.extensions_mut().insert(RequestId(id.clone()));
+
+    let value = HeaderValue::from_str(&id).ok();
+    match next.call(req).await {
+        Ok(mut resp) => {
+            if let Some(value) = value {
+                resp.headers_mut().insert(REQUEST_ID_HEADER, value);
+            }
+            Ok(resp)
+        },
+        // errors of the inner middleware become responses only after all
+        // middleware, the request id is added to the response of the error
+        Err(err) => {
+            let mut resp = err.error_response();
+            if let Some(value) = value {
+                resp.headers_mut().insert(REQUEST_ID_HEADER, value);
+            }
+            Err(InternalError::from_response(err, resp).into())
+        },
+    }
+}
+
+// Client ids are echoed into logs and headers, only printable ASCII is accepted
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+// Random 128 bit id formatted like a UUID v4
+fn generate() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{middleware::from_fn, test, App, HttpResponse};
+
+    async fn response_id(sent: Option<&str>) -> String {
+        let app = test::init_service(App::new()
+            .wrap(from_fn(request_id))
+            .route("/", web::get().to(HttpResponse::Ok))).await;
+        let mut req = test::TestRequest::get().uri("/");
+        if let Some(id) = sent {
+            req = req.insert_header((REQUEST_ID_HEADER, id));
+        }
+
+        let resp = test::call_service(&app, req.to_request()).await;
+        resp.headers().get(&REQUEST_ID_HEADER).unwrap().to_str().unwrap().to_owned()
+    }
+
+    #[actix_web::test]
+    async fn generates_an_id() {
+        let id = response_id(None).await;
+        assert_eq!(id.len(), 36);
+        assert_eq!(id.as_bytes()[14], b'4');
+        assert_ne!(id, response_id(None).await);
+    }
+
+    #[actix_web::test]
+    async fn propagates_the_client_id() {
+        assert_eq!(response_id(Some("backstage-42")).await, "backstage-42");
+    }
+
+    #[actix_web::test]
+    async fn replaces_invalid_ids() {
+        for invalid in ["two words", &"x".repeat(MAX_REQUEST_ID_LEN + 1)] {
+            let id = response_id(Some(invalid)).await;
+            assert_ne!(id, invalid);
+            assert_eq!(id.len(), 36);
+        }
+    }
+
+    #[actix_web::test]
+    async fn errors_of_inner_middleware_carry_the_id() {
+        let app = test::init_service(App::new()
+            .wrap(from_fn(|_req: ServiceRequest, _next: Next<actix_web::body::BoxBody>| async {
+                Err::<ServiceResponse, _>(actix_web::error::ErrorForbidden("denied"))
+            }))
+            .wrap(from_fn(request_id))
+            .route("/", web::get().to(HttpResponse::Ok))).await;
+        let req = test::TestRequest::get().uri("/").insert_header((REQUEST_ID_HEADER, "denied-1"));
+
+        let Err(err) = test::try_call_service(&app, req.to_request()).await else {
+            panic!("the inner middleware failed the request");
+        };
+        let resp = err.error_response();
+        assert_eq!(resp.status(), actix_web::http::StatusCode::FORBIDDEN);
+        assert_eq!(resp.headers().get(&REQUEST_ID_HEADER).unwrap(), "denied-1");
+    }
+}
@@ -0,0 +1,114 @@
+use std::time::Duration;
+
+use actix_web::{
+    body::{EitherBody, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    middleware::Next,
+    web, Error};
+
+use crate::configuration::ServerSettings;
+use crate::errors::{AppError, ServerError};
+use crate::startup::ApplicationState;
+
+// draining - Answers 503 Service Unavailable once the server is shutting down
+//         and the cache is no longer maintained.
+pub async fn draining(req: ServiceRequest,
+    next: Next<impl MessageBody>) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let draining = req.app_data::<web::Data<ApplicationState>>()
+        .is_some_and(|state| state.supervisor.token().is_cancelled());
+    if draining {
+        let err = AppError::Server(ServerError::unavailable("server is shutting down"));
+        return Ok(req.error_response(err).map_into_right_body());
+    }
+
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+// timeout - Answers 504 Gateway Timeout when the handler misses the deadline
+//         of server.request_timeout. Wraps resources rather than the App: the
+//         request is cloned for the response, which routing does not allow.
+pub async fn timeout(req: ServiceRequest,
+    next: Next<impl MessageBody>) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, Error> {
+    let deadline = match req.app_data::<web::Data<ServerSettings>>() {
+        Some(settings) => Duration::from_secs(settings.request_timeout),
+        None => return next.call(req).await.map(ServiceResponse::map_into_left_body),
+    };
+
+    // answered like a handler error, so that the outer middleware still add
+    // the request id, CORS and default headers
+    let http_req = req.request().clone();
+    match tokio::time::timeout(deadline, next.call(req)).await {
+        Ok(resp) => resp.map(ServiceResponse::map_into_left_body),
+        Err(_) => {
+            tracing::warn!("request to {} timed out after {:?}", http_req.path(), deadline);
+            let err = AppError::Server(ServerError::timeout(format!("no response within {:?}", deadline)));
+            Ok(ServiceResponse::from_err(err, http_req).map_into_right_body())
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, middleware::from_fn, test, App, HttpResponse};
+
+    use crate::ax_kube::WatchQueue;
+    use crate::configuration::{get_configuration, QueuePolicy};
+    use crate::middleware::request_id::{request_id, REQUEST_ID_HEADER};
+    use crate::ax_types::{CacheIndex, Db, EntityDb, OrgDb};
+    use crate::supervisor::Supervisor;
+
+    fn app_state() -> web::Data<ApplicationState> {
+        web::Data::new(ApplicationState::new(get_configuration().unwrap(),
+            Db::default(),
+            CacheIndex::default(),
+            WatchQueue::new(1, QueuePolicy::Block),
+            EntityDb::default(),
+            OrgDb::default(),
+            Supervisor::new()))
+    }
+
+    async fn slow() -> HttpResponse {
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        HttpResponse::Ok().finish()
+    }
+
+    macro_rules! service {
+        ($state:expr) => {
+            test::init_service(App::new()
+                .app_data($state.clone())
+                .app_data(web::Data::new(ServerSettings { request_timeout: 1, ..$state.snapshot().config.server.clone() }))
+                .wrap(from_fn(draining))
+                .wrap(from_fn(request_id))
+                .service(web::resource("/slow").wrap(from_fn(timeout)).to(slow))
+                .service(web::resource("/fast").wrap(from_fn(timeout)).to(HttpResponse::Ok))).await
+        };
+    }
+
+    #[actix_web::test]
+    async fn missed_deadline_is_answered_with_the_request_id() {
+        tokio::time::pause();
+        let state = app_state();
+        let app = service!(state);
+
+        let req = test::TestRequest::get().uri("/slow").insert_header((REQUEST_ID_HEADER, "slow-1"));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(resp.headers().get(&REQUEST_ID_HEADER).unwrap(), "slow-1");
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/fast").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn draining_server_is_unavailable() {
+        let state = app_state();
+        let app = service!(state);
+        state.supervisor.token().cancel();
+
+        let req = test::TestRequest::get().uri("/fast").insert_header((REQUEST_ID_HEADER, "late-1"));
+        let resp = test::call_service(&app, req.to_request()).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers().get(&REQUEST_ID_HEADER).unwrap(), "late-1");
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+
+use actix_web::web;
+use anyhow::Result;
+use k8s_openapi::chrono::Utc;
+use tokio::sync::Mutex as AsyncMutex;
+use tokio::time::{self, Duration};
+
+use crate::ax_kube::watch::Watchers;
+use crate::configuration::{configuration_files, get_configuration};
+use crate::startup::{ApplicationState, ConfigSnapshot};
+
+/// Outcome of the last configuration reload
+#[derive(serde::Serialize, Debug, Clone, Default)]
+pub struct ReloadStatus {
+    /// Number of successfully applied configurations since startup
+    pub generation: u64,
+    /// RFC 3339 time of the last reload attempt
+    pub last_attempt: Option<String>,
+    /// RFC 3339 time of the last successful reload
+    pub last_success: Option<String>,
+    /// Whether the last reload attempt succeeded
+    pub success: bool,
+    pub message: String,
+    /// Resources whose watch was started by the last reload
+    pub resources_started: usize,
+    /// Resources whose watch was stopped by the last reload
+    pub resources_stopped: usize,
+}
+
+// spawn_reloader - Starts a thread checking the configuration files for changes, e.g. when
+//         the mounted ConfigMap is updated. A changed configuration is validated, the
+//         watches of added and removed resources are started and stopped, and the static
+//         Backstage entities, authentication and rate limit are swapped. Other server,
+//         connection and CatalogEntity/org entity watch settings still apply after a
+//         restart only.
+pub fn spawn_reloader(app_state: web::Data<ApplicationState>, watchers: Option<Watchers>) {
+    let reload = app_state.snapshot().config.reload.clone();
+    if !reload.enabled {
+        tracing::info!("Configuration hot reload disabled");
+        return;
+    }
+
+    let watchers = Arc::new(AsyncMutex::new(watchers));
+    let supervisor = app_state.supervisor.clone();
+
+    supervisor.spawn("config reloader", supervisor.token(), move |token| {
+        let app_state = app_state.clone();
+        let watchers = watchers.clone();
+        let reload = reload.clone();
+
+        async move {
+            let mut last = match fingerprint() {
+                Ok(fp) => fp,
+                Err(why) => {
+                    tracing::error!("failed to read configuration files: {:?}", why);
+                    0
+                }
+            };
+            let mut interval = time::interval(Duration::from_secs(reload.interval));
+
+            loop {
+                tokio::select! {
+                    _ = token.cancelled() => break,
+                    _ = interval.tick() => {},
+                }
+
+                let current = match fingerprint() {
+                    Ok(fp) => fp,
+                    Err(why) => {
+                        tracing::error!("failed to read configuration files: {:?}", why);
+                        continue;
+                    }
+                };
+
+                if current == last {
+                    continue;
+                }
+                last = current;
+
+                tracing::info!("Configuration files changed, reloading");
+                let mut status = app_state.reload_status.lock().unwrap().clone();
+                status.last_attempt = Some(Utc::now().to_rfc3339());
+
+                match apply(&app_state, watchers.lock().await.as_mut()).await {
+                    Ok((started, stopped)) => {
+                        tracing::info!("Configuration reloaded: {} resources started, {} stopped",
+                            started,
+                            stopped);
+                        status.generation += 1;
+                        status.last_success = status.last_attempt.clone();
+                        status.success = true;
+                        status.message = "configuration reloaded".to_owned();
+                        status.resources_started = started;
+                        status.resources_stopped = stopped;
+                    },
+                    Err(why) => {
+                        tracing::error!("Configuration reload failed, keeping the previous one: {:?}", why);
+                        status.success = false;
+                        status.message = format!("{:#}", why);
+                        status.resources_started = 0;
+                        status.resources_stopped = 0;
+                    },
+                }
+
+                *app_state.reload_status.lock().unwrap() = status;
+            }
+        }
+    });
+}
+
+// Validate the new configuration, then reconcile the watches and swap it in
+async fn apply(app_state: &ApplicationState, watchers: Option<&mut Watchers>) -> Result<(usize, usize)> {
+    let config = get_configuration()?;
+
+    let counts = match watchers {
+        Some(watchers) => watchers.reconcile(&config.kube.resources).await?,
+        None => {
+            tracing::warn!("k8s resources are not watched, kube.resources changes are ignored");
+            (0, 0)
+        }
+    };
+
+    app_state.swap(ConfigSnapshot::new(config));
+    Ok(counts)
+}
+
+// Hash of the configuration files contents, a mounted ConfigMap is updated through symlinks
+fn fingerprint() -> Result<u64> {
+    let (_, base_file_path, env_file_path) = configuration_files()?;
+    let mut hasher = DefaultHasher::new();
+    std::fs::read(base_file_path)?.hash(&mut hasher);
+    std::fs::read(env_file_path)?.hash(&mut hasher);
+    Ok(hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ax_kube::WatchQueue;
+    use crate::ax_types::{CacheIndex, Db, EntityDb, OrgDb};
+    use crate::configuration::QueuePolicy;
+    use crate::supervisor::Supervisor;
+
+    #[tokio::test]
+    async fn apply_swaps_the_configuration() {
+        let state = ApplicationState::new(get_configuration().unwrap(),
+            Db::default(),
+            CacheIndex::default(),
+            WatchQueue::new(1, QueuePolicy::Block),
+            EntityDb::default(),
+            OrgDb::default(),
+            Supervisor::new());
+        let before = state.snapshot();
+
+        // resources are not reconciled without watchers
+        assert_eq!(apply(&state, None).await.unwrap(), (0, 0));
+        assert!(!Arc::ptr_eq(&before, &state.snapshot()));
+        assert_eq!(fingerprint().unwrap(), fingerprint().unwrap());
+    }
+}
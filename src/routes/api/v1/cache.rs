@@ -0,0 +1,127 @@
+use actix_web::{web, HttpResponse};
+use kube::api::{DynamicObject, ResourceExt};
+
+use crate::backstage::format_creation_since;
+use crate::errors;
+use crate::startup::ApplicationState;
+
+/// Query parameters of the cache endpoint
+#[derive(serde::Deserialize, Debug, Default)]
+pub struct CacheQuery {
+    /// k8s kind like deployment or group/kind like apps/deployment, case insensitive
+    pub kind: Option<String>,
+    pub namespace: Option<String>,
+    /// Part of the object name
+    pub name: Option<String>,
+    /// Include the cached DynamicObject
+    #[serde(default)]
+    pub object: bool,
+}
+
+/// Cached k8s object
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CachedObject {
+    key: String,
+    group: String,
+    kind: String,
+    namespace: String,
+    name: String,
+    resource_version: String,
+    age: String,
+    event_type: String,
+    resource_url: String,
+    /// ids of the watches reporting the object
+    watches: Vec<String>,
+    cached_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    object: Option<DynamicObject>,
+}
+
+// return the objects of the watch cache, admin only per server.auth.admin_paths
+pub async fn list_cache(query: web::Query<CacheQuery>,
+    app_state: web::Data<ApplicationState>) -> errors::Result<HttpResponse> {
+    // copy the matching objects, the ingest thread waits while the cache is locked
+    let matching: Vec<(String, DynamicObject)> = {
+        let db = app_state.cache.lock().unwrap();
+        db.iter()
+            .filter(|(_, obj)| query.matches(obj))
+            .map(|(key, obj)| (key.clone(), obj.clone()))
+            .collect()
+    };
+    let entries: Vec<_> = {
+        let index = app_state.cache_index.lock().unwrap();
+        matching.iter().map(|(key, _)| index.get(key).cloned()).collect()
+    };
+
+    let res: Vec<CachedObject> = matching.into_iter()
+        .zip(entries)
+        .map(|((key, obj), entry)| {
+            let (group, kind) = group_kind(&obj);
+            CachedObject {
+                key,
+                group,
+                kind,
+                namespace: obj.namespace().unwrap_or_default(),
+                name: obj.name_any(),
+                resource_version: obj.resource_version().unwrap_or_default(),
+                age: obj.creation_timestamp()
+                    .map(|ts| format_creation_since(Some(ts)))
+                    .unwrap_or_default(),
+                event_type: entry.as_ref().map(|e| e.event_type.clone()).unwrap_or_default(),
+                resource_url: entry.as_ref().map(|e| e.resource_url.clone()).unwrap_or_default(),
+                watches: entry.as_ref().map(|e| e.watches.iter().cloned().collect()).unwrap_or_default(),
+                cached_at: entry.as_ref().map(|e| e.cached_at.to_rfc3339()).unwrap_or_default(),
+                object: query.object.then_some(obj),
+            }
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(res))
+}
+
+impl CacheQuery {
+    fn matches(&self, obj: &DynamicObject) -> bool {
+        let (group, kind) = group_kind(obj);
+        let kind_matches = |k: &str| match k.split_once('/') {
+            Some((g, k)) => g.eq_ignore_ascii_case(&group) && k.eq_ignore_ascii_case(&kind),
+            None => k.eq_ignore_ascii_case(&kind),
+        };
+
+        self.kind.as_deref().is_none_or(kind_matches)
+            && self.namespace.as_ref().is_none_or(|ns| obj.namespace().unwrap_or_default() == *ns)
+            && self.name.as_ref().is_none_or(|n| obj.name_any().contains(n.as_str()))
+    }
+}
+
+// API group, core for the core group, and kind of a cached object
+fn group_kind(obj: &DynamicObject) -> (String, String) {
+    match obj.types {
+        Some(ref tm) => (
+            tm.api_version.rsplit_once('/').map(|(group, _)| group).unwrap_or("core").to_owned(),
+            tm.kind.clone()),
+        None => (String::new(), String::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kind_matches_with_or_without_group() {
+        let obj: DynamicObject = serde_json::from_value(serde_json::json!({
+            "apiVersion": "apps/v1",
+            "kind": "Deployment",
+            "metadata": { "name": "orders", "namespace": "shop" },
+        })).unwrap();
+        let query = |kind: &str| CacheQuery { kind: Some(kind.to_owned()), ..Default::default() };
+
+        assert_eq!(group_kind(&obj), ("apps".to_owned(), "Deployment".to_owned()));
+        assert!(query("deployment").matches(&obj));
+        assert!(query("apps/Deployment").matches(&obj));
+        assert!(!query("core/Deployment").matches(&obj));
+        assert!(!CacheQuery { namespace: Some("default".into()), ..Default::default() }.matches(&obj));
+        assert!(CacheQuery { name: Some("ord".into()), ..Default::default() }.matches(&obj));
+    }
+}
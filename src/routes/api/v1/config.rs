@@ -0,0 +1,9 @@
+use actix_web::{web, Result, Responder};
+
+use crate::startup::ApplicationState;
+
+// Outcome of the last configuration reload
+pub async fn reload_status(app_state: web::Data<ApplicationState>) -> Result<impl Responder> {
+    let status = app_state.reload_status.lock().unwrap().clone();
+    Ok(web::Json(status))
+}
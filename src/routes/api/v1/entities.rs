@@ -1,211 +1,300 @@
-use std::collections::HashMap;
-
-use actix_web::{web, Result, Responder};
-use kube::ResourceExt;
+use actix_web::{web, HttpRequest, HttpResponse};
+use actix_web::http::header::{self, Header};
 use serde_json::Value;
-use crate::backstage::entities;
-use crate::configuration::Settings;
-use crate::ax_types::Db;
-
-#[derive(Debug)]
-pub enum K8sKinds {
-    StatefulSet,
-    Deployment,
-    Pod,
-    Unknown,
+use crate::backstage::{catalog::{self, EntitySources, SourceRef}, entities::BackstageEntity};
+use crate::backstage::query::{self, EntityQuery};
+use crate::errors::{self, AppError, ServerError};
+use crate::backstage::org::OrgEntities;
+use crate::startup::ApplicationState;
+
+// Media types of the YAML responses
+const YAML_MEDIA_TYPES: [&str; 4] = ["application/yaml", "application/x-yaml", "text/yaml", "text/x-yaml"];
+
+// Header with the cursor of the next page of entities
+pub const NEXT_CURSOR_HEADER: &str = "x-next-cursor";
+
+/// Query parameters of the entities endpoint
+#[derive(serde::Deserialize, Debug, Default)]
+pub struct EntitiesQuery {
+    /// Response format, json (default) or yaml
+    pub format: Option<String>,
+    #[serde(flatten)]
+    pub query: EntityQuery,
 }
 
-impl K8sKinds {
-    fn get_kind(name: &String) -> Self {
-        match name.to_lowercase().as_str() {
-            "statefulset" => K8sKinds::StatefulSet,
-            "deployment" => K8sKinds::Deployment,
-            "pod" => K8sKinds::Pod,
-            _ => K8sKinds::Unknown,
-        }
+pub async fn get_entities(req: HttpRequest,
+    query: web::Query<EntitiesQuery>,
+    app_state: web::Data<ApplicationState>) -> errors::Result<HttpResponse> {
+    let (entities, _) = catalog_entities(&app_state).await;
+    let page = query.query.apply(&entities).map_err(AppError::Server)?;
+
+    let mut resp = respond(&req, query.format.as_deref(), &page.entities)?;
+    if let Some(cursor) = page.next_cursor {
+        let value = header::HeaderValue::from_str(&cursor)
+            .map_err(|why| AppError::Server(ServerError::serialization(why.to_string())))?;
+        resp.headers_mut().insert(header::HeaderName::from_static(NEXT_CURSOR_HEADER), value);
     }
+    Ok(resp)
 }
 
-pub async fn get_entities(web_config: web::Data<Settings>, 
-    groups: web::Data<Vec<entities::Group>>,
-    users: web::Data<Vec<entities::User>>,
-    domains: web::Data<Vec<entities::Domain>>,
-    cache: web::Data<Db>) -> Result<impl Responder> {
-    // HttpResponse {
-        // Result<impl Responder>
-    let db = cache.lock().unwrap();
-    
-    // let mut res: Vec<entities:::Resource> = Vec::new();
-    let mut res: Vec<Box<dyn entities::BackstageEntity>> = Vec::new();
-    let mut seen: HashMap<String, entities::Resource> = HashMap::new();
-    let mut seen_system: HashMap<String, u8> = HashMap::new();
-    for (_, obj) in db.iter() {
-        let obj_kind: K8sKinds = match &obj.types {
-            Some(t) => {
-                K8sKinds::get_kind(&t.kind)
-            },
-            None => {
-                tracing::debug!("unknown k8s resource {:?}", obj.name_any());
-                continue;
-            }
-        };
+/// Entity and the k8s objects it was derived from
+#[derive(serde::Serialize)]
+struct EntitySource {
+    entity: Value,
+    sources: Vec<SourceRef>,
+}
 
-        match obj_kind {
-            K8sKinds::StatefulSet => {
-                // Create Resource for Redis Shard
-                let redis_shard = match entities::Resource::redis_shard_from_statefulset(&web_config, obj){
-                    Ok(res) => res,
-                    Err(why) => {
-                        tracing::error!("Resource Entity conversion failed {:?}", why);
-                        continue;
-                    }
-                };
-                res.push(Box::new(redis_shard.clone()));
-
-                // Create Redis cluster Resource
-                match entities::Resource::redis_cluster_from_shard(&web_config, redis_shard) {
-                    Ok(cluster) => {
-                        let sname = format!("redis_cluster/{}", cluster.metadata.name.clone());
-                        match seen.get_mut(&sname) {
-                            Some(seen_cluster) => {
-                                // append new dependencies to seen cluster's dependencies
-                                let mut dep_new = cluster.spec.depends_on.clone().unwrap();
-                                let mut dep_seen = seen_cluster.spec.depends_on.clone().unwrap();
-                                dep_seen.append(&mut dep_new);
-                                seen_cluster.spec.depends_on = Some(dep_seen);
-                            },
-                            None => {
-                                seen.insert(sname, cluster);
-                            },
-                        }
-                    },
-                    Err(why) => {
-                        tracing::error!("System Entity conversion failed {:?}", why);
-                    }
-                }
-
-                // create System for the Redis cluster
-                match entities::System::from_stateful_set(&web_config, obj) {
-                    Ok(system) => {
-                        let sname = format!("system/{}", system.metadata.name.clone());
-                        if seen_system.contains_key(&sname) {
-                            continue;
-                        }else{
-                            seen_system.insert(sname, 1);
-                        }
-                        res.push(Box::new(system));
-                    },
-                    Err(why) => {
-                        tracing::error!("System Entity conversion failed {:?}", why);
-                    },
-                }
-                
-            },
-            K8sKinds::Pod => {
-                let redis_node = match entities::Resource::redis_node_from_pod(&web_config, obj){
-                    Ok(node) => node,
-                    Err(why) => {
-                        tracing::error!("Resource Entity conversion failed {:?}", why);
-                        continue;
-                    }
-                };
-                res.push(Box::new(redis_node.clone()));
-            },
-            K8sKinds::Deployment => {
-                tracing::debug!("k8s kind coming soon: {:?}", obj_kind);
-            },
-            _ => {
-                tracing::debug!("k8s kind not supported: {:?}", obj_kind);
-            }
+// return the entity of a kind, namespace and name, compared without case like
+// the entity references of the filters
+pub async fn get_entity_by_name(path: web::Path<(String, String, String)>,
+    app_state: web::Data<ApplicationState>) -> errors::Result<HttpResponse> {
+    let (kind, namespace, name) = path.into_inner();
+    let wanted = format!("{}:{}/{}", kind.to_lowercase(), namespace, name);
+    let (entities, sources) = catalog_entities(&app_state).await;
+
+    for entity in entities.iter() {
+        let value = serde_json::to_value(entity)
+            .map_err(|why| AppError::Server(ServerError::serialization(why.to_string())))?;
+        let entity_ref = query::entity_ref(&value);
+        if entity_ref.eq_ignore_ascii_case(&wanted) {
+            return Ok(HttpResponse::Ok().json(EntitySource {
+                entity: value,
+                sources: sources.get(&entity_ref).cloned().unwrap_or_default(),
+            }));
         }
     }
 
-    for (_key, redis_cluster) in seen {
-        res.push(Box::new(redis_cluster.clone()));
-    }
+    Err(AppError::Server(ServerError::routing(format!("entity {} not found", wanted))))
+}
+
+// return the entity converted from the k8s object with the uid
+pub async fn get_entity_by_uid(path: web::Path<String>,
+    app_state: web::Data<ApplicationState>) -> errors::Result<HttpResponse> {
+    let uid = path.into_inner();
+    let (entities, sources) = catalog_entities(&app_state).await;
 
-    if !groups.is_empty() {
-        for g in groups.iter() {
-            res.push(Box::new(g.clone()));
+    for entity in entities.iter() {
+        let value = serde_json::to_value(entity)
+            .map_err(|why| AppError::Server(ServerError::serialization(why.to_string())))?;
+        let refs = match sources.get(&query::entity_ref(&value)) {
+            Some(refs) => refs,
+            None => continue,
+        };
+        // the first entity derived from an object is the one converted from it,
+        // e.g. the Redis shard of a StatefulSet rather than its cluster
+        if refs.iter().any(|src| src.uid.as_deref() == Some(uid.as_str())) {
+            return Ok(HttpResponse::Ok().json(EntitySource {
+                entity: value,
+                sources: refs.clone(),
+            }));
         }
     }
 
-    if !users.is_empty() {
-        for u in users.iter() {
-            res.push(Box::new(u.clone()));
+    Err(AppError::Server(ServerError::routing(format!("no entity of k8s object {}", uid))))
+}
+
+// All entities of the cluster, the configuration and the CatalogEntity resources,
+// along with the k8s objects they were derived from
+async fn catalog_entities(app_state: &ApplicationState) -> (Vec<Box<dyn BackstageEntity>>, EntitySources) {
+    let snapshot = app_state.snapshot();
+    let web_config = &snapshot.config;
+    let sources = catalog::definition_sources(&app_state.cache.lock().unwrap());
+    let api_defs = catalog::api_definitions(web_config, sources, &app_state.api_definitions).await;
+    let db = app_state.cache.lock().unwrap();
+
+    let (mut res, mut sources) = catalog::cluster_entities_with_sources(web_config, &db, &api_defs);
+
+    // org entities from ConfigMaps override those of the app config
+    let mut org = OrgEntities::default();
+    for (key, o) in app_state.org.lock().unwrap().iter() {
+        let src = SourceRef::from_store_key(key);
+        let refs = o.groups.iter().map(|g| g.metadata.entity_ref(&g.kind))
+            .chain(o.users.iter().map(|u| u.metadata.entity_ref(&u.kind)))
+            .chain(o.domains.iter().map(|d| d.metadata.entity_ref(&d.kind)));
+        for entity_ref in refs {
+            sources.entry(entity_ref).or_default().push(src.clone());
         }
+        org.extend(o.clone());
     }
 
-    if !domains.is_empty() {
-        for d in domains.iter() {
-            res.push(Box::new(d.clone()));
-        }
+    res.extend(catalog::org_entities(&snapshot.groups, 
+        &snapshot.users, 
+        snapshot.domains.as_deref().unwrap_or_default(), 
+        org));
+
+    for (key, e) in app_state.entities.lock().unwrap().iter() {
+        sources.entry(e.entity_ref()).or_default().push(SourceRef::from_store_key(key));
+        res.push(Box::new(e.clone()));
     }
 
-    Ok(web::Json(res))
+    (res, sources)
 }
-#[derive(serde::Serialize)]
-struct RedisStatus {
-    name: String,
-    namespace: String,
-    cluster: String,
-    available_replicas: Value,
-    collision_count: Value,
-    current_replicas: Value,
-    current_revision: Value,
-    observed_generation: Value,
-    ready_replicas: Value,
-    replicas: Value,
-    update_revision: Value,
-    updated_replicas: Value,
+
+// Entities as multi-document catalog-info YAML when asked for by ?format=yaml
+// or the Accept header, so that the endpoint can be a Backstage url Location target.
+fn respond<T: serde::Serialize>(req: &HttpRequest, 
+    format: Option<&str>, 
+    entities: &[T]) -> errors::Result<HttpResponse> {
+    let yaml = match format {
+        Some(f) if f.eq_ignore_ascii_case("yaml") => true,
+        Some(f) if f.eq_ignore_ascii_case("json") => false,
+        Some(f) => {
+            return Err(AppError::Server(
+                ServerError::validation(format!("unsupported format {}, use json or yaml", f))));
+        },
+        None => accepts_yaml(req),
+    };
+
+    // caches must not answer a YAML request with a JSON response
+    let mut resp = HttpResponse::Ok();
+    resp.insert_header((header::VARY, "Accept"));
+    if !yaml {
+        return Ok(resp.json(entities));
+    }
+
+    let body = catalog::to_yaml_documents(entities)
+        .map_err(|why| AppError::Server(ServerError::serialization(why.to_string())))?;
+    Ok(resp.content_type("application/yaml").body(body))
+}
+
+// Whether the Accept header prefers YAML over JSON, by q-value and then by
+// the order they are listed in
+fn accepts_yaml(req: &HttpRequest) -> bool {
+    let accept = match header::Accept::parse(req) {
+        Ok(accept) => accept,
+        Err(_) => return false,
+    };
+
+    // ranked keeps the media types refused with q=0
+    let refused: Vec<String> = accept.iter()
+        .filter(|media| media.quality == header::Quality::ZERO)
+        .map(|media| media.item.essence_str().to_lowercase())
+        .collect();
+
+    accept.ranked().iter()
+        .map(|media| media.essence_str().to_lowercase())
+        .filter(|media| !refused.contains(media))
+        .find(|media| media == "application/json" || YAML_MEDIA_TYPES.contains(&media.as_str()))
+        .is_some_and(|media| media != "application/json")
 }
 
-// return status of Redis StatefulSets clusters
-pub async fn redis_status(cache: web::Data<Db>) ->Result<impl Responder> {
-    let db = cache.lock().unwrap();    
-    let mut res: Vec<RedisStatus> = Vec::new();
-    for (_, obj) in db.iter() {
-
-        match &obj.types {
-            Some(tp) => {
-                if tp.kind.to_lowercase() != "statefulset" {
-                    continue;
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test::{self, TestRequest}, App};
+    use kube::api::DynamicObject;
+
+    use crate::ax_kube::WatchQueue;
+    use crate::ax_types::{CacheIndex, Db, EntityDb, OrgDb};
+    use crate::backstage::{entities::RawEntity, ingest::cache_key};
+    use crate::configuration::{get_configuration, QueuePolicy};
+    use crate::supervisor::Supervisor;
+
+    const ORDERS_UID: &str = "6f1c0d4e-0000-4000-8000-000000000001";
+
+    // State with the orders Deployment cached and a System declared by a CatalogEntity
+    fn app_state() -> web::Data<ApplicationState> {
+        let config = get_configuration().unwrap();
+        let deployment: DynamicObject = serde_json::from_value(serde_json::json!({
+            "apiVersion": "apps/v1",
+            "kind": "Deployment",
+            "metadata": {
+                "name": "orders",
+                "namespace": "shop",
+                "uid": ORDERS_UID,
+                "labels": { "app.kubernetes.io/name": "orders" },
             },
-            None => continue,
-        }
+            "spec": { "replicas": 1 },
+        })).unwrap();
+        let system = RawEntity::from_value(&config.backstage, serde_json::json!({
+            "apiVersion": "backstage.io/v1alpha1",
+            "kind": "System",
+            "metadata": { "name": "shop" },
+            "spec": { "owner": "platform" },
+        })).unwrap();
 
-        let labels = obj.labels();
-        if let Some(lval) = labels.get("app.kubernetes.io/component") {
-            if lval == "redis-cluster" {
-                let ns = match &obj.metadata.namespace {
-                    Some(ns) => ns.clone(),
-                    None => "".to_string(),
-                };
-
-                let status = match obj.data.get("status") {
-                    Some(Value::Object(st)) => st,
-                    Some(_) => continue,
-                    None => continue,
-                };
-
-                res.push(RedisStatus{
-                    name: obj.name_any(),
-                    namespace: ns,
-                    cluster: "ci-cd".to_string(),
-                    available_replicas: status.get("availableReplicas").unwrap().to_owned(),
-                    collision_count: status.get("collisionCount").unwrap().to_owned(),
-                    current_replicas: status.get("currentReplicas").unwrap().to_owned(),
-                    current_revision: status.get("currentRevision").unwrap().to_owned(),
-                    observed_generation: status.get("observedGeneration").unwrap().to_owned(),
-                    ready_replicas: status.get("readyReplicas").unwrap().to_owned(),
-                    replicas: status.get("replicas").unwrap().to_owned(),
-                    update_revision: status.get("updateRevision").unwrap().to_owned(),
-                    updated_replicas: status.get("updatedReplicas").unwrap().to_owned(),
-                });
-            }
-        }
+        let state = ApplicationState::new(config,
+            Db::default(),
+            CacheIndex::default(),
+            WatchQueue::new(1, QueuePolicy::Block),
+            EntityDb::default(),
+            OrgDb::default(),
+            Supervisor::new());
+        state.cache.lock().unwrap().insert(cache_key(&deployment), deployment);
+        state.entities.lock().unwrap().insert("catalogentity/shop/shop-system".to_owned(), system);
+        web::Data::new(state)
     }
 
-    Ok(web::Json(res))
-}
\ No newline at end of file
+    async fn get(uri: &str) -> (StatusCode, Value) {
+        let app = test::init_service(App::new()
+            .app_data(app_state())
+            .route("/entities/by-name/{kind}/{namespace}/{name}", web::get().to(get_entity_by_name))
+            .route("/entities/by-uid/{uid}", web::get().to(get_entity_by_uid))).await;
+        let resp = test::call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+        let status = resp.status();
+        let body = test::read_body(resp).await;
+        (status, serde_json::from_slice(&body).unwrap_or(Value::Null))
+    }
+
+    #[actix_web::test]
+    async fn entity_by_name_ignores_case() {
+        let (status, body) = get("/entities/by-name/Component/Default/Orders").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["entity"]["metadata"]["name"], "orders");
+        assert_eq!(body["sources"][0]["kind"], "Deployment");
+        assert_eq!(body["sources"][0]["uid"], ORDERS_UID);
+
+        let (status, body) = get("/entities/by-name/system/default/shop").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["sources"], serde_json::json!([{ "kind": "catalogentity", "namespace": "shop", "name": "shop-system" }]));
+
+        let (status, _) = get("/entities/by-name/component/default/billing").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn entity_by_uid_of_its_source() {
+        let (status, body) = get(&format!("/entities/by-uid/{}", ORDERS_UID)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["entity"]["kind"], "Component");
+        assert_eq!(body["entity"]["metadata"]["name"], "orders");
+        assert_eq!(body["sources"][0]["name"], "orders");
+
+        let (status, _) = get("/entities/by-uid/6f1c0d4e-0000-4000-8000-00000000ffff").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    fn accepts(accept: &str) -> bool {
+        accepts_yaml(&TestRequest::default().insert_header((header::ACCEPT, accept)).to_http_request())
+    }
+
+    #[test]
+    fn preferred_media_type_wins() {
+        assert!(accepts("application/yaml"));
+        assert!(accepts("text/html, Text/YAML;q=0.9, application/json;q=0.8"));
+        assert!(accepts("application/json;q=0.1, application/yaml"));
+        assert!(!accepts("application/yaml;q=0.5, application/json"));
+        assert!(!accepts("application/yaml;q=0, text/yaml;q=0"));
+        assert!(!accepts("text/html, */*"));
+        assert!(!accepts_yaml(&TestRequest::default().to_http_request()));
+    }
+
+    #[test]
+    fn listed_order_breaks_ties() {
+        assert!(!accepts("application/json, application/yaml"));
+        assert!(accepts("application/x-yaml;q=0.8, application/json;q=0.8"));
+    }
+
+    #[test]
+    fn format_overrides_accept() {
+        let req = TestRequest::default().insert_header((header::ACCEPT, "application/yaml")).to_http_request();
+        let entities = [serde_json::json!({ "kind": "System", "metadata": { "name": "shop" } })];
+
+        let resp = respond(&req, Some("json"), &entities).unwrap();
+        assert_eq!(resp.headers().get(header::CONTENT_TYPE).unwrap(), "application/json");
+        let resp = respond(&req, None, &entities).unwrap();
+        assert_eq!(resp.headers().get(header::CONTENT_TYPE).unwrap(), "application/yaml");
+        assert_eq!(resp.headers().get(header::VARY).unwrap(), "Accept");
+        assert!(respond(&req, Some("xml"), &entities).is_err());
+    }
+}
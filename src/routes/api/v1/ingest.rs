@@ -0,0 +1,8 @@
+use actix_web::{web, Result, Responder};
+
+use crate::startup::ApplicationState;
+
+// Depth and counters of the watch event queue, shows whether ingestion keeps up
+pub async fn queue_stats(app_state: web::Data<ApplicationState>) -> Result<impl Responder> {
+    Ok(web::Json(app_state.watch_queue.stats()))
+}
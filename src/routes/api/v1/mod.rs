@@ -1 +1,5 @@
-pub mod entities;
\ No newline at end of file
+pub mod entities;
+pub mod config;
+pub mod cache;
+pub mod status;
+pub mod ingest;
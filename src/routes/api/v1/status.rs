@@ -0,0 +1,220 @@
+use actix_web::{web, HttpResponse};
+use kube::api::{DynamicObject, ResourceExt};
+use serde_json::Value;
+
+use crate::errors::{self, AppError, ServerError};
+use crate::startup::ApplicationState;
+
+/// Status summary of a cached k8s object
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ObjectStatus {
+    cluster: String,
+    kind: String,
+    namespace: String,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generation: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    observed_generation: Option<i64>,
+    /// Pod phase, or the phase reported by custom resources
+    #[serde(skip_serializing_if = "Option::is_none")]
+    phase: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ready: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    replicas: Option<Replicas>,
+    conditions: Vec<Condition>,
+}
+
+/// Replica counts of Deployments, StatefulSets, ReplicaSets and DaemonSets
+#[derive(serde::Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct Replicas {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    desired: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    current: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ready: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    available: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    updated: Option<i64>,
+}
+
+#[derive(serde::Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Condition {
+    r#type: String,
+    status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_transition_time: Option<String>,
+}
+
+// return the status of the cached objects of a watched kind
+pub async fn get_status(path: web::Path<String>,
+    app_state: web::Data<ApplicationState>) -> errors::Result<HttpResponse> {
+    let wanted = path.into_inner().to_lowercase();
+    let snapshot = app_state.snapshot();
+    let config = &snapshot.config;
+    let db = app_state.cache.lock().unwrap();
+    let index = app_state.cache_index.lock().unwrap();
+
+    let mut watched = config.kube.resources.iter()
+        .any(|r| r.name.eq_ignore_ascii_case(&wanted));
+    let mut res: Vec<ObjectStatus> = Vec::new();
+    for (key, obj) in db.iter() {
+        let ar = match index.get(key).and_then(|e| e.api_resource.as_ref()) {
+            Some(ar) => ar,
+            None => continue,
+        };
+        if !ar.kind.eq_ignore_ascii_case(&wanted) && ar.plural != wanted {
+            continue;
+        }
+        let kind = ar.kind.clone();
+
+        watched = true;
+        res.push(summarize(&config.cluster, kind, obj));
+    }
+
+    if !watched {
+        return Err(AppError::Server(ServerError::routing(format!("kind {} is not watched", wanted))));
+    }
+
+    Ok(HttpResponse::Ok().json(res))
+}
+
+fn summarize(cluster: &str, kind: String, obj: &DynamicObject) -> ObjectStatus {
+    let status = obj.data.get("status").unwrap_or(&Value::Null);
+    let spec = obj.data.get("spec").unwrap_or(&Value::Null);
+    let conditions = conditions(status);
+    let replicas = replicas(spec, status);
+
+    let ready = conditions.iter()
+        .find(|c| c.r#type == "Ready" || c.r#type == "Available")
+        .map(|c| c.status == "True")
+        .or_else(|| replicas.as_ref().and_then(|r| match (r.desired, r.ready) {
+            (Some(desired), ready) => Some(ready.unwrap_or(0) >= desired),
+            _ => None,
+        }));
+
+    ObjectStatus {
+        cluster: cluster.to_owned(),
+        kind,
+        namespace: obj.namespace().unwrap_or_default(),
+        name: obj.name_any(),
+        generation: obj.metadata.generation,
+        observed_generation: status["observedGeneration"].as_i64(),
+        phase: status["phase"].as_str().map(str::to_owned),
+        ready,
+        replicas,
+        conditions,
+    }
+}
+
+fn conditions(status: &Value) -> Vec<Condition> {
+    let conditions = match status["conditions"].as_array() {
+        Some(conditions) => conditions,
+        None => return Vec::new(),
+    };
+
+    conditions.iter()
+        .filter_map(|c| Some(Condition {
+            r#type: c["type"].as_str()?.to_owned(),
+            status: c["status"].as_str().unwrap_or("Unknown").to_owned(),
+            reason: c["reason"].as_str().map(str::to_owned),
+            message: c["message"].as_str().map(str::to_owned),
+            last_transition_time: c["lastTransitionTime"].as_str().map(str::to_owned),
+        }))
+        .collect()
+}
+
+// Replica counts, DaemonSets report them as numbers of scheduled Pods
+fn replicas(spec: &Value, status: &Value) -> Option<Replicas> {
+    let replicas = if status.get("desiredNumberScheduled").is_some() {
+        Replicas {
+            desired: status["desiredNumberScheduled"].as_i64(),
+            current: status["currentNumberScheduled"].as_i64(),
+            ready: status["numberReady"].as_i64(),
+            available: status["numberAvailable"].as_i64(),
+            updated: status["updatedNumberScheduled"].as_i64(),
+        }
+    } else if spec.get("replicas").is_some() || status.get("replicas").is_some() {
+        Replicas {
+            desired: spec["replicas"].as_i64(),
+            current: status["replicas"].as_i64(),
+            ready: status["readyReplicas"].as_i64(),
+            available: status["availableReplicas"].as_i64(),
+            updated: status["updatedReplicas"].as_i64(),
+        }
+    } else {
+        return None;
+    };
+
+    Some(replicas)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn object(kind: &str, spec: Value, status: Value) -> DynamicObject {
+        serde_json::from_value(json!({
+            "apiVersion": "apps/v1",
+            "kind": kind,
+            "metadata": { "name": "redis", "namespace": "shop", "generation": 3 },
+            "spec": spec,
+            "status": status,
+        })).unwrap()
+    }
+
+    #[test]
+    fn statefulset_is_ready_with_all_replicas() {
+        let sts = object("StatefulSet", json!({ "replicas": 3 }),
+            json!({ "replicas": 3, "readyReplicas": 2, "observedGeneration": 3 }));
+        let summary = summarize("mars", "StatefulSet".into(), &sts);
+        let replicas = summary.replicas.unwrap();
+        assert_eq!((replicas.desired, replicas.current, replicas.ready), (Some(3), Some(3), Some(2)));
+        assert_eq!(summary.ready, Some(false));
+        assert_eq!((summary.generation, summary.observed_generation), (Some(3), Some(3)));
+
+        let sts = object("StatefulSet", json!({ "replicas": 3 }), json!({ "replicas": 3, "readyReplicas": 3 }));
+        assert_eq!(summarize("mars", "StatefulSet".into(), &sts).ready, Some(true));
+    }
+
+    #[test]
+    fn daemonset_reports_scheduled_pods() {
+        let ds = object("DaemonSet", json!({}),
+            json!({ "desiredNumberScheduled": 4, "currentNumberScheduled": 4, "numberReady": 4 }));
+        let scheduled = replicas(&ds.data["spec"], &ds.data["status"]).unwrap();
+        assert_eq!((scheduled.desired, scheduled.current, scheduled.ready), (Some(4), Some(4), Some(4)));
+
+        assert!(replicas(&json!({}), &json!({ "phase": "Running" })).is_none());
+    }
+
+    #[test]
+    fn conditions_decide_readiness() {
+        let deployment = object("Deployment", json!({ "replicas": 2 }), json!({
+            "replicas": 2,
+            "conditions": [
+                { "type": "Progressing", "status": "True", "reason": "NewReplicaSetAvailable" },
+                { "type": "Available", "status": "False", "message": "minimum replicas unavailable" },
+                { "status": "True" },
+            ],
+        }));
+        let summary = summarize("mars", "Deployment".into(), &deployment);
+
+        let types: Vec<&str> = summary.conditions.iter().map(|c| c.r#type.as_str()).collect();
+        assert_eq!(types, ["Progressing", "Available"]);
+        assert_eq!(summary.conditions[0].reason.as_deref(), Some("NewReplicaSetAvailable"));
+        // the Available condition wins over the replica counts
+        assert_eq!(summary.ready, Some(false));
+        assert!(conditions(&Value::Null).is_empty());
+    }
+}
@@ -3,14 +3,24 @@ use crate::routes::{
     health_check, 
     bs_provider_version};
 use crate::configuration::Settings;
-use crate::ax_types::Db;
-use crate::backstage::entities;
+use crate::ax_types::{CacheIndex, Db, EntityDb, OrgDb};
+use crate::backstage::{entities, definitions::DefinitionCache};
 use crate::errors::{AppError, ServerError, Result};
+use crate::reload::ReloadStatus;
+use crate::supervisor::Supervisor;
+use crate::ax_kube::WatchQueue;
+use crate::middleware::{
+    cors::cors,
+    auth::{auth, Authenticator},
+    rate_limit::{rate_limit, RateLimiter},
+    request_id::{request_id, RequestId},
+    timeout::{draining, timeout}};
 use actix_web::{web, 
     get, 
     App, 
     HttpServer, 
     HttpResponse,
+    HttpMessage,
     middleware};
 use std::net::TcpListener;
 use actix_web::dev::{
@@ -22,48 +32,115 @@ use tracing_actix_web::{
     Level};
 use actix_web::Error as ActixError;
 use tracing::Span;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, RwLock};
 use std::time::Duration;
-use std::future::Future;
 use tokio::signal;
 
+// Time given to the background tasks to stop and drain on shutdown
+const TASKS_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
 
-/// Application state shared across all request handlers
-pub struct ApplicationState {
+/// Configuration and static Backstage entities, swapped as a whole on reload
+pub struct ConfigSnapshot {
     /// Application configuration
     pub config: Settings,
-    /// Shared data cache
-    pub cache: Db,
     /// Backstage groups
-    pub groups: Arc<Vec<entities::Group>>,
+    pub groups: Vec<entities::Group>,
     /// Backstage users
-    pub users: Arc<Vec<entities::User>>,
+    pub users: Vec<entities::User>,
     /// Backstage domains
-    pub domains: Arc<Option<Vec<entities::Domain>>>,
+    pub domains: Option<Vec<entities::Domain>>,
+    /// Authentication of the clients per server.auth
+    pub authenticator: Arc<Authenticator>,
+    /// Rate limit per server.rate_limit, its buckets start full after a reload
+    pub rate_limiter: Arc<RateLimiter>,
 }
 
-impl ApplicationState {
-    /// Create a new application state
-    pub fn new(config: Settings, cache: Db) -> Self {
-        let groups = Arc::new(entities::Group::groups_from_config(config.backstage.clone()));
-        let users = Arc::new(entities::User::users_from_config(config.backstage.clone()));
-        let domains = Arc::new(
-            Some(entities::Domain::domains_from_config(
-                config.backstage.clone())));
-        
+impl ConfigSnapshot {
+    /// Create a snapshot of the configuration and its static entities
+    pub fn new(config: Settings) -> Self {
+        let groups = entities::Group::groups_from_config(config.backstage.clone());
+        let users = entities::User::users_from_config(config.backstage.clone());
+        let domains = Some(entities::Domain::domains_from_config(
+                config.backstage.clone()));
+        let authenticator = Arc::new(Authenticator::new(&config.server.auth));
+        let rate_limiter = Arc::new(RateLimiter::new(&config.server.rate_limit));
+
         Self {
             config,
-            cache,
             groups,
             users,
             domains,
+            authenticator,
+            rate_limiter,
         }
     }
+}
+
+/// Application state shared across all request handlers
+pub struct ApplicationState {
+    /// Current configuration, replaced on configuration reload
+    snapshot: RwLock<Arc<ConfigSnapshot>>,
+    /// Shared data cache
+    pub cache: Db,
+    /// How the cached objects were watched
+    pub cache_index: CacheIndex,
+    /// WatchEvents waiting for ingestion
+    pub watch_queue: WatchQueue,
+    /// Entities declared in-cluster
+    pub entities: EntityDb,
+    /// Groups, Users and Domains declared in ConfigMaps and Secrets
+    pub org: OrgDb,
+    /// API definitions fetched from annotated Services
+    pub api_definitions: DefinitionCache,
+    /// Outcome of the last configuration reload
+    pub reload_status: Mutex<ReloadStatus>,
+    /// Background tasks stopped on shutdown
+    pub supervisor: Arc<Supervisor>,
+}
+
+impl ApplicationState {
+    /// Create a new application state
+    pub fn new(config: Settings, 
+        cache: Db, 
+        cache_index: CacheIndex, 
+        watch_queue: WatchQueue,
+        entities: EntityDb, 
+        org: OrgDb, 
+        supervisor: Arc<Supervisor>) -> Self {
+        let api_definitions = DefinitionCache::new(
+            Duration::from_secs(config.cache.poll_interval));
+        
+        Self {
+            snapshot: RwLock::new(Arc::new(ConfigSnapshot::new(config))),
+            cache,
+            cache_index,
+            watch_queue,
+            entities,
+            org,
+            api_definitions,
+            reload_status: Mutex::new(ReloadStatus::default()),
+            supervisor,
+        }
+    }
+
+    /// Current configuration and static entities
+    pub fn snapshot(&self) -> Arc<ConfigSnapshot> {
+        self.snapshot.read().unwrap().clone()
+    }
+
+    /// Atomically replace the configuration, in-flight requests keep the previous one
+    pub fn swap(&self, snapshot: ConfigSnapshot) {
+        *self.snapshot.write().unwrap() = Arc::new(snapshot);
+    }
     
     /// Clean up any resources on shutdown
+    ///
+    /// Stops the watches, lets the ingest thread drain the queued events
+    /// and waits for all background tasks to finish.
     pub async fn cleanup(&self) {
         tracing::info!("Cleaning up application resources");
-        // Add cleanup logic here
+        self.supervisor.shutdown(TASKS_SHUTDOWN_TIMEOUT).await;
+        tracing::info!("Background tasks stopped");
     }
 }
 
@@ -76,7 +153,12 @@ impl RootSpanBuilder for CustomLevelRootSpanBuilder {
             "/api/v1/entities" => Level::INFO,
             _ => Level::INFO
         };
-        tracing_actix_web::root_span!(level = level, request)
+        let span = tracing_actix_web::root_span!(level = level, request);
+        // log the propagated X-Request-Id rather than an id of our own
+        if let Some(id) = request.extensions().get::<RequestId>() {
+            span.record("request_id", id.0.as_str());
+        }
+        span
     }
 
     fn on_request_end<B: actix_web::body::MessageBody>(
@@ -89,7 +171,7 @@ impl RootSpanBuilder for CustomLevelRootSpanBuilder {
 
 #[get("/")]
 async fn index(data: web::Data<ApplicationState>) -> HttpResponse {
-    let welcome = format!("Welcome to {}!", data.config.display);
+    let welcome = format!("Welcome to {}!", data.snapshot().config.display);
     HttpResponse::Ok().body(welcome)
 }
 
@@ -97,53 +179,77 @@ async fn index(data: web::Data<ApplicationState>) -> HttpResponse {
 /// 
 /// # Arguments
 /// * `listener` - TCP listener for the server
-/// * `conf` - Application configuration
-/// * `cache` - Shared data cache
+/// * `app_state` - Application state shared with the configuration reloader
 /// 
 /// # Returns
-/// A server instance that can be awaited
+/// Once the server and the background tasks were shut down
 /// 
 /// # Errors
-/// Returns an error if the server fails to start
+/// Returns an error if the server fails to start or its shutdown timed out
 pub async fn run(
     listener: TcpListener, 
-    conf: &Settings,
-    cache: Db
-) -> Result<impl Future<Output = std::io::Result<()>>> {
-    // Create application state
-    let app_state = ApplicationState::new(conf.clone(), cache);
-    let app_state_data = web::Data::new(app_state);
+    app_state_data: web::Data<ApplicationState>,
+) -> Result<()> {
     let app_state_data_closure = app_state_data.clone();
+    let server_settings = app_state_data.snapshot().config.server.clone();
+
+    let server_settings_data = web::Data::new(server_settings.clone());
 
-    // TODO find out how actix handles request timeouts
-    // Define request timeout - default 30 seconds
-    // let request_timeout = conf.server.request_timeout;
-    // let timeout_duration = Duration::from_secs(request_timeout);
+    let tls_config = if server_settings.tls.enabled {
+        let config = crate::tls::server_config(&server_settings.tls, &app_state_data.supervisor)
+            .map_err(|e| AppError::Server(ServerError::InternalError(format!("TLS: {:?}", e))))?;
+        Some(config)
+    } else {
+        None
+    };
 
     // Create the server
     let server = HttpServer::new(move || {
+        // resources answering 504 after server.request_timeout
+        let timed = |path: &str| web::resource(path).wrap(middleware::from_fn(timeout));
         let api_v1 = web::scope("/api/v1")
             .app_data(app_state_data.clone())
-            .service(web::resource("/entities").to(api_v1::entities::get_entities))
-            .service(web::resource("/redis/status").to(api_v1::entities::redis_status));
+            .service(timed("/entities").to(api_v1::entities::get_entities))
+            .service(timed("/entities/by-name/{kind}/{namespace}/{name}")
+                .to(api_v1::entities::get_entity_by_name))
+            .service(timed("/entities/by-uid/{uid}").to(api_v1::entities::get_entity_by_uid))
+            .service(timed("/status/{kind}").to(api_v1::status::get_status))
+            .service(timed("/config/reload").to(api_v1::config::reload_status))
+            .service(timed("/cache").to(api_v1::cache::list_cache))
+            .service(timed("/ingest/queue").to(api_v1::ingest::queue_stats));
 
         App::new()
             .app_data(app_state_data.clone())
+            .app_data(server_settings_data.clone())
+            .wrap(middleware::from_fn(auth))
+            .wrap(middleware::from_fn(draining))
+            .wrap(middleware::from_fn(rate_limit))
             // Add logging middleware
             .wrap(TracingLogger::<CustomLevelRootSpanBuilder>::new())
+            // the request id is known before the root span is created
+            .wrap(middleware::from_fn(request_id))
             // Add common middleware for security and compression
             .wrap(middleware::Compress::default())
             .wrap(middleware::DefaultHeaders::new().add(("X-Content-Type-Options", "nosniff")))
+            // outermost, so that preflight requests are answered before any other check
+            .wrap(middleware::Condition::new(server_settings.cors.enabled, cors(&server_settings.cors)))
             // Add services and routes
             .service(index)
             .service(bs_provider_version)
             .service(api_v1)
             .route("/healthz", web::get().to(health_check))
     })
-    .listen(listener)
+    // client certificates of TLS connections, checked by the auth middleware
+    .on_connect(crate::tls::on_connect);
+    let server = match tls_config {
+        Some(config) => server.listen_rustls_0_23(listener, config),
+        None => server.listen(listener),
+    }
     .map_err(ServerError::BindError)?
     .workers(num_cpus::get()) // Set workers to number of CPU cores
     .shutdown_timeout(30)
+    // signals are handled by graceful_shutdown, which stops the background tasks first
+    .disable_signals()
     .run(); // Give 30 seconds for graceful shutdown
 
     let server_handle = server.handle();
@@ -155,20 +261,19 @@ pub async fn run(
     //     ))),
     // };
     
-    // Create a future that handles graceful shutdown
-    let shutdown_future = graceful_shutdown(server_handle, 
-                                    app_state_data_closure.into_inner());
+    // Handle graceful shutdown while the server is running
+    let shutdown = tokio::spawn(graceful_shutdown(server_handle, 
+                                    app_state_data_closure.into_inner()));
 
-    match server.await {
-        Ok(_) => {
-            Ok(shutdown_future)
-        },
-        Err(e) => {
-            Err(AppError::Server(ServerError::InternalError(e.to_string())))
-        }
+    if let Err(e) = server.await {
+        return Err(AppError::Server(ServerError::InternalError(e.to_string())));
     }
 
-    
+    match shutdown.await {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => Err(AppError::Server(ServerError::InternalError(e.to_string()))),
+        Err(e) => Err(AppError::Server(ServerError::InternalError(e.to_string()))),
+    }
 }
 
 /// Handles graceful shutdown of the server
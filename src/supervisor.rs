@@ -0,0 +1,169 @@
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use tokio::task::JoinHandle;
+use tokio::time::{self, Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+// Backoff before restarting a panicked task, doubled on every panic
+const RESTART_BASE_DELAY: Duration = Duration::from_secs(1);
+const RESTART_MAX_DELAY: Duration = Duration::from_secs(60);
+// A task running longer than this is considered healthy again
+const RESTART_RESET_AFTER: Duration = Duration::from_secs(300);
+
+/// Supervisor of the long running background tasks.
+///
+/// Tasks are spawned with a cancellation token, restarted with backoff when
+/// they panic, and awaited on shutdown.
+#[derive(Default)]
+pub struct Supervisor {
+    token: CancellationToken,
+    tasks: Mutex<Vec<(String, JoinHandle<()>)>>,
+}
+
+impl Supervisor {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self::default())
+    }
+
+    /// Token cancelled when the application shuts down
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    /// Spawn a supervised task.
+    ///
+    /// `task` is called again with the same token when the previous run panicked.
+    /// The task is expected to return once the token is cancelled.
+    pub fn spawn<F, Fut>(&self, name: &str, token: CancellationToken, task: F)
+    where
+        F: Fn(CancellationToken) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let task_name = name.to_owned();
+        let handle = tokio::spawn(async move {
+            let mut delay = RESTART_BASE_DELAY;
+
+            loop {
+                let started = Instant::now();
+                let run = tokio::spawn(task(token.clone()));
+
+                match run.await {
+                    Ok(()) => {
+                        tracing::debug!("task {} finished", task_name);
+                        break;
+                    },
+                    Err(why) if why.is_panic() => {
+                        if started.elapsed() > RESTART_RESET_AFTER {
+                            delay = RESTART_BASE_DELAY;
+                        }
+                        tracing::error!("task {} panicked, restarting in {:?}: {:?}",
+                            task_name,
+                            delay,
+                            why);
+                    },
+                    Err(why) => {
+                        tracing::warn!("task {} aborted: {:?}", task_name, why);
+                        break;
+                    },
+                }
+
+                tokio::select! {
+                    _ = token.cancelled() => break,
+                    _ = time::sleep(delay) => {},
+                }
+                delay = (delay * 2).min(RESTART_MAX_DELAY);
+            }
+        });
+
+        let mut tasks = self.tasks.lock().unwrap();
+        // forget tasks which already ended, e.g. stopped watches
+        tasks.retain(|(_, h)| !h.is_finished());
+        tasks.push((name.to_owned(), handle));
+    }
+
+    /// Cancel all tasks and wait for them to finish, at most `timeout`
+    pub async fn shutdown(&self, timeout: Duration) {
+        self.token.cancel();
+
+        let tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        let deadline = Instant::now() + timeout;
+
+        for (name, handle) in tasks {
+            match time::timeout_at(deadline, handle).await {
+                Ok(_) => tracing::debug!("task {} stopped", name),
+                Err(_) => tracing::warn!("task {} did not stop in {:?}", name, timeout),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Counts the runs of a task which panics on its first `panics` runs
+    fn flaky(supervisor: &Supervisor, panics: usize) -> Arc<AtomicUsize> {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = runs.clone();
+        supervisor.spawn("flaky", supervisor.token(), move |token| {
+            let run = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            async move {
+                if run <= panics {
+                    panic!("run {} failed", run);
+                }
+                token.cancelled().await;
+            }
+        });
+        runs
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn panicked_task_is_restarted_with_backoff() {
+        let supervisor = Supervisor::new();
+        let start = Instant::now();
+        let runs = flaky(&supervisor, 2);
+
+        time::sleep_until(start + Duration::from_millis(500)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        // restarted after 1s, then after 2s more
+        time::sleep_until(start + Duration::from_millis(1500)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        time::sleep_until(start + Duration::from_millis(2900)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        time::sleep_until(start + Duration::from_millis(3500)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+
+        supervisor.shutdown(Duration::from_secs(1)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cancelled_task_is_not_restarted() {
+        let supervisor = Supervisor::new();
+        let runs = flaky(&supervisor, 1);
+
+        time::sleep(Duration::from_millis(500)).await;
+        supervisor.token().cancel();
+        time::sleep(RESTART_MAX_DELAY).await;
+
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert!(supervisor.tasks.lock().unwrap().iter().all(|(_, h)| h.is_finished()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_waits_at_most_the_timeout() {
+        let supervisor = Supervisor::new();
+        let runs = flaky(&supervisor, 0);
+        supervisor.spawn("stuck", supervisor.token(), |_| async {
+            time::sleep(Duration::from_secs(3600)).await;
+        });
+        tokio::task::yield_now().await;
+
+        let start = Instant::now();
+        supervisor.shutdown(Duration::from_secs(5)).await;
+        assert_eq!(start.elapsed(), Duration::from_secs(5));
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+}
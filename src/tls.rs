@@ -0,0 +1,260 @@
+use std::any::Any;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use actix_tls::accept::rustls_0_23::TlsStream;
+use actix_web::{dev::Extensions, rt::net::TcpStream};
+use anyhow::{Context, Result};
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{RootCertStore, ServerConfig};
+use tokio::time;
+
+use crate::configuration::TlsSettings;
+use crate::supervisor::Supervisor;
+
+/// Marks connections of clients which presented a certificate of the
+/// client_ca_file, found in the connection data of their requests
+#[derive(Debug, Clone, Copy)]
+pub struct ClientCertificate;
+
+/// Server certificate of the files in the settings, swapped when they change.
+#[derive(Debug)]
+pub struct ReloadingCert {
+    cert_file: String,
+    key_file: String,
+    provider: Arc<CryptoProvider>,
+    current: RwLock<(Option<SystemTime>, Arc<CertifiedKey>)>,
+}
+
+impl ReloadingCert {
+    /// Loads the certificate, fails if the files are missing or invalid
+    pub fn new(settings: &TlsSettings, provider: Arc<CryptoProvider>) -> Result<Self> {
+        let modified = modified(&settings.cert_file, &settings.key_file);
+        let key = load_certified_key(&settings.cert_file, &settings.key_file, &provider)?;
+
+        Ok(Self {
+            cert_file: settings.cert_file.clone(),
+            key_file: settings.key_file.clone(),
+            provider,
+            current: RwLock::new((modified, Arc::new(key))),
+        })
+    }
+
+    /// Loads the files again if they changed. A broken rotation keeps the
+    /// previous certificate.
+    pub fn reload(&self) {
+        let modified = modified(&self.cert_file, &self.key_file);
+        if modified.is_none() || self.current.read().unwrap().0 == modified {
+            return;
+        }
+
+        match load_certified_key(&self.cert_file, &self.key_file, &self.provider) {
+            Ok(key) => {
+                *self.current.write().unwrap() = (modified, Arc::new(key));
+                tracing::info!("reloaded TLS certificate {}", self.cert_file);
+            },
+            Err(why) => tracing::error!("failed to reload TLS certificate {}: {:?}", self.cert_file, why),
+        }
+    }
+}
+
+impl ResolvesServerCert for ReloadingCert {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().1.clone())
+    }
+}
+
+// server_config - Builds the rustls configuration of the server and spawns the
+//         task reloading the certificate. Certificates presented by clients are
+//         verified with the client_ca_file when set, the CAs are loaded once.
+// Connections without a certificate are accepted, so that probes reach /healthz,
+// the auth middleware requires the certificate on the other routes.
+pub fn server_config(settings: &TlsSettings, supervisor: &Supervisor) -> Result<ServerConfig> {
+    let provider = Arc::new(ring::default_provider());
+    let cert = Arc::new(ReloadingCert::new(settings, provider.clone())?);
+
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let builder = match settings.client_ca_file {
+        Some(ref ca_file) => {
+            let mut roots = RootCertStore::empty();
+            for ca in load_certs(ca_file)? {
+                roots.add(ca).with_context(|| format!("invalid CA in {}", ca_file))?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .allow_unauthenticated()
+                .build()?;
+            tracing::info!("client certificates of {} required outside the public paths", ca_file);
+            builder.with_client_cert_verifier(verifier)
+        },
+        None => builder.with_no_client_auth(),
+    };
+    let config = builder.with_cert_resolver(cert.clone());
+
+    let interval = Duration::from_secs(settings.reload_interval_secs);
+    supervisor.spawn("tls certificate reloader", supervisor.token(), move |token| {
+        let cert = cert.clone();
+        async move {
+            let mut ticker = time::interval(interval);
+            loop {
+                tokio::select! {
+                    _ = token.cancelled() => break,
+                    _ = ticker.tick() => cert.reload(),
+                }
+            }
+        }
+    });
+
+    Ok(config)
+}
+
+// on_connect - Records the client certificate of TLS connections, rustls
+//         already rejected those not issued by the client_ca_file.
+pub fn on_connect(conn: &dyn Any, ext: &mut Extensions) {
+    let presented = conn.downcast_ref::<TlsStream<TcpStream>>()
+        .and_then(|tls| tls.get_ref().1.peer_certificates())
+        .is_some_and(|certs| !certs.is_empty());
+    if presented {
+        ext.insert(ClientCertificate);
+    }
+}
+
+// Latest modification of the certificate and key, none if one is missing
+fn modified(cert_file: &str, key_file: &str) -> Option<SystemTime> {
+    let cert = std::fs::metadata(cert_file).and_then(|m| m.modified()).ok()?;
+    let key = std::fs::metadata(key_file).and_then(|m| m.modified()).ok()?;
+    Some(cert.max(key))
+}
+
+fn load_certs(path: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let mut reader = BufReader::new(File::open(path).with_context(|| format!("failed to open {}", path))?);
+    let certs = rustls_pemfile::certs(&mut reader)
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .with_context(|| format!("invalid PEM in {}", path))?;
+    if certs.is_empty() {
+        anyhow::bail!("no certificate in {}", path);
+    }
+
+    Ok(certs)
+}
+
+fn load_key(path: &str) -> Result<PrivateKeyDer<'static>> {
+    let mut reader = BufReader::new(File::open(path).with_context(|| format!("failed to open {}", path))?);
+    rustls_pemfile::private_key(&mut reader)
+        .with_context(|| format!("invalid PEM in {}", path))?
+        .with_context(|| format!("no private key in {}", path))
+}
+
+fn load_certified_key(cert_file: &str, key_file: &str, provider: &CryptoProvider) -> Result<CertifiedKey> {
+    let certs = load_certs(cert_file)?;
+    let key = provider.key_provider.load_private_key(load_key(key_file)?)
+        .with_context(|| format!("unsupported private key in {}", key_file))?;
+    let certified = CertifiedKey::new(certs, key);
+    // a half written rotation may pair a new certificate with the old key
+    certified.keys_match()
+        .with_context(|| format!("{} does not match {}", key_file, cert_file))?;
+
+    Ok(certified)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+
+    // Certificate and key files in a directory of the test, replaced by rotate
+    struct Files {
+        dir: PathBuf,
+        settings: TlsSettings,
+    }
+
+    impl Files {
+        fn new(test: &str, pair: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("tls-{}-{}", test, std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let settings = TlsSettings {
+                enabled: true,
+                cert_file: dir.join("tls.crt").to_string_lossy().into_owned(),
+                key_file: dir.join("tls.key").to_string_lossy().into_owned(),
+                ..TlsSettings::default()
+            };
+            let files = Self { dir, settings };
+            files.rotate(pair, pair, 0);
+            files
+        }
+
+        // Copy the certificate and key of the fixture pairs, modified `secs` after the epoch
+        fn rotate(&self, cert: &str, key: &str, secs: u64) {
+            let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000 + secs);
+            for (fixture, path) in [(format!("{}.crt", cert), &self.settings.cert_file), (format!("{}.key", key), &self.settings.key_file)] {
+                std::fs::copy(fixture_path(&fixture), path).unwrap();
+                File::options().write(true).open(path).unwrap().set_modified(modified).unwrap();
+            }
+        }
+    }
+
+    impl Drop for Files {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn fixture_path(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls").join(name)
+    }
+
+    fn served(cert: &ReloadingCert) -> CertificateDer<'static> {
+        cert.current.read().unwrap().1.cert[0].clone()
+    }
+
+    fn fixture_cert(pair: &str) -> CertificateDer<'static> {
+        load_certs(&fixture_path(&format!("{}.crt", pair)).to_string_lossy()).unwrap().remove(0)
+    }
+
+    #[test]
+    fn loads_the_certificate() {
+        let files = Files::new("load", "a");
+        let cert = ReloadingCert::new(&files.settings, Arc::new(ring::default_provider())).unwrap();
+        assert_eq!(served(&cert), fixture_cert("a"));
+
+        files.rotate("b", "a", 0);
+        assert!(ReloadingCert::new(&files.settings, Arc::new(ring::default_provider())).is_err());
+    }
+
+    #[test]
+    fn reloads_changed_files() {
+        let files = Files::new("reload", "a");
+        let cert = ReloadingCert::new(&files.settings, Arc::new(ring::default_provider())).unwrap();
+
+        // unchanged modification times are not read again
+        std::fs::copy(fixture_path("b.crt"), &files.settings.cert_file).unwrap();
+        File::options().write(true).open(&files.settings.cert_file).unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000)).unwrap();
+        cert.reload();
+        assert_eq!(served(&cert), fixture_cert("a"));
+
+        files.rotate("b", "b", 60);
+        cert.reload();
+        assert_eq!(served(&cert), fixture_cert("b"));
+    }
+
+    #[test]
+    fn keeps_the_certificate_when_the_key_does_not_match() {
+        let files = Files::new("mismatch", "a");
+        let cert = ReloadingCert::new(&files.settings, Arc::new(ring::default_provider())).unwrap();
+
+        // a half written rotation
+        files.rotate("b", "a", 60);
+        cert.reload();
+        assert_eq!(served(&cert), fixture_cert("a"));
+
+        files.rotate("b", "b", 120);
+        cert.reload();
+        assert_eq!(served(&cert), fixture_cert("b"));
+    }
+}
@@ -0,0 +1,55 @@
+//! Golden files of `render -f`: the manifests of each directory under
+//! tests/fixtures/render render to the catalog-info.yaml next to it.
+//! Run with UPDATE_GOLDEN=1 to rewrite the expected files.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use k8s_entity_provider::ax_kube::manifests::load_manifests;
+use k8s_entity_provider::cli::render_catalog;
+use k8s_entity_provider::configuration::{get_configuration, Settings};
+
+fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+fn config() -> Settings {
+    std::env::set_var("APP_CONFIG_DIR", fixtures().join("config"));
+    std::env::set_var("APP_ENVIRONMENT", "local");
+    get_configuration().unwrap()
+}
+
+fn check_golden(name: &str) {
+    let manifests = fixtures().join("render").join(name);
+    let expected_file = fixtures().join("render").join(format!("{}.yaml", name));
+
+    let objects = load_manifests(&[manifests]).unwrap();
+    let rendered = render_catalog(&config(), &objects, &HashMap::new()).unwrap();
+
+    if std::env::var("UPDATE_GOLDEN").is_ok() {
+        std::fs::write(&expected_file, &rendered).unwrap();
+    }
+    let expected = std::fs::read_to_string(&expected_file).unwrap();
+    assert_eq!(rendered, expected, "{} differs from the rendered entities", expected_file.display());
+}
+
+#[test]
+fn render_shop() {
+    check_golden("shop");
+}
+
+#[test]
+fn manifests_are_keyed_like_the_watch_cache() {
+    let objects = load_manifests(&[fixtures().join("render/shop")]).unwrap();
+    let keys: Vec<&str> = objects.keys().map(String::as_str).collect();
+
+    assert_eq!(keys, vec![
+        "apps/Deployment/default/billing",
+        "apps/Deployment/shop/orders",
+        "core/Service/shop/orders",
+        "networking.k8s.io/Ingress/shop/orders",
+    ]);
+    // trimmed like the watched objects
+    let orders = &objects["apps/Deployment/shop/orders"];
+    assert!(orders.metadata.annotations.as_ref().is_none_or(|anns| anns.is_empty()));
+}